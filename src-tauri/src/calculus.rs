@@ -0,0 +1,479 @@
+use crate::error::Error;
+use crate::types::{BaseField, CResult, Limit};
+
+
+/// First step of numerical derivatives, relative to the size of the value
+const DERIVATIVE_STEP: f64 = 0.01;
+/// Each step is this much smaller than the one before
+const STEP_SHRINK: f64 = 1.4;
+/// Most steps taken for one derivative
+const MAX_STEPS: usize = 10;
+/// The extrapolation stops once it changes by this many times its estimated error
+const MAX_ERROR_GROWTH: f64 = 2.0;
+
+/// Integrals are refined until their estimated error is this small, relative to the integral of the absolute value
+const INTEGRAL_TOLERANCE: f64 = 1e-10;
+/// Most pieces an integral is split into before giving up
+const MAX_SEGMENTS: usize = 500;
+
+/// Points sampled between the limits of a search for roots, to find where the function changes sign
+const ROOT_SAMPLES: usize = 200;
+/// Most iterations of Brent's or Newton's method for one root
+const MAX_ROOT_ITERATIONS: usize = 200;
+/// Newton's method stops once its steps are this small, relative to the position, and only finds a root if the
+/// function is then this small, relative to where it started
+const ROOT_TOLERANCE: f64 = 1e-14;
+const ROOT_RESIDUAL: f64 = 1e-9;
+
+/// Steps of differential equations are chosen so that the estimated error of each is this small, relative to the
+/// size of the solution (or absolute, in SI units, where the solution is smaller than one)
+const ODE_TOLERANCE: f64 = 1e-10;
+/// Most steps taken to solve a differential equation
+const MAX_ODE_STEPS: usize = 100_000;
+
+/// Each stage of the Dormand-Prince method evaluates the equation at this fraction of the step, using the weighted
+/// sum of the rates of the stages before it. The last stage is at the end of the step, so it's also the first stage
+/// of the next one
+const DORMAND_PRINCE_NODES: [f64; 6] = [1.0 / 5.0, 3.0 / 10.0, 4.0 / 5.0, 8.0 / 9.0, 1.0, 1.0];
+const DORMAND_PRINCE_WEIGHTS: [&[f64]; 6] = [
+    &[1.0 / 5.0],
+    &[3.0 / 40.0, 9.0 / 40.0],
+    &[44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0],
+    &[19372.0 / 6561.0, -25360.0 / 2187.0, 64448.0 / 6561.0, -212.0 / 729.0],
+    &[9017.0 / 3168.0, -355.0 / 33.0, 46732.0 / 5247.0, 49.0 / 176.0, -5103.0 / 18656.0],
+    // Also the weights of the 5th order solution
+    &[35.0 / 384.0, 0.0, 500.0 / 1113.0, 125.0 / 192.0, -2187.0 / 6784.0, 11.0 / 84.0],
+];
+/// The difference between the 5th order solution and the embedded 4th order one, which estimates the error of a step
+const DORMAND_PRINCE_ERROR: [f64; 7] = [
+    71.0 / 57600.0, 0.0, -71.0 / 16695.0, 71.0 / 1920.0, -17253.0 / 339200.0, 22.0 / 525.0, -1.0 / 40.0,
+];
+
+/// Nodes of the 15 point Kronrod rule on [-1, 1], from the outside in. Every other one is also a node of the 7 point
+/// Gauss rule
+const KRONROD_NODES: [f64; 8] = [
+    0.9914553711208126, 0.9491079123427585, 0.8648644233597691, 0.7415311855993945,
+    0.5860872354676911, 0.4058451513773972, 0.20778495500789848, 0.0,
+];
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022935322010529224, 0.06309209262997856, 0.10479001032225019, 0.14065325971552592,
+    0.1690047266392679, 0.19035057806478542, 0.20443294007529889, 0.20948214108472782,
+];
+const GAUSS_WEIGHTS: [f64; 4] = [0.1294849661688697, 0.27970539148927664, 0.3818300505051189, 0.4179591836734694];
+
+/// A step to start numerical derivatives at `x` with: a small fraction of `x`, so that e.g. `ln(x)` is never sampled
+/// at negative values, or of one (SI) unit at zero
+pub fn derivative_step(x: f64) -> f64 {
+    if x == 0.0 { DERIVATIVE_STEP } else { x.abs() * DERIVATIVE_STEP }
+}
+
+/// The derivative of `f` at `x`, using Ridders' method: central differences with smaller and smaller steps are
+/// extrapolated to a step of zero, until rounding errors start to dominate.
+///
+/// Everything is calculated in the field, so the result has the units of `f` divided by those of `x`
+pub fn derivative<'a, T: BaseField<'a>>(x: &T, f: impl Fn(T) -> CResult<T>) -> CResult<T> {
+    let central_difference = |step: &T| -> CResult<T> {
+        // Rounded to the step that's actually taken, which cancels some rounding error
+        let step = ((x.clone() + step.clone())? - x.clone())?;
+        let rise = (f((x.clone() + step.clone())?)? - f((x.clone() - step.clone())?)?)?;
+        rise / (step.clone() * 2.0.into())?
+    };
+    let distance = |a: &T, b: &T| -> CResult<f64> { (a.clone() - b.clone())?.magnitude() };
+
+    let mut step = (x.unit()? * derivative_step(x.magnitude()?).into())?;
+    // Each row holds a central difference and its extrapolations using the rows before
+    let mut previous = vec![central_difference(&step)?];
+    let mut best = previous[0].clone();
+    let mut best_error = f64::INFINITY;
+    for _ in 1..MAX_STEPS {
+        step = (step / STEP_SHRINK.into())?;
+        let mut row = vec![central_difference(&step)?];
+        let mut factor = STEP_SHRINK * STEP_SHRINK;
+        for j in 1..=previous.len() {
+            let estimate = (((row[j - 1].clone() * factor.into())? - previous[j - 1].clone())? / (factor - 1.0).into())?;
+            factor *= STEP_SHRINK * STEP_SHRINK;
+            let error = distance(&estimate, &row[j - 1])?.max(distance(&estimate, &previous[j - 1])?);
+            if error <= best_error {
+                best = estimate.clone();
+                best_error = error;
+            }
+            row.push(estimate);
+        }
+        if distance(&row[row.len() - 1], &previous[previous.len() - 1])? >= MAX_ERROR_GROWTH * best_error {
+            break;
+        }
+        previous = row;
+    }
+    Ok(best)
+}
+
+/// The integral of `f` between the limits and an estimate of its error, using adaptive Gauss-Kronrod quadrature.
+///
+/// Infinite limits are mapped to finite ones, e.g. `x = a + t / (1 - t)` maps `[0, 1)` to `[a, ∞)`, in units of the
+/// other limit. Integrals over the whole real line are unitless
+pub fn integral<'a, T: BaseField<'a>>(lower: &Limit<T>, upper: &Limit<T>, f: impl Fn(T) -> CResult<T>) -> CResult<(T, T)> {
+    let scaled = |value: T, factor: f64| value * factor.into();
+    match (lower, upper) {
+        (Limit::Finite(lower), Limit::Finite(upper)) => {
+            let (lower, upper) = (zero_in_units_of(lower, upper)?, zero_in_units_of(upper, lower)?);
+            let width = (upper - lower.clone())?;
+            adaptive_integral(0.0, 1.0, |t| f((lower.clone() + scaled(width.clone(), t)?)?)? * width.clone())
+        },
+        (Limit::Finite(lower), Limit::Infinity) => {
+            let unit = lower.unit()?;
+            adaptive_integral(0.0, 1.0, |t| {
+                let x = (lower.clone() + scaled(unit.clone(), t / (1.0 - t))?)?;
+                scaled((f(x)? * unit.clone())?, 1.0 / ((1.0 - t) * (1.0 - t)))
+            })
+        },
+        (Limit::NegativeInfinity, Limit::Finite(upper)) => {
+            let unit = upper.unit()?;
+            adaptive_integral(0.0, 1.0, |t| {
+                let x = (upper.clone() - scaled(unit.clone(), t / (1.0 - t))?)?;
+                scaled((f(x)? * unit.clone())?, 1.0 / ((1.0 - t) * (1.0 - t)))
+            })
+        },
+        (Limit::NegativeInfinity, Limit::Infinity) => adaptive_integral(-1.0, 1.0, |t| {
+            let x = T::from(t / (1.0 - t * t));
+            scaled(f(x)?, (1.0 + t * t) / ((1.0 - t * t) * (1.0 - t * t)))
+        }),
+        (Limit::Infinity, Limit::Infinity) | (Limit::NegativeInfinity, Limit::NegativeInfinity) => Ok((0.0.into(), 0.0.into())),
+        // Integrating backwards gives the negative of the integral
+        (lower, upper) => {
+            let (value, error) = integral(upper, lower, f)?;
+            Ok((scaled(value, -1.0)?, error))
+        },
+    }
+}
+
+/// A limit of zero without units takes the units of the other limit, e.g. in `\int_0^{2 m}`
+fn zero_in_units_of<'a, T: BaseField<'a>>(limit: &T, other: &T) -> CResult<T> {
+    match limit.as_scalar() {
+        Ok(0.0) => other.clone() * 0.0.into(),
+        _ => Ok(limit.clone()),
+    }
+}
+
+/// Rounds `value` to the last digit that its estimated `error` leaves, for fields that can't show the error itself.
+/// The real and imaginary parts are rounded separately
+pub fn round_to_error<'a, T: BaseField<'a>>(value: &T, error: &T) -> CResult<T> {
+    let error_size = error.magnitude()?;
+    if error_size == 0.0 || !error_size.is_finite() {
+        return Ok(value.clone());
+    }
+    let digit = (error.unit()? * 10f64.powf(error_size.log10().ceil()).into())?;
+    let digits = (value.clone() / digit.clone())?;
+    let (re, im) = (digits.re()?.as_scalar()?, digits.im()?.as_scalar()?);
+    let mut rounded = T::from(re.round());
+    if im != 0.0 {
+        let imaginary = (digits - re.into())?;
+        rounded = (rounded + (imaginary * (im.round() / im).into())?)?;
+    }
+    rounded * digit
+}
+
+/// The integral over a piece of the range
+struct Segment<T> {
+    start: f64,
+    end: f64,
+    value: T,
+    error: T,
+    /// Sizes of the error and of the integral of `|f|` in SI units, which decide what to refine
+    error_size: f64,
+    absolute_size: f64,
+}
+
+/// Splits the piece with the largest error in half until the total error is small enough
+fn adaptive_integral<'a, T: BaseField<'a>>(start: f64, end: f64, f: impl Fn(f64) -> CResult<T>) -> CResult<(T, T)> {
+    let mut segments = vec![gauss_kronrod(&f, start, end)?];
+    loop {
+        let error_size: f64 = segments.iter().map(|segment| segment.error_size).sum();
+        let absolute_size: f64 = segments.iter().map(|segment| segment.absolute_size).sum();
+        let (worst, _) = segments.iter().enumerate()
+            .max_by(|(_, a), (_, b)| a.error_size.total_cmp(&b.error_size))
+            .unwrap();
+        let midpoint = (segments[worst].start + segments[worst].end) / 2.0;
+        let converged = error_size <= INTEGRAL_TOLERANCE * absolute_size;
+        let indivisible = midpoint <= segments[worst].start || midpoint >= segments[worst].end;
+        if converged || indivisible || segments.len() >= MAX_SEGMENTS {
+            let sum = |values: Vec<T>| values.into_iter().map(Ok).reduce(|a, b| a? + b?).unwrap();
+            let value = sum(segments.iter().map(|segment| segment.value.clone()).collect())?;
+            if !converged && value.magnitude()?.is_finite() {
+                return Err(Error::EvalError(format!("The integral doesn't converge, the closest estimate was {value}")));
+            } else if !converged {
+                return Err(Error::EvalError("The integral doesn't converge".to_string()));
+            }
+            let error = sum(segments.into_iter().map(|segment| segment.error).collect())?;
+            return Ok((value, error));
+        }
+        let segment = segments.swap_remove(worst);
+        segments.push(gauss_kronrod(&f, segment.start, midpoint)?);
+        segments.push(gauss_kronrod(&f, midpoint, segment.end)?);
+    }
+}
+
+/// Integrates over `[start, end]` with the 15 point Kronrod rule. The 7 point Gauss rule uses some of the same
+/// points, and the difference between the two is the estimated error
+fn gauss_kronrod<'a, T: BaseField<'a>>(f: impl Fn(f64) -> CResult<T>, start: f64, end: f64) -> CResult<Segment<T>> {
+    let center = (start + end) / 2.0;
+    let half_width = (end - start) / 2.0;
+    let center_value = f(center)?;
+    let mut absolute_size = KRONROD_WEIGHTS[7] * center_value.magnitude()?;
+    let mut kronrod = (center_value.clone() * KRONROD_WEIGHTS[7].into())?;
+    let mut gauss = (center_value * GAUSS_WEIGHTS[3].into())?;
+    for i in 0..7 {
+        let offset = half_width * KRONROD_NODES[i];
+        let (left, right) = (f(center - offset)?, f(center + offset)?);
+        absolute_size += KRONROD_WEIGHTS[i] * (left.magnitude()? + right.magnitude()?);
+        let pair = (left + right)?;
+        kronrod = (kronrod + (pair.clone() * KRONROD_WEIGHTS[i].into())?)?;
+        if i % 2 == 1 {
+            gauss = (gauss + (pair * GAUSS_WEIGHTS[i / 2].into())?)?;
+        }
+    }
+    let error = ((kronrod.clone() - gauss)?.abs()? * half_width.into())?;
+    Ok(Segment {
+        start,
+        end,
+        value: (kronrod * half_width.into())?,
+        error_size: error.magnitude()?,
+        error,
+        absolute_size: absolute_size * half_width,
+    })
+}
+
+/// The solution of the differential equation `y^(n) = f(x, [y, y', ..., y^(n-1)])` at each of `points`, given `y`
+/// and its first n - 1 derivatives at `start`, using the Dormand-Prince method (an adaptive 5th order Runge-Kutta
+/// method).
+///
+/// The points are solved for in order of their distance from the start, continuing from the one before, so solving
+/// for a whole series of points costs about as much as solving for the furthest one
+pub fn solve_ode<'a, T: BaseField<'a>>(start: &T, initial: &[T], points: &[T], f: impl Fn(&T, &[T]) -> CResult<T>) -> CResult<Vec<T>> {
+    let mut results = vec![None; points.len()];
+    let mut forwards = vec![];
+    let mut backwards = vec![];
+    for (i, point) in points.iter().enumerate() {
+        let distance = (point.clone() - start.clone())?.magnitude()?;
+        match point.compare(start)? {
+            Some(std::cmp::Ordering::Less) => backwards.push((distance, i)),
+            Some(_) => forwards.push((distance, i)),
+            None => return Err(Error::EvalError(format!("The differential equation can't be solved at {point}"))),
+        }
+    }
+    for mut direction in [forwards, backwards] {
+        direction.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        let (mut x, mut state) = (start.clone(), initial.to_vec());
+        // The size of the last step in SI units, which the next part starts from
+        let mut step_size = None;
+        for (_, i) in direction {
+            step_size = solve_ode_between(&x, &points[i], &mut state, step_size, &f)?;
+            x = points[i].clone();
+            results[i] = Some(state[0].clone());
+        }
+    }
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// Advances `state` from `start` to `end`, returning the size of the last step
+fn solve_ode_between<'a, T: BaseField<'a>>(
+    start: &T, end: &T, state: &mut Vec<T>, step_size: Option<f64>, f: impl Fn(&T, &[T]) -> CResult<T>,
+) -> CResult<Option<f64>> {
+    let span = (end.clone() - start.clone())?;
+    let length = span.magnitude()?;
+    if length == 0.0 {
+        return Ok(step_size);
+    }
+    // Steps are fractions of the span, so each component of the rate (the derivative with respect to the fraction)
+    // has the same units as the state
+    let rates = |fraction: f64, state: &[T]| -> CResult<Vec<T>> {
+        let x = (start.clone() + (span.clone() * fraction.into())?)?;
+        let highest = f(&x, state)?;
+        state[1..].iter().cloned().chain([highest]).map(|rate| rate * span.clone()).collect()
+    };
+    let weighted_sum = |state: &[T], stages: &[Vec<T>], weights: &[f64], step: f64| -> CResult<Vec<T>> {
+        state.iter().enumerate().map(|(component, value)| {
+            stages.iter().zip(weights).filter(|(_, weight)| **weight != 0.0)
+                .try_fold(value.clone(), |sum, (rates, weight)| sum + (rates[component].clone() * (step * weight).into())?)
+        }).collect()
+    };
+
+    let mut fraction = 0.0;
+    let mut step = step_size.map_or(0.01, |size| size / length).min(1.0);
+    let mut first_rates = rates(0.0, state)?;
+    for _ in 0..MAX_ODE_STEPS {
+        step = step.min(1.0 - fraction);
+        let mut stages = vec![first_rates.clone()];
+        for (node, weights) in DORMAND_PRINCE_NODES.iter().zip(DORMAND_PRINCE_WEIGHTS) {
+            let stage_state = weighted_sum(state, &stages, weights, step)?;
+            stages.push(rates(fraction + node * step, &stage_state)?);
+        }
+        let next = weighted_sum(state, &stages, DORMAND_PRINCE_WEIGHTS[5], step)?;
+        // Zero in the units of each component
+        let zeros = state.iter().map(|value| value.clone() - value.clone()).collect::<CResult<Vec<_>>>()?;
+        let error = weighted_sum(&zeros, &stages, &DORMAND_PRINCE_ERROR, step)?;
+        let mut error_ratio: f64 = 0.0;
+        for ((error, before), after) in error.iter().zip(state.iter()).zip(&next) {
+            let size = 1.0f64.max(before.magnitude()?).max(after.magnitude()?);
+            error_ratio = error_ratio.max(error.magnitude()? / (ODE_TOLERANCE * size));
+        }
+
+        if error_ratio <= 1.0 {
+            fraction += step;
+            *state = next;
+            if fraction >= 1.0 {
+                return Ok(Some(step * length));
+            }
+            first_rates = stages.pop().unwrap();
+        }
+        let growth = if error_ratio.is_finite() { 0.9 * error_ratio.powf(-0.2) } else { 0.0 };
+        step *= growth.clamp(0.2, 5.0);
+        if fraction + step == fraction {
+            let x = (start.clone() + (span.clone() * fraction.into())?)?;
+            return Err(Error::EvalError(format!("The differential equation can't be solved past {x}")));
+        }
+    }
+    Err(Error::EvalError(format!("The differential equation takes too many steps to solve up to {end}")))
+}
+
+/// Every root of `f` between `lower` and `upper`, in order: where it changes sign between two samples (refined with
+/// Brent's method), or comes close to zero without changing sign (refined with Newton's method)
+pub fn find_roots<'a, T: BaseField<'a>>(lower: &T, upper: &T, f: impl Fn(T) -> CResult<T>) -> CResult<Vec<T>> {
+    let width = (upper.clone() - lower.clone())?;
+    let at = |fraction: f64| lower.clone() + (width.clone() * fraction.into())?;
+    let signed = |fraction: f64| signed_magnitude(&f(at(fraction)?)?);
+    let samples = (0..=ROOT_SAMPLES).map(|i| i as f64 / ROOT_SAMPLES as f64).map(|fraction| (fraction, signed(fraction))).collect::<Vec<_>>();
+    // Where the function can't be evaluated, e.g. ln(x) for x < 0, there are no roots
+    if samples.iter().all(|(_, value)| value.is_err()) {
+        return Err(samples.into_iter().find_map(|(_, value)| value.err()).unwrap());
+    }
+    let samples = samples.into_iter().map(|(fraction, value)| (fraction, value.unwrap_or(f64::NAN))).collect::<Vec<_>>();
+    let signed = |fraction: f64| signed(fraction).unwrap_or(f64::NAN);
+
+    let mut roots = vec![];
+    for (i, &(fraction, value)) in samples.iter().enumerate() {
+        if value == 0.0 {
+            roots.push(fraction);
+            continue;
+        }
+        if let Some(&(next, next_value)) = samples.get(i + 1) {
+            if value * next_value < 0.0 {
+                roots.extend(brent(signed, (fraction, value), (next, next_value)));
+            }
+        }
+        if let (Some(&(previous, previous_value)), Some(&(next, next_value))) = (i.checked_sub(1).map(|i| &samples[i]), samples.get(i + 1)) {
+            let touches = value * previous_value > 0.0 && value * next_value > 0.0;
+            if touches && value.abs() < previous_value.abs() && value.abs() < next_value.abs() {
+                roots.extend(newton(signed, fraction, (previous, next), previous_value.abs().max(next_value.abs())));
+            }
+        }
+    }
+    roots.sort_by(f64::total_cmp);
+    roots.dedup_by(|a, b| (*a - *b).abs() <= ROOT_TOLERANCE);
+    roots.into_iter().map(at).collect()
+}
+
+/// A root of `f` near `guess`, using Newton's method
+pub fn find_root_near<'a, T: BaseField<'a>>(guess: &T, f: impl Fn(T) -> CResult<T>) -> CResult<Option<T>> {
+    // Steps are measured relative to the guess, or to one of its units if it's zero
+    let scale = if guess.magnitude()? == 0.0 { guess.unit()? } else { guess.clone() };
+    let at = |offset: f64| guess.clone() + (scale.clone() * offset.into())?;
+    let initial = signed_magnitude(&f(guess.clone())?)?;
+    if initial == 0.0 {
+        return Ok(Some(guess.clone()));
+    }
+    let signed = |offset: f64| at(offset).and_then(&f).and_then(|value| signed_magnitude(&value)).unwrap_or(f64::NAN);
+    newton(signed, 0.0, (f64::NEG_INFINITY, f64::INFINITY), initial.abs()).map(at).transpose()
+}
+
+/// The magnitude of a value, negative if the value is below zero. Values that can't be told apart from zero (e.g. an
+/// interval around it, which rounding errors leave near roots) are zero
+fn signed_magnitude<'a, T: BaseField<'a>>(value: &T) -> CResult<f64> {
+    let zero = (value.clone() * T::from(0.0))?;
+    match value.compare(&zero) {
+        Ok(Some(std::cmp::Ordering::Less)) => Ok(-value.magnitude()?),
+        Ok(Some(_)) => value.magnitude(),
+        Ok(None) => Ok(f64::NAN),
+        // The value's own uncertainty reaches zero
+        Err(_) if (value.clone() - value.clone())?.magnitude()? >= value.magnitude()? => Ok(0.0),
+        Err(err) => Err(err),
+    }
+}
+
+/// The root of `f` between two points where it has opposite signs, using Brent's method, which combines bisection
+/// with interpolation. Poles, where the function changes sign without passing through zero, aren't roots
+fn brent(f: impl Fn(f64) -> f64, (mut a, mut f_a): (f64, f64), (mut b, mut f_b): (f64, f64)) -> Option<f64> {
+    let largest_root = f_a.abs().min(f_b.abs());
+    let (mut c, mut f_c) = (b, f_b);
+    let (mut step, mut previous_step) = (b - a, b - a);
+    for _ in 0..MAX_ROOT_ITERATIONS {
+        if (f_b > 0.0) == (f_c > 0.0) {
+            (c, f_c) = (a, f_a);
+            (step, previous_step) = (b - a, b - a);
+        }
+        // b is always the best estimate, and the root is between b and c
+        if f_c.abs() < f_b.abs() {
+            (a, f_a) = (b, f_b);
+            (b, f_b) = (c, f_c);
+            (c, f_c) = (a, f_a);
+        }
+        let tolerance = 2.0 * f64::EPSILON * b.abs() + 0.5 * f64::EPSILON;
+        let midpoint = (c - b) / 2.0;
+        if midpoint.abs() <= tolerance || f_b == 0.0 {
+            return (f_b.abs() <= largest_root).then_some(b);
+        }
+        if previous_step.abs() >= tolerance && f_a.abs() > f_b.abs() {
+            // Secant or inverse quadratic interpolation, if it stays well within the bracket
+            let s = f_b / f_a;
+            let (mut p, mut q) = if a == c {
+                (2.0 * midpoint * s, 1.0 - s)
+            } else {
+                let (q, r) = (f_a / f_c, f_b / f_c);
+                (s * (2.0 * midpoint * q * (q - r) - (b - a) * (r - 1.0)), (q - 1.0) * (r - 1.0) * (s - 1.0))
+            };
+            if p > 0.0 {
+                q = -q;
+            } else {
+                p = -p;
+            }
+            if 2.0 * p < (3.0 * midpoint * q - (tolerance * q).abs()).min((previous_step * q).abs()) {
+                previous_step = step;
+                step = p / q;
+            } else {
+                (step, previous_step) = (midpoint, midpoint);
+            }
+        } else {
+            (step, previous_step) = (midpoint, midpoint);
+        }
+        (a, f_a) = (b, f_b);
+        b += if step.abs() > tolerance { step } else { tolerance.copysign(midpoint) };
+        f_b = f(b);
+        if f_b.is_nan() {
+            return None;
+        }
+    }
+    None
+}
+
+/// A root of `f` near `start` and within `bounds`, using Newton's method with numerical derivatives. It's only a
+/// root if the function gets much smaller than `reference`, its size nearby
+fn newton(f: impl Fn(f64) -> f64, start: f64, bounds: (f64, f64), reference: f64) -> Option<f64> {
+    let mut x = start;
+    let mut value = f(x);
+    for _ in 0..MAX_ROOT_ITERATIONS {
+        if value == 0.0 {
+            return Some(x);
+        }
+        let step = 1e-7 * x.abs().max(1.0);
+        let slope = (f(x + step) - f(x - step)) / (2.0 * step);
+        let next = x - value / slope;
+        if !next.is_finite() || next < bounds.0 || next > bounds.1 {
+            return None;
+        }
+        let converged = (next - x).abs() <= ROOT_TOLERANCE * next.abs().max(1.0);
+        (x, value) = (next, f(next));
+        if converged {
+            return (value.abs() <= ROOT_RESIDUAL * reference).then_some(x);
+        }
+    }
+    None
+}
@@ -0,0 +1,180 @@
+use crate::error::Error;
+use crate::types::{BaseField, Expr};
+
+use std::collections::HashMap;
+
+
+/// The order to evaluate the lines of a sheet in, so that definitions can be used above where they are written.
+/// Lines that can't be evaluated, because they are defined twice or in terms of themselves, get an error instead
+pub struct EvaluationOrder {
+    pub order: Vec<usize>,
+    pub errors: HashMap<usize, Error>,
+}
+
+/// Sorts the lines (`None` for empty lines or ones that didn't parse) by their dependencies
+pub fn evaluation_order<T>(lines: &[Option<&Expr<T>>]) -> EvaluationOrder where for<'a> T: BaseField<'a> {
+    let mut definitions: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, expr) in lines.iter().enumerate() {
+        if let Some(name) = expr.and_then(Expr::defined_name) {
+            definitions.entry(name).or_default().push(i);
+        }
+    }
+    // Initial values, e.g. "y(0) = 1", go along with the differential equation that defines the name
+    let is_initial_value = |i: &usize| lines[*i].is_some_and(Expr::is_initial_value);
+    let mut errors = HashMap::new();
+    for (name, defining_lines) in &definitions {
+        let definitions = defining_lines.iter().copied().filter(|i| !is_initial_value(i)).collect::<Vec<_>>();
+        if definitions.len() > 1 {
+            for &i in &definitions {
+                errors.insert(i, Error::EvalError(format!("'{name}' is defined more than once, on lines {}", line_list(&definitions))));
+            }
+        }
+    }
+
+    // Each line depends on the lines that define the names it uses. Using the name being defined is left to the
+    // evaluator, which allows it for recursive functions
+    let dependencies = lines.iter().enumerate().map(|(i, expr)| {
+        let mut dependencies = expr.map(Expr::dependencies).unwrap_or_default().iter()
+            .filter_map(|name| definitions.get(name.as_str()))
+            .filter(|defining_lines| !defining_lines.iter().any(|line| errors.contains_key(line)))
+            .flatten()
+            .copied()
+            .filter(|&line| line != i)
+            .collect::<Vec<_>>();
+        dependencies.sort();
+        dependencies.dedup();
+        dependencies
+    }).collect::<Vec<_>>();
+
+    let mut order = vec![];
+    for component in strongly_connected_components(&dependencies) {
+        // Function definitions aren't evaluated until they're called, so functions can call each other
+        let only_functions = component.iter().all(|&i| matches!(lines[i], Some(Expr::EDefFunc(..))));
+        if component.len() > 1 && !only_functions {
+            for &i in &component {
+                errors.insert(i, Error::EvalError(format!("Circular definition between lines {}", line_list(&component))));
+            }
+        } else {
+            order.extend(component.into_iter().filter(|i| !errors.contains_key(i)));
+        }
+    }
+    EvaluationOrder { order, errors }
+}
+
+/// Groups of lines that depend on each other (Tarjan's algorithm). Each group comes after the ones it depends on
+fn strongly_connected_components(dependencies: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    struct Search<'a> {
+        dependencies: &'a [Vec<usize>],
+        index: Vec<Option<usize>>,
+        low_link: Vec<usize>,
+        on_stack: Vec<bool>,
+        stack: Vec<usize>,
+        components: Vec<Vec<usize>>,
+    }
+
+    impl Search<'_> {
+        fn visit(&mut self, line: usize) {
+            let index = self.stack.len() + self.components.iter().map(Vec::len).sum::<usize>();
+            self.index[line] = Some(index);
+            self.low_link[line] = index;
+            self.stack.push(line);
+            self.on_stack[line] = true;
+
+            let dependencies = self.dependencies;
+            for &dependency in &dependencies[line] {
+                match self.index[dependency] {
+                    None => {
+                        self.visit(dependency);
+                        self.low_link[line] = self.low_link[line].min(self.low_link[dependency]);
+                    },
+                    Some(dependency_index) if self.on_stack[dependency] => {
+                        self.low_link[line] = self.low_link[line].min(dependency_index);
+                    },
+                    Some(_) => (),
+                }
+            }
+
+            if self.low_link[line] == index {
+                let mut component = vec![];
+                while let Some(member) = self.stack.pop() {
+                    self.on_stack[member] = false;
+                    component.push(member);
+                    if member == line {
+                        break;
+                    }
+                }
+                component.sort();
+                self.components.push(component);
+            }
+        }
+    }
+
+    let count = dependencies.len();
+    let mut search = Search {
+        dependencies,
+        index: vec![None; count],
+        low_link: vec![0; count],
+        on_stack: vec![false; count],
+        stack: vec![],
+        components: vec![],
+    };
+    for line in 0..count {
+        if search.index[line].is_none() {
+            search.visit(line);
+        }
+    }
+    search.components
+}
+
+/// e.g. "2 and 5" or "1, 3 and 4", counting from 1
+fn line_list(lines: &[usize]) -> String {
+    let numbers = lines.iter().map(|i| (i + 1).to_string()).collect::<Vec<_>>();
+    match numbers.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {last}", rest.join(", ")),
+        _ => numbers.concat(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::evaluate_sheet;
+    use crate::fields::Float;
+
+    fn evaluate(inputs: &[&str]) -> Vec<Result<Option<f64>, String>> {
+        evaluate_sheet::<Float>(&inputs.join("\n"), None).unwrap().into_iter()
+            .map(|result| result.map(|value| value.map(|value| value.to_string().parse().unwrap())).map_err(|err| format!("{err:?}")))
+            .collect()
+    }
+
+    #[test]
+    fn test_order_independence() {
+        let results = evaluate(&["c = a + b", "a = 1", "", "b = f(a)", "f(x) = 2x"]);
+        assert_eq!(results, vec![Ok(Some(3.0)), Ok(Some(1.0)), Ok(None), Ok(Some(2.0)), Ok(None)]);
+
+        // Functions can call each other, wherever they are defined
+        let results = evaluate(&["even(n) = \\{n = 0: 1, odd(n - 1)\\}", "odd(n) = \\{n = 0: 0, even(n - 1)\\}", "even(10)"]);
+        assert_eq!(results[2], Ok(Some(1.0)));
+
+        // Differential equations can be used above their equation and initial values
+        let results = evaluate(&["y(0)", "y' = y", "y(0) = 2", "z(0) = 1"]);
+        assert_eq!(results, vec![Ok(Some(2.0)), Ok(None), Ok(Some(2.0)), Ok(Some(1.0))]);
+    }
+
+    #[test]
+    fn test_invalid_definitions() {
+        let results = evaluate(&["a = 1", "b = a", "a = 2"]);
+        assert_eq!(results[0], Err("EvalError(\"'a' is defined more than once, on lines 1 and 3\")".to_string()));
+        assert_eq!(results[2], results[0]);
+        assert!(results[1].is_err());
+
+        let results = evaluate(&["x = 1", "a = c + 1", "b = a", "c = f(b)", "f(y) = y", "d = x"]);
+        let circular = Err("EvalError(\"Circular definition between lines 2, 3 and 4\")".to_string());
+        assert_eq!(results, vec![Ok(Some(1.0)), circular.clone(), circular.clone(), circular, Ok(None), Ok(Some(1.0))]);
+
+        // A definition that fails doesn't stop later ones
+        let results = evaluate(&["x = 1/0 + q", "z = 2", "w = z + 1"]);
+        assert!(results[0].is_err());
+        assert_eq!(results[1..], [Ok(Some(2.0)), Ok(Some(3.0))]);
+    }
+}
@@ -1,18 +1,43 @@
-use crate::types::{BaseField, CResult, Context, Expr::{self, *}, LatexExpr};
+use crate::calculus;
+use crate::types::{primed_name, BaseField, CResult, Context, DifferentialEquation, Expr::{self, *}, InitialValue, LatexExpr};
 use crate::error::Error;
 
 use itertools::Itertools;
 
+/// How deeply user functions can call themselves (or each other) before evaluation is stopped
+pub const DEFAULT_MAX_DEPTH: usize = 100;
+
+/// Largest maximum depth a sheet can ask for, since the stack for all of the calls is reserved up front
+pub const MAX_DEPTH_LIMIT: usize = 10_000;
+
+/// Stack reserved for each nested function call. A simple recursive function takes about 64 KB per call in debug
+/// builds and a few KB in release builds, so only functions with large bodies stop before the maximum depth
+const STACK_PER_CALL: usize = 128 * 1024;
+
+/// Stack for everything around the function calls, as much as a main thread gets
+const BASE_STACK: usize = 8 * 1024 * 1024;
+
+/// Longest list that a range or comprehension can create, so a typo like `[1...1e9]` doesn't hang the app
+const MAX_LIST_LENGTH: usize = 10_000;
 
 #[derive(Debug, Clone)]
 pub struct Evaluator<T> where for<'a> T: BaseField<'a> {
     pub context: Context<T>,
     pub defining: Option<String>,
+    /// Number of user function calls being evaluated, which can't exceed `max_depth`
+    pub depth: usize,
+    pub max_depth: usize,
+    /// Position of the stack at the outermost function call, to measure how much stack the calls within it use
+    stack_start: Option<usize>,
 }
 
 impl<T> Evaluator<T> where for<'a> T: BaseField<'a> {
     pub fn new() -> Self {
-        Evaluator { context: Context::new(), defining: None }
+        Self::with_max_depth(DEFAULT_MAX_DEPTH)
+    }
+
+    pub fn with_max_depth(max_depth: usize) -> Self {
+        Evaluator { context: Context::new(), defining: None, depth: 0, max_depth, stack_start: None }
     }
 
     pub fn eval_expr_mut_context(&mut self, expr: &Expr<T>) -> CResult<Option<T>> {
@@ -21,9 +46,14 @@ impl<T> Evaluator<T> where for<'a> T: BaseField<'a> {
                 if self.defining.is_some() {
                     return Err(Error::EvalError(format!("Cannot contain nested variable definitions (variable '{}' & '{}')", var, self.defining.as_ref().unwrap())));
                 }
+                // A sum's index can still be called i, but a definition would change e.g. e^{i\pi} on every other line
+                if var == "i" && T::try_from("i").is_ok() {
+                    return Err(Error::EvalError("i is the imaginary unit, so it can't be defined in complex mode".to_string()));
+                }
                 self.defining = Some(var.clone());
-                let result = self.eval_expr(expr)?;
+                let result = self.eval_expr(expr);
                 self.defining = None;
+                let result = result?;
                 if self.context.vars.contains_key(var) {
                     return Err(Error::EvalError(format!("Variable '{var}' already defined")));
                 }
@@ -34,12 +64,37 @@ impl<T> Evaluator<T> where for<'a> T: BaseField<'a> {
                 if self.defining.is_some() {
                     return Err(Error::EvalError(format!("Cannot contain nested variable definitions (variable '{}' & '{}')", name, self.defining.as_ref().unwrap())));
                 }
-                if self.context.funcs.contains_key(name) {
+                if self.context.funcs.contains_key(name) || self.context.equations.contains_key(name) {
                     return Err(Error::EvalError(format!("Variable '{name}' already defined")));
                 }
                 self.context.funcs.insert(name.clone(), (params.clone(), *expr.clone()));
                 Ok(None)
             },
+            EDefODE(name, order, var, expr) => {
+                if self.context.funcs.contains_key(name) || self.context.equations.contains_key(name) {
+                    return Err(Error::EvalError(format!("Variable '{name}' already defined")));
+                }
+                let equation = DifferentialEquation { order: *order, var: var.clone(), expr: *expr.clone() };
+                self.context.equations.insert(name.clone(), equation);
+                Ok(None)
+            },
+            EDefInitialValue(name, order, point, value) => {
+                if self.context.equations.get(name).is_some_and(|equation| *order >= equation.order) {
+                    return Err(Error::EvalError(format!("{} is given by the differential equation, so it can't have an initial value", primed_name(name, *order))));
+                }
+                let (point, value) = (self.eval_expr(point)?, self.eval_expr(value)?);
+                let initial_values = self.context.initial_values.entry(name.clone()).or_default();
+                if initial_values.iter().any(|initial| initial.order == *order) {
+                    return Err(Error::EvalError(format!("The initial value of {} is given more than once", primed_name(name, *order))));
+                }
+                initial_values.push(InitialValue { order: *order, point, value: value.clone() });
+                Ok(Some(value))
+            },
+            EDefUnit(name, expr) => {
+                let result = self.eval_expr(expr)?;
+                self.context.units.define(result.define_unit(name)?)?;
+                Ok(Some(result))
+            },
             _ => Ok(Some(self.eval_expr(expr)?)),
         }
     }
@@ -50,49 +105,336 @@ impl<T> Evaluator<T> where for<'a> T: BaseField<'a> {
             ENum(num) => Ok(num.clone()),
             EAdd(expr1, expr2) => self.eval_expr(expr1)? + self.eval_expr(expr2)?,
             ESub(expr1, expr2) => self.eval_expr(expr1)? - self.eval_expr(expr2)?,
-            EMul(expr1, expr2) => Ok(self.eval_expr(expr1)? * self.eval_expr(expr2)?),
-            EDiv(expr1, expr2) => Ok(self.eval_expr(expr1)? / self.eval_expr(expr2)?),
+            EPlusMinus(expr1, expr2) => self.eval_expr(expr1)?.plus_minus(self.eval_expr(expr2)?),
+            EMul(expr1, expr2) => self.eval_expr(expr1)? * self.eval_expr(expr2)?,
+            EDiv(expr1, expr2) => self.eval_expr(expr1)? / self.eval_expr(expr2)?,
             EExp(expr1, expr2) => self.eval_expr(expr1)?.powf(self.eval_expr(expr2)?),
+            EHadamard(expr1, expr2) => self.eval_expr(expr1)?.hadamard(self.eval_expr(expr2)?),
+            EMatrix(rows) => {
+                let rows = rows.iter().map(|row| row.iter().map(|expr| self.eval_expr(expr)).collect()).collect::<CResult<_>>()?;
+                T::matrix(rows)
+            },
+            EList(elements) => T::list(elements.iter().map(|expr| self.eval_expr(expr)).collect::<CResult<_>>()?),
+            ERange(first, second, last) => self.evaluate_range(first, second.as_deref(), last),
+            EIndex(list, index) => {
+                let list = self.eval_expr(list)?;
+                let elements = list.as_list().ok_or_else(|| Error::EvalError("Only lists can be indexed".to_string()))?;
+                let index = self.eval_expr(index)?;
+                if index.fract()? != 0.0 {
+                    return Err(Error::EvalError("List indices must be integers".to_string()));
+                }
+                // Like Desmos, lists are indexed from 1
+                let position = index.as_scalar()? as i64;
+                if position < 1 || position as usize > elements.len() {
+                    return Err(Error::EvalError(format!("Index {position} is outside of a list of length {}", elements.len())));
+                }
+                Ok(elements[position as usize - 1].clone())
+            },
+            EListComprehension(expr, var, list) => {
+                let elements = self.eval_expr(list)?.as_list()
+                    .ok_or_else(|| Error::EvalError(format!("'{var}' must range over a list")))?;
+                let mut element_eval = self.clone();
+                let results = elements.into_iter().map(|element| {
+                    element_eval.context.vars.insert(var.clone(), element);
+                    element_eval.eval_expr(expr)
+                }).collect::<CResult<_>>()?;
+                T::list(results)
+            },
             EVar(var) => {
-                if self.defining.is_some() && var == self.defining.as_ref().unwrap() {
-                    return Err(Error::EvalError(format!("Variable '{var}' cannot be defined recursively")))
-                } else if let Some(val) = self.context.vars.get(var) {
+                // Variables shadow units of the same name, except in their own definition, e.g. "s = 10 s"
+                let recursive = self.defining.as_ref() == Some(var);
+                if let Some(val) = self.context.vars.get(var).filter(|_| !recursive) {
                     Ok(val.clone())
+                } else if let Ok(val) = T::try_from(var.as_str()) {
+                    // Built-in units, and constants such as i in complex mode
+                    Ok(val)
+                } else if let Ok(val) = T::unit_identity(var, &self.context.units) {
+                    Ok(val)
+                } else if recursive {
+                    Err(Error::EvalError(format!("Variable '{var}' cannot be defined recursively")))
                 } else {
                     Err(Error::DefinitionNotFoundError(var.clone()))
                 }
             },
             EFunc(name, inputs) => {
-                if self.defining.is_some() && name == self.defining.as_ref().unwrap() {
-                    Err(Error::EvalError(format!("Function '{name}' cannot be defined recursively")))
-                } else if let Some((params, func_def)) = self.context.funcs.get(name) {
-                    if params.len() != inputs.len() {
-                        return Err(Error::EvalError(format!("Function '{}' expects {} arguments, but got {}", name, params.len(), inputs.len())));
-                    }
-                    let mut sub_eval = self.clone();
-                    for (param, input) in params.iter().zip(inputs.iter()) {
-                        sub_eval.context.vars.insert(param.clone(), self.eval_expr(input)?);
-                    }
-                    sub_eval.eval_expr(func_def)
+                if let Some((params, func_def)) = self.context.funcs.get(name) {
+                    self.call_function(name, params, func_def, inputs)
+                } else if let Some(equation) = self.context.equations.get(name) {
+                    let [input] = inputs.as_slice() else {
+                        return Err(Error::EvalError(format!("Function '{name}' expects 1 argument, but got {}", inputs.len())));
+                    };
+                    self.solve_differential_equation(name, equation, self.eval_expr(input)?)
                 } else {
-                    Err(Error::DefinitionNotFoundError(name.clone()))
+                    self.apply_default_function(name, inputs)
                 }
             },
+            EDerivative(expr, var, point) => {
+                self.eval_expr(point)?.derivative(|x| {
+                    let mut derivative_eval = self.clone();
+                    derivative_eval.context.vars.insert(var.clone(), x);
+                    derivative_eval.eval_expr(expr)
+                })
+            },
+            EIntegral(integrand, var, lower, upper) => {
+                let lower = lower.try_map(|limit| self.eval_expr(limit))?;
+                let upper = upper.try_map(|limit| self.eval_expr(limit))?;
+                T::integral(&lower, &upper, |x| {
+                    let mut integral_eval = self.clone();
+                    integral_eval.context.vars.insert(var.clone(), x);
+                    integral_eval.eval_expr(integrand)
+                })
+            },
+            ESolve(lhs, rhs, var, limits) => self.solve(lhs, rhs, var, limits),
+            EPiecewise(conditions, otherwise) => {
+                for (condition, value) in conditions {
+                    if self.eval_condition(condition)? {
+                        return self.eval_expr(value);
+                    }
+                }
+                match otherwise {
+                    Some(value) => self.eval_expr(value),
+                    None => Err(Error::EvalError("None of the conditions hold, and there is no value for otherwise".to_string())),
+                }
+            },
+            ECompare(..) | EAnd(..) | EOr(..) => {
+                Err(Error::EvalError("Comparisons can only be used as conditions, e.g. \\{x < 0: -x, x\\}".to_string()))
+            },
             ETex(expr) => self.eval_latex(expr),
+            EConvert(expr, unit) => self.eval_expr(expr)?.convert(unit, &self.context.units),
             _ => Err(Error::EvalError(format!("Unexpected expression '{expr:?}'. Did you mean to call `eval_expr_mut_context`?")),)
         }
     }
 
+    /// Calls a user defined function. Functions may call themselves, but only `max_depth` calls deep, and only while
+    /// the calls fit in the stack that `with_stack` reserves for them
+    fn call_function(&self, name: &str, params: &[String], func_def: &Expr<T>, inputs: &[Expr<T>]) -> CResult<T> {
+        if params.len() != inputs.len() {
+            return Err(Error::EvalError(format!("Function '{}' expects {} arguments, but got {}", name, params.len(), inputs.len())));
+        }
+        if self.depth >= self.max_depth {
+            return Err(Error::EvalError(format!("Function '{name}' exceeded the maximum recursion depth of {}", self.max_depth)));
+        }
+        let position = stack_position();
+        if self.stack_start.is_some_and(|start| start.abs_diff(position) > self.max_depth * STACK_PER_CALL) {
+            return Err(Error::EvalError(format!("Function '{name}' ran out of stack after {} nested calls", self.depth)));
+        }
+        let mut sub_eval = self.clone();
+        sub_eval.depth += 1;
+        sub_eval.stack_start.get_or_insert(position);
+        for (param, input) in params.iter().zip(inputs.iter()) {
+            sub_eval.context.vars.insert(param.clone(), self.eval_expr(input)?);
+        }
+        sub_eval.eval_expr(func_def)
+    }
+
+    /// Whether a condition such as `x > 0 and x < 1 km` holds
+    fn eval_condition(&self, condition: &Expr<T>) -> CResult<bool> {
+        match condition {
+            ECompare(lhs, comparison, rhs) => {
+                let ordering = self.eval_expr(lhs)?.compare(&self.eval_expr(rhs)?)?;
+                // Nothing is equal to (or ordered with) NaN
+                Ok(ordering.is_some_and(|ordering| comparison.holds(ordering)))
+            },
+            EAnd(lhs, rhs) => Ok(self.eval_condition(lhs)? && self.eval_condition(rhs)?),
+            EOr(lhs, rhs) => Ok(self.eval_condition(lhs)? || self.eval_condition(rhs)?),
+            _ => Err(Error::EvalError(format!("Expected a condition such as 'x < 0', but got {condition:?}"))),
+        }
+    }
+
     fn apply_default_function(&self, name: &str, inputs: &Vec<Expr<T>>) -> CResult<T> {
+        if matches!(name, "total" | "sum" | "mean" | "length") {
+            return self.apply_list_function(name, inputs);
+        }
+        let variadic_function: Option<fn(&T, &T) -> CResult<T>> = match name {
+            "min" => Some(T::min),
+            "max" => Some(T::max),
+            "gcd" => Some(T::gcd),
+            "lcm" => Some(T::lcm),
+            _ => None,
+        };
+        if let Some(function) = variadic_function {
+            let mut elements = self.list_arguments(name, inputs)?.into_iter();
+            let first = elements.next().ok_or_else(|| Error::EvalError(format!("{name} expects at least one value")))?;
+            return elements.try_fold(first, |result, element| function(&result, &element));
+        }
+        if name == "log" && inputs.len() == 1 {
+            return self.eval_expr(&inputs[0])?.log(&10.0.into());
+        }
+        let binary_function: Option<fn(&T, &T) -> CResult<T>> = match name {
+            "dot" => Some(T::dot),
+            "cross" => Some(T::cross),
+            "log" => Some(T::log),
+            "mod" => Some(T::modulo),
+            "nCr" | "binom" => Some(T::combinations),
+            "nPr" => Some(T::permutations),
+            _ => None,
+        };
+        if let Some(function) = binary_function {
+            if inputs.len() != 2 {
+                return Err(Error::EvalError(format!("{name} expects 2 arguments, received {}", inputs.len())));
+            }
+            return function(&self.eval_expr(&inputs[0])?, &self.eval_expr(&inputs[1])?);
+        }
+        let function: fn(&T) -> CResult<T> = match name {
+            "sin" => T::sin,
+            "cos" => T::cos,
+            "tan" => T::tan,
+            "arcsin" | "asin" => T::asin,
+            "arccos" | "acos" => T::acos,
+            "arctan" | "atan" => T::atan,
+            "sinh" => T::sinh,
+            "cosh" => T::cosh,
+            "tanh" => T::tanh,
+            "arsinh" | "arcsinh" | "asinh" => T::asinh,
+            "arcosh" | "arccosh" | "acosh" => T::acosh,
+            "artanh" | "arctanh" | "atanh" => T::atanh,
+            "ln" => T::ln,
+            "exp" => T::exp,
+            "floor" => T::floor,
+            "ceil" => T::ceil,
+            "round" => T::round,
+            "sign" | "sgn" | "signum" => T::sign,
+            "factorial" => T::factorial,
+            "abs" => T::abs,
+            "re" | "Re" => T::re,
+            "im" | "Im" => T::im,
+            "conj" => T::conj,
+            "arg" => T::arg,
+            "det" => T::det,
+            "inv" => T::inv,
+            "transpose" => T::transpose,
+            _ => return Err(Error::DefinitionNotFoundError(name.to_string())),
+        };
         if inputs.len() != 1 {
             return Err(Error::EvalError(format!("Default functions only accept one argument, received {} for {name}", inputs.len())));
         }
-        let input = self.eval_expr(inputs.get(0).unwrap())?;
+        function(&self.eval_expr(inputs.get(0).unwrap())?)
+    }
+
+    /// The values a function such as `mean` or `min` is applied to. Several arguments are treated as a list,
+    /// e.g. `mean(1, 2, 3)`
+    fn list_arguments(&self, name: &str, inputs: &[Expr<T>]) -> CResult<Vec<T>> {
+        match inputs {
+            [] => Err(Error::EvalError(format!("{name} expects a list"))),
+            [input] => {
+                let value = self.eval_expr(input)?;
+                Ok(value.as_list().unwrap_or_else(|| vec![value]))
+            },
+            inputs => inputs.iter().map(|input| self.eval_expr(input)).collect(),
+        }
+    }
+
+    /// Reduces a list to a single value
+    fn apply_list_function(&self, name: &str, inputs: &[Expr<T>]) -> CResult<T> {
+        let elements = self.list_arguments(name, inputs)?;
+        let length = elements.len();
+        if name == "length" {
+            return Ok((length as f64).into());
+        }
+        let total = elements.into_iter()
+            .map(Ok)
+            .reduce(|a, b| a? + b?)
+            .unwrap_or_else(|| Ok(0.0.into()))?;
         match name {
-            "sin" => input.sin(),
-            "cos" => input.cos(),
-            "tan" => input.tan(),
-            _ => Err(Error::EvalError(format!("Function '{name}' not defined")))
+            "mean" if length == 0 => Err(Error::EvalError("Cannot take the mean of an empty list".to_string())),
+            "mean" => total / (length as f64).into(),
+            _ => Ok(total),
+        }
+    }
+
+    /// Evaluates `[first, second, ..., last]`, stepping by one (SI) unit towards `last` if there is no second element,
+    /// e.g. `[1 m...3 m]` is `[1 m, 2 m, 3 m]`
+    fn evaluate_range(&self, first: &Expr<T>, second: Option<&Expr<T>>, last: &Expr<T>) -> CResult<T> {
+        let first = self.eval_expr(first)?;
+        let last = self.eval_expr(last)?;
+        // Subtracting checks that all the elements have the same units, so the number of steps is unitless
+        let span = (last - first.clone())?;
+        let mut step = match second {
+            Some(second) => (self.eval_expr(second)? - first.clone())?,
+            None => first.unit()?,
+        };
+        let mut steps = (span / step.clone())?.as_scalar()?;
+        if second.is_none() && steps < 0.0 {
+            step = (step * (-1.0).into())?;
+            steps = -steps;
+        }
+        // Rounded slightly up, so that e.g. [0, 0.1, ..., 1] includes 1
+        let count = (steps + 1e-9).floor() + 1.0;
+        if !count.is_finite() || count < 1.0 {
+            return Err(Error::EvalError("The range never reaches its last element".to_string()));
+        }
+        if count > MAX_LIST_LENGTH as f64 {
+            return Err(Error::EvalError(format!("Ranges can have at most {MAX_LIST_LENGTH} elements")));
+        }
+        let elements = (0..count as usize)
+            .map(|i| first.clone() + (step.clone() * (i as f64).into())?)
+            .collect::<CResult<_>>()?;
+        T::list(elements)
+    }
+
+    /// Solves `lhs = rhs` for `var`: every solution between two limits, or the one closest to a guess (one if not given)
+    fn solve(&self, lhs: &Expr<T>, rhs: &Expr<T>, var: &str, limits: &[Expr<T>]) -> CResult<T> {
+        let limits = limits.iter().map(|limit| self.eval_expr(limit)).collect::<CResult<Vec<_>>>()?;
+        let difference = |x: T| {
+            let mut solve_eval = self.clone();
+            solve_eval.context.vars.insert(var.to_string(), x);
+            solve_eval.eval_expr(lhs)? - solve_eval.eval_expr(rhs)?
+        };
+        match limits.as_slice() {
+            [lower, upper] => {
+                let mut roots = calculus::find_roots(lower, upper, difference)?;
+                match roots.len() {
+                    0 => Err(Error::EvalError(format!("No solutions for '{var}' were found between {lower} and {upper}"))),
+                    1 => Ok(roots.remove(0)),
+                    _ => T::list(roots),
+                }
+            },
+            [guess] => calculus::find_root_near(guess, difference)?.ok_or_else(|| {
+                Error::EvalError(format!("No solution for '{var}' was found near {guess}. Try searching between two limits instead"))
+            }),
+            [] => match calculus::find_root_near(&1.0.into(), difference) {
+                Ok(Some(root)) => Ok(root),
+                Ok(None) => Err(Error::EvalError(format!("No solution for '{var}' was found. Try giving a guess, or two limits to search between"))),
+                Err(Error::UnitError(_)) => Err(Error::UnitError(format!("'{var}' has units, so give a guess with units to solve for it, e.g. solve(..., {var}, 1 m)"))),
+                Err(err) => Err(err),
+            },
+            _ => Err(Error::EvalError("solve expects an equation, a variable, and optionally a guess or two limits".to_string())),
+        }
+    }
+
+    /// The solution of a differential equation at a point, or at each point of a list, e.g. to plot it
+    fn solve_differential_equation(&self, name: &str, equation: &DifferentialEquation<T>, at: T) -> CResult<T> {
+        let initial_values = self.context.initial_values.get(name).map(Vec::as_slice).unwrap_or_default();
+        let mut start = None;
+        let mut initial = vec![];
+        for order in 0..equation.order {
+            let initial_value = initial_values.iter().find(|initial| initial.order == order).ok_or_else(|| {
+                Error::EvalError(format!("'{name}' needs an initial value for {}, e.g. {}(0) = 1", primed_name(name, order), primed_name(name, order)))
+            })?;
+            let start = start.get_or_insert(&initial_value.point);
+            if start.compare(&initial_value.point)? != Some(std::cmp::Ordering::Equal) {
+                return Err(Error::EvalError(format!("The initial values of '{name}' must all be at the same point")));
+            }
+            initial.push(initial_value.value.clone());
+        }
+        if let Some(extra) = initial_values.iter().find(|initial| initial.order >= equation.order) {
+            return Err(Error::EvalError(format!("{} is given by the differential equation, so it can't have an initial value", primed_name(name, extra.order))));
+        }
+
+        let rate = |x: &T, state: &[T]| {
+            let mut equation_eval = self.clone();
+            for (order, value) in state.iter().enumerate() {
+                equation_eval.context.vars.insert(primed_name(name, order), value.clone());
+            }
+            if let Some(var) = &equation.var {
+                equation_eval.context.vars.insert(var.clone(), x.clone());
+            }
+            equation_eval.eval_expr(&equation.expr)
+        };
+        let start = start.cloned().unwrap_or_else(|| 0.0.into());
+        match at.as_list() {
+            Some(points) => T::list(calculus::solve_ode(&start, &initial, &points, rate)?),
+            None => Ok(calculus::solve_ode(&start, &initial, &[at], rate)?.remove(0)),
         }
     }
 
@@ -106,7 +448,7 @@ impl<T> Evaluator<T> where for<'a> T: BaseField<'a> {
                     return Err(Error::EvalError("frac does not support subscripts or superscripts".to_string()));
                 }
                 let (num, denom) = expr.params.clone().into_iter().collect_tuple().unwrap();
-                Ok(self.eval_expr(&num)? / self.eval_expr(&denom)?)
+                self.eval_expr(&num)? / self.eval_expr(&denom)?
             },
             "sqrt" => {
                 if expr.params.len() != 1 {
@@ -124,6 +466,14 @@ impl<T> Evaluator<T> where for<'a> T: BaseField<'a> {
             "prod" => {
                 self.evaluate_repetition(expr, |a, b| a * b, 1.0)
             },
+            // The subscript is the base, e.g. "\log_2 8"
+            "log" if expr.subscript.is_some() => {
+                if expr.params.len() != 1 || expr.superscript.is_some() {
+                    return Err(Error::EvalError("log expects 1 argument and a base".to_string()));
+                }
+                let base = self.eval_expr(expr.subscript.as_ref().unwrap())?;
+                self.eval_expr(&expr.params[0])?.log(&base)
+            },
             name => {
                 if expr.subscript.is_some() || expr.superscript.is_some() {
                     return Err(Error::EvalError(format!("Function {name} does not support subscripts or superscripts")));
@@ -133,7 +483,7 @@ impl<T> Evaluator<T> where for<'a> T: BaseField<'a> {
         }
     }
 
-    fn evaluate_repetition(&self, expr: &LatexExpr<T>, op: impl Fn(f64, f64) -> f64, identity: f64) -> Result<T, Error> {
+    fn evaluate_repetition(&self, expr: &LatexExpr<T>, op: impl Fn(T, T) -> CResult<T>, identity: f64) -> Result<T, Error> {
         if expr.params.len() != 1 || expr.subscript.is_none() || expr.superscript.is_none() {
             return Err(Error::EvalError(format!("Summation expects a parameter, a subscript, and a superscript, received {:?}", expr)));
         }
@@ -147,7 +497,15 @@ impl<T> Evaluator<T> where for<'a> T: BaseField<'a> {
             EDefVar(name, _) => Some(name),
             _ => None,
         };
-        let lb = sum_eval.eval_expr_mut_context(&subscript)?.unwrap();
+        // The index shadows a variable of the same name, e.g. from an enclosing sum in a recursive call
+        if let Some(var) = &lb_var {
+            sum_eval.context.vars.remove(var);
+        }
+        // The index is bound by the sum rather than defined in the sheet, so it can be called i in complex mode
+        let lb = match subscript.as_ref() {
+            EDefVar(_, start) => sum_eval.eval_expr(start)?,
+            subscript => sum_eval.eval_expr_mut_context(subscript)?.unwrap(),
+        };
 
         // Ensure up and ub are integers
         if ub.fract()? != 0.0 || lb.fract()? != 0.0 {
@@ -156,17 +514,43 @@ impl<T> Evaluator<T> where for<'a> T: BaseField<'a> {
         let up = ub.as_scalar()? as i32 + 1;
         let ub = lb.as_scalar()? as i32;
 
-        let mut sum = identity;
+        // Terms are combined in the field itself, so they keep their units and precision
+        let mut result: Option<T> = None;
         for i in ub..up {
             if lb_var.is_some() {
                 sum_eval.context.vars.insert(lb_var.clone().unwrap(), (i as f64).into());
             }
-            sum = op(sum, sum_eval.eval_expr(&param)?.as_scalar()?);
+            let term = sum_eval.eval_expr(&param)?;
+            result = Some(match result {
+                Some(result) => op(result, term)?,
+                None => term,
+            });
         }
-        Ok(sum.into())
+        Ok(result.unwrap_or_else(|| identity.into()))
     }
 }
 
+/// Runs `f` on a thread with enough stack for evaluators with the given maximum depth. Evaluating on a smaller
+/// stack (e.g. the 2 MiB of other threads) can overflow it before the depth is reached
+pub fn with_stack<R: Send>(max_depth: usize, f: impl FnOnce() -> R + Send) -> CResult<R> {
+    if max_depth > MAX_DEPTH_LIMIT {
+        return Err(Error::EvalError(format!("The maximum recursion depth can be at most {MAX_DEPTH_LIMIT}")));
+    }
+    std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(BASE_STACK + max_depth * STACK_PER_CALL)
+            .spawn_scoped(scope, f)
+            .map_err(|err| Error::EvalError(format!("Couldn't start evaluating: {err}")))?;
+        Ok(thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+    })
+}
+
+/// Roughly where the stack currently is. Only differences between positions are meaningful
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -179,6 +563,17 @@ mod tests {
         eval.eval_expr_mut_context(&expr).unwrap().unwrap()
     }
 
+    /// Evaluates each input and its expected value, which must agree to a relative error of 1e-9
+    fn assert_close<T>(tests: Vec<(&str, &str)>, eval: &mut Evaluator<T>) where for<'a> T: BaseField<'a> + 'a {
+        use crate::{evaluate_line, types::Span};
+        for (input, expected) in tests {
+            let result = evaluate_line(Span::new(input), eval).unwrap().unwrap();
+            let expected = evaluate_line(Span::new(expected), eval).unwrap().unwrap();
+            let error = ((result - expected.clone()).unwrap() / expected).unwrap().magnitude().unwrap();
+            assert!(error < 1e-9, "{input}: relative error {error}");
+        }
+    }
+
     fn num(x: f64) -> Expr<UnitVal> {
         ENum(UnitVal::scalar(x))
     }
@@ -255,4 +650,219 @@ mod tests {
         );
         assert_eq!(evaluate(expr), UnitVal::new_value(2.0, "km"));
     }
+
+    #[test]
+    fn test_variables_shadow_units() {
+        use crate::{evaluate_line, types::Span};
+        let mut eval = Evaluator::<UnitVal>::new();
+        let mut evaluate = |input: &str| evaluate_line(Span::new(input), &mut eval).unwrap().map(|value| value.to_string());
+        assert_eq!(evaluate("2 t").unwrap(), "2000 kg");
+        assert_eq!(evaluate("\\int_0^2 t dt").unwrap(), "2");
+        evaluate("f(h) = h + 1");
+        assert_eq!(evaluate("f(2)").unwrap(), "3");
+        evaluate("t = 2 s");
+        assert_eq!(evaluate("3 t").unwrap(), "6 s");
+        let slope: f64 = evaluate("\\frac{d}{dt} 3 t").unwrap().parse().unwrap();
+        assert!((slope - 3.0).abs() < 1e-9);
+        evaluate("s = 10 s");
+        assert_eq!(evaluate("2 s").unwrap(), "20 s");
+    }
+
+    #[test]
+    fn test_piecewise() {
+        use crate::{evaluate_line, types::Span};
+        let mut eval = Evaluator::<UnitVal>::new();
+        let mut evaluate = |input: &str| evaluate_line(Span::new(input), &mut eval).map(|result| result.map(|value| value.to_string()));
+        evaluate("f(x) = \\left\\{x<0:-x,x\\right\\}").unwrap();
+        assert_eq!(evaluate("f(2 m - 5 m)").unwrap().unwrap(), "3 m");
+        assert_eq!(evaluate("f(4)").unwrap().unwrap(), "4");
+        assert_eq!(evaluate("\\{1 km > 500 m: 1, 2\\}").unwrap().unwrap(), "1");
+        assert_eq!(evaluate("\\{1 km \\le 500 m or 2 \\ne 2: 1, 2\\}").unwrap().unwrap(), "2");
+        assert_eq!(evaluate("\\{20 °C > 50 °F and 1 = 1\\}").unwrap().unwrap(), "1");
+        evaluate("fib(k) = \\begin{cases} k & k < 2 \\\\ fib(k - 1) + fib(k - 2) & \\text{otherwise} \\end{cases}").unwrap();
+        assert_eq!(evaluate("fib(12)").unwrap().unwrap(), "144");
+
+        evaluate("\\{1 km > 500 s: 1, 2\\}").unwrap_err();
+        evaluate("\\{1 > 2: 1\\}").unwrap_err();
+    }
+
+    #[test]
+    fn test_math_functions() {
+        use crate::{evaluate_line, fields::Value, types::Span};
+        let mut eval = Evaluator::<Value<UnitVal>>::new();
+        let tests = vec![
+            ("\\log_2 8", "3"),
+            ("\\log_{10}(1000)", "3"),
+            ("log(100)", "2"),
+            ("\\ln(e^2)", "2"),
+            ("\\operatorname{arsinh}(\\sinh(1))", "1"),
+            ("5!", "120"),
+            ("3!^2", "36"),
+            ("nCr(5, 2)", "10"),
+            ("\\binom{6}{3}", "20"),
+            ("nPr(5, 2)", "20"),
+            ("mod(-1, 3)", "2"),
+            ("mod(7 m, 2 m)", "1 m"),
+            ("\\min(3, 1, 2)", "1"),
+            ("max([4, 9, 2])", "9"),
+            ("min(1 km, 500 m)", "500 m"),
+            ("gcd(12, 18, 30)", "6"),
+            ("lcm(4, 6)", "12"),
+            ("floor(2.7) + ceil(2.2) + round(-2.5)", "2"),
+            ("sign(-3 m)", "-1"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(evaluate_line(Span::new(input), &mut eval).unwrap().unwrap().to_string(), expected, "{input}");
+        }
+        for input in ["2.5!", "(-1)!", "171!", "nPr(1000, 200)", "nCr(2, 3)", "gcd(1.5, 3)", "floor(2 m)", "min(1 m, 1 s)"] {
+            assert!(evaluate_line(Span::new(input), &mut eval).is_err(), "{input} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_derivatives() {
+        use crate::{evaluate_line, types::Span};
+        let mut eval = Evaluator::<UnitVal>::new();
+        for definition in ["x = 3", "pos(w) = w^3 \\cdot 5 m/s^3", "g(y) = \\frac{d}{dy} \\sin(y)", "temp(w) = 20 °C + w \\cdot 2 K/s"] {
+            evaluate_line(Span::new(definition), &mut eval).unwrap();
+        }
+        let tests = vec![
+            ("\\frac{d}{dx} x^2", "6"),
+            ("\\frac{d}{dx}\\left(x^3 + 1\\right) + 1", "28"),
+            ("\\frac{d^2}{dx^2} x^3", "18"),
+            ("\\frac{d}{dx} \\ln(x)", "1/3"),
+            ("g(0)", "1"),
+            ("pos'(2 s)", "60 m/s"),
+            ("pos''(2 s)", "60 m/s^2"),
+            ("temp'(10 s)", "2 K/s"),
+        ];
+        assert_close(tests, &mut eval);
+
+        for input in ["\\frac{d}{dz} z^2", "\\frac{d^2}{dx^3} x^3", "\\frac{d^4}{dx^4} x^3", "pos'(1 s, 2 s)"] {
+            assert!(evaluate_line(Span::new(input), &mut eval).is_err(), "{input} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_integrals() {
+        use crate::{evaluate_line, types::Span};
+        let mut eval = Evaluator::<UnitVal>::new();
+        evaluate_line(Span::new("force(w) = 3 N + w \\cdot 2 N/m"), &mut eval).unwrap();
+        let tests = vec![
+            ("\\int_0^1 x^2 dx", "1/3"),
+            ("\\int_0^1 x^2 dx + 1", "4/3"),
+            ("\\int_1^0 x \\, dx", "-1/2"),
+            ("\\int_0^{\\pi} \\sin(y) \\mathrm{d}y", "2"),
+            ("\\int_0^\\infty e^{-x} dx", "1"),
+            ("\\int_{-\\infty}^{\\infty} e^{-x^2} dx", "\\sqrt{\\pi}"),
+            ("\\int_0^1 \\frac{1}{\\sqrt{x}} dx", "2"),
+            ("\\int_0^1 \\int_0^2 x \\cdot y \\, dy \\, dx", "1"),
+            ("\\int_0^3 dx", "3"),
+            ("\\int_{0 m}^{2 m} force(w) dw", "10 J"),
+            ("\\int_0^{2 m} 3 N dx", "6 J"),
+            ("\\int_{2 m}^0 3 N dx", "-6 J"),
+        ];
+        assert_close(tests, &mut eval);
+
+        // Without room for the estimated error, only the digits it leaves are shown
+        let result = evaluate_line(Span::new("\\int_0^1 \\frac{1}{\\sqrt{x}} dx"), &mut eval).unwrap().unwrap();
+        assert_eq!(result.to_string(), "2");
+
+        for input in ["\\int_0^1 x^2", "\\int^1 x dx", "\\int_1^\\infty \\frac{1}{x} dx", "\\int_{0 s}^{1 m} 1 dx"] {
+            assert!(evaluate_line(Span::new(input), &mut eval).is_err(), "{input} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_differential_equations() {
+        use crate::{evaluate_line, fields::Value, types::Span};
+        let mut eval = Evaluator::<Value<UnitVal>>::new();
+        let definitions = [
+            "spring = 4", "y'' = -spring \\cdot y", "y(0) = 1", "y'(0) = 0",
+            "pos'' = 9.8 m/s^2", "pos(0 s) = 0 m", "pos'(0 s) = 2 m/s",
+            "decay'(x) = -x \\cdot decay", "decay(0) = 3",
+        ];
+        for definition in definitions {
+            evaluate_line(Span::new(definition), &mut eval).unwrap();
+        }
+        let tests = vec![
+            ("y(1)", "\\cos(2)"),
+            ("y(-2.5)", "\\cos(-5)"),
+            ("y(10) + 1", "1 + \\cos(20)"),
+            ("pos(2 s)", "23.6 m"),
+            ("decay(2)", "3 e^{-2}"),
+            ("y([0.5, 1, -1])", "[\\cos(1), \\cos(2), \\cos(2)]"),
+            ("pos([0 s, 3 s, 1 s])", "[0 m, 50.1 m, 6.9 m]"),
+        ];
+        assert_close(tests, &mut eval);
+
+        let definitions = ["growth' = growth \\cdot growth", "growth(0) = 1", "z' = z", "w'' = -w", "w(0) = 1", "w'(1) = 0"];
+        for definition in definitions {
+            evaluate_line(Span::new(definition), &mut eval).unwrap();
+        }
+        for input in ["growth(2)", "z(1)", "w(1)", "y(0) = 2", "y''(0) = 1", "y(1, 2)"] {
+            assert!(evaluate_line(Span::new(input), &mut eval).is_err(), "{input} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_solve() {
+        use crate::{evaluate_line, fields::Value, types::Span};
+        let mut eval = Evaluator::<Value<UnitVal>>::new();
+        evaluate_line(Span::new("f(w) = w^3 - 2 w"), &mut eval).unwrap();
+        let tests = vec![
+            ("solve(x^2 = 2, x)", "\\sqrt{2}"),
+            ("\\operatorname{solve}\\left(\\cos(x)=x,x,1\\right)", "0.7390851332151607"),
+            ("solve(f(w) = 10, w) + 1", "3.46204478758741"),
+            ("solve(x^2 = 4 m^2, x, 1 m)", "2 m"),
+            ("solve(x^2 = 4, x, -10, 10)", "[-2, 2]"),
+            ("solve(\\sin(x) = 0.5, x, 0, 10)", "[\\pi/6, 5\\pi/6, 13\\pi/6, 17\\pi/6]"),
+            ("solve(\\ln(x) = 1, x, -5, 5)", "e"),
+            ("solve(x^2 = 9 m^2, x, 0 m, 1 km)", "3 m"),
+        ];
+        assert_close(tests, &mut eval);
+        // Roots where the function only touches zero are found too
+        let result = evaluate_line(Span::new("solve((x - 1)^2 = 0, x, -2, 2)"), &mut eval).unwrap().unwrap();
+        assert!((result.as_scalar().unwrap() - 1.0).abs() < 1e-9);
+
+        // Poles, where the sign changes without passing through zero, aren't solutions
+        for input in ["solve(x^2 = -1, x)", "solve(x^2 = -1, x, -5, 5)", "solve(\\tan(x) = 0, x, 1.5, 1.6)", "solve(x^2 = 4 m^2, x)", "solve(x^2, x)"] {
+            assert!(evaluate_line(Span::new(input), &mut eval).is_err(), "{input} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_recursion() {
+        // Function calls need the stack that sheets are evaluated with
+        with_stack(DEFAULT_MAX_DEPTH, || {
+            use crate::{evaluate_line, fields::Float, types::Span};
+            let mut eval = Evaluator::<Float>::new();
+            // Each call adds up all of the smaller ones, so f(n) = 2^(n-1)
+            evaluate_line(Span::new("f(n) = 1 + \\sum_{i=1}^{n-1} f(i)"), &mut eval).unwrap();
+            assert_eq!(evaluate_line(Span::new("f(6)"), &mut eval).unwrap(), Some(32.0.into()));
+
+            evaluate_line(Span::new("a = a + 1"), &mut eval).unwrap_err();
+            assert_eq!(evaluate_line(Span::new("b = 4"), &mut eval).unwrap(), Some(4.0.into()));
+            assert_eq!(evaluate_line(Span::new("f(b)"), &mut eval).unwrap(), Some(8.0.into()));
+
+            // The maximum depth is reached before the stack runs out, in debug builds too
+            evaluate_line(Span::new("fact(n) = \\{n \\le 1: 1, n \\cdot fact(n - 1)\\}"), &mut eval).unwrap();
+            let result = evaluate_line(Span::new("fact(100)"), &mut eval).unwrap().unwrap();
+            assert!((result.as_scalar().unwrap() / 9.33262154439441e157 - 1.0).abs() < 1e-9);
+
+            // Runaway recursion stops before the stack overflows, and doesn't affect later lines either
+            evaluate_line(Span::new("g(x) = g(x) + 1"), &mut eval).unwrap();
+            let error = evaluate_line(Span::new("g(1)"), &mut eval).unwrap_err();
+            assert!(matches!(error, Error::EvalError(message) if message.contains("maximum recursion depth of 100")));
+            assert_eq!(evaluate_line(Span::new("c = b + 1"), &mut eval).unwrap(), Some(5.0.into()));
+
+            let mut shallow_eval = Evaluator::<Float>::with_max_depth(3);
+            evaluate_line(Span::new("g(x) = g(x) + 1"), &mut shallow_eval).unwrap();
+            let error = evaluate_line(Span::new("g(1)"), &mut shallow_eval).unwrap_err();
+            assert!(matches!(error, Error::EvalError(message) if message == "Function 'g' exceeded the maximum recursion depth of 3"));
+            evaluate_line(Span::new("f(n) = 1 + \\sum_{i=1}^{n-1} f(i)"), &mut shallow_eval).unwrap();
+            assert_eq!(evaluate_line(Span::new("f(3)"), &mut shallow_eval).unwrap(), Some(4.0.into()));
+            evaluate_line(Span::new("f(4)"), &mut shallow_eval).unwrap_err();
+        }).unwrap();
+    }
 }
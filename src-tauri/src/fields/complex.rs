@@ -1,16 +1,99 @@
+use crate::calculus;
 use crate::error;
-use crate::types::{CResult, BaseField};
+use crate::fields::unit_value::round_significant;
+use crate::fields::units::{unit_map, Quantity};
+use crate::fields::{Unit, UnitRegistry, UnitSystem};
+use crate::types::{self, CResult, BaseField};
 use std::ops::{Add, Sub, Mul, Div};
 use std::convert::{TryFrom, From};
 use serde::Serialize;
 use num_complex::Complex as NumComplex;
 
 
+/// Imaginary step of complex-step derivatives, relative to the value. It can be tiny, as nothing is subtracted
+const COMPLEX_STEP: f64 = 1e-20;
+/// How closely a complex-step derivative has to agree with a central difference to be used
+const COMPLEX_STEP_TOLERANCE: f64 = 1e-6;
+
+
+/// How complex values are written out
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ComplexForm {
+    /// `(3 + 4i) Ω`
+    #[default]
+    Rectangular,
+    /// `5 Ω ∠ 53.13°`, with the angle in the unit system's angle unit
+    Polar,
+    /// `5 e^(0.927295218002i) Ω`, with the angle in radians
+    Exponential,
+}
+
+impl ComplexForm {
+    pub fn named(name: &str) -> CResult<Self> {
+        match name {
+            "rectangular" => Ok(ComplexForm::Rectangular),
+            "polar" => Ok(ComplexForm::Polar),
+            "exponential" => Ok(ComplexForm::Exponential),
+            _ => Err(error::Error::EvalError(format!("Unknown complex number form '{name}'. Expected 'rectangular', 'polar' or 'exponential'"))),
+        }
+    }
+
+    /// Writes `value` in this form followed by `unit`, if there is one
+    pub fn format(&self, value: NumComplex<f64>, unit: &str, system: &UnitSystem) -> String {
+        let with_unit = |value: String| if unit.is_empty() { value } else { format!("{value} {unit}") };
+        let magnitude = round_significant(value.norm(), 12);
+        match self {
+            ComplexForm::Rectangular => with_unit(format_rectangular(value, !unit.is_empty())),
+            ComplexForm::Polar => {
+                let angle_unit = system.base_unit(&Quantity::angle()).unwrap_or("rad");
+                let angle = round_significant(value.arg() / unit_map()[angle_unit].si_scale, 12);
+                let angle = if angle_unit == "deg" { format!("{angle}°") } else { format!("{angle} {angle_unit}") };
+                format!("{} ∠ {angle}", with_unit(magnitude.to_string()))
+            },
+            ComplexForm::Exponential => {
+                let angle = round_significant(value.arg(), 12);
+                if angle == 0.0 {
+                    with_unit(magnitude.to_string())
+                } else {
+                    with_unit(format!("{magnitude} e^({angle}i)"))
+                }
+            },
+        }
+    }
+}
+
+/// Writes `value` as "a + bi", in parentheses if it is followed by a unit and has both parts
+fn format_rectangular(value: NumComplex<f64>, has_unit: bool) -> String {
+    // Parts that are only rounding errors relative to the other, e.g. in e^{i\pi}, are dropped
+    let negligible = |part: f64| if part.abs() < value.norm() * 1e-12 { 0.0 } else { round_significant(part, 12) };
+    let (re, im) = (negligible(value.re), negligible(value.im));
+    if im == 0.0 {
+        return re.to_string();
+    }
+    let imaginary = if im.abs() == 1.0 { "i".to_string() } else { format!("{}i", im.abs()) };
+    if re == 0.0 {
+        return format!("{}{imaginary}", if im < 0.0 { "-" } else { "" });
+    }
+    let sign = if im < 0.0 { '-' } else { '+' };
+    if has_unit {
+        format!("({re} {sign} {imaginary})")
+    } else {
+        format!("{re} {sign} {imaginary}")
+    }
+}
+
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Complex {
     value: NumComplex<f64>,
 }
 
+impl Complex {
+    pub fn to_string_in(&self, system: &UnitSystem, form: ComplexForm) -> String {
+        form.format(self.value, "", system)
+    }
+}
+
 impl<'a> BaseField<'a> for Complex {
     fn as_scalar(&self) -> CResult<f64> {
         if self.value.im == 0.0 {
@@ -44,11 +127,118 @@ impl<'a> BaseField<'a> for Complex {
     fn tan(&self) -> CResult<Self> {
         Ok(Complex { value: self.value.tan() })
     }
+
+    fn asin(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.asin() })
+    }
+
+    fn acos(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.acos() })
+    }
+
+    fn atan(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.atan() })
+    }
+
+    fn abs(&self) -> CResult<Self> {
+        Ok(Complex::from(self.value.norm()))
+    }
+
+    fn sinh(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.sinh() })
+    }
+
+    fn cosh(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.cosh() })
+    }
+
+    fn tanh(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.tanh() })
+    }
+
+    fn asinh(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.asinh() })
+    }
+
+    fn acosh(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.acosh() })
+    }
+
+    fn atanh(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.atanh() })
+    }
+
+    /// The principal value, e.g. ln(-1) = iπ
+    fn ln(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.ln() })
+    }
+
+    fn exp(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.exp() })
+    }
+
+    fn log(&self, base: &Self) -> CResult<Self> {
+        if self.value.im == 0.0 && self.value.re > 0.0 && base.value.im == 0.0 {
+            return Ok(Complex::from(types::log(self.value.re, base.value.re)));
+        }
+        Ok(Complex { value: self.value.ln() / base.value.ln() })
+    }
+
+    fn re(&self) -> CResult<Self> {
+        Ok(Complex::from(self.value.re))
+    }
+
+    fn im(&self) -> CResult<Self> {
+        Ok(Complex::from(self.value.im))
+    }
+
+    fn conj(&self) -> CResult<Self> {
+        Ok(Complex { value: self.value.conj() })
+    }
+
+    fn arg(&self) -> CResult<Self> {
+        Ok(Complex::from(self.value.arg()))
+    }
+
+    /// Functions that are real along the real line are differentiated with a complex step, `Im(f(x + ih)) / h`, which
+    /// has no cancellation error. That's only valid for analytic functions, so others (e.g. `abs`) are caught by
+    /// checking against a central difference, and use central differences instead
+    fn derivative(&self, f: impl Fn(Self) -> CResult<Self>) -> CResult<Self> {
+        if self.value.im == 0.0 {
+            let x = self.value.re;
+            let value = f(self.clone())?.value;
+            let step = COMPLEX_STEP * x.abs().max(1.0);
+            let slope = f(Complex { value: NumComplex::new(x, step) })?.value.im / step;
+            let h = calculus::derivative_step(x) / 100.0;
+            let central = (f(Complex::from(x + h))?.value - f(Complex::from(x - h))?.value) / (2.0 * h);
+            let tolerance = COMPLEX_STEP_TOLERANCE * slope.abs().max(central.norm()) + 10.0 * f64::EPSILON * value.norm() / h;
+            if value.im == 0.0 && (central - slope).norm() <= tolerance {
+                return Ok(Complex::from(slope));
+            }
+        }
+        calculus::derivative(self, f)
+    }
+
+    fn convert(&self, unit: &str, _units: &UnitRegistry) -> CResult<Self> {
+        Err(error::Error::UnitError(format!("Cannot convert to '{unit}' without units")))
+    }
+
+    fn define_unit(&self, name: &str) -> CResult<Unit> {
+        Err(error::Error::UnitError(format!("Cannot define unit '{name}' without units")))
+    }
+
+    fn unit_identity(unit: &str, _units: &UnitRegistry) -> CResult<Self> {
+        Err(error::Error::UnitError(format!("Cannot use unit '{unit}' without units")))
+    }
+
+    fn plus_minus(&self, _uncertainty: Self) -> CResult<Self> {
+        Err(error::Error::EvalError("Uncertainties (±) can only be used in uncertain mode".to_string()))
+    }
 }
 
 impl std::fmt::Display for Complex {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.value)
+        write!(f, "{}", self.to_string_in(&UnitSystem::default(), ComplexForm::default()))
     }
 }
 
@@ -87,18 +277,18 @@ impl Sub for Complex {
 }
 
 impl Mul for Complex {
-    type Output = Self;
+    type Output = CResult<Self>;
 
-    fn mul(self, other: Self) -> Self {
-        Complex { value: self.value * other.value }
+    fn mul(self, other: Self) -> CResult<Self> {
+        Ok(Complex { value: self.value * other.value })
     }
 }
 
 impl Div for Complex {
-    type Output = Self;
+    type Output = CResult<Self>;
 
-    fn div(self, other: Self) -> Self {
-        Complex { value: self.value / other.value }
+    fn div(self, other: Self) -> CResult<Self> {
+        Ok(Complex { value: self.value / other.value })
     }
 }
 
@@ -107,3 +297,101 @@ impl Serialize for Complex {
         serializer.serialize_str(&self.value.to_string())
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::Evaluator;
+    use crate::types::Span;
+    use crate::evaluate_line;
+
+    fn evaluate_in(input: &str, form: ComplexForm) -> String {
+        let result = evaluate_line(Span::new(input), &mut Evaluator::<Complex>::new());
+        result.unwrap().unwrap().to_string_in(&UnitSystem::default().prefer("deg").unwrap(), form)
+    }
+
+    fn evaluate(input: &str) -> String {
+        evaluate_in(input, ComplexForm::Rectangular)
+    }
+
+    #[test]
+    fn test_literals() {
+        let tests = vec![
+            ("3i", "3i"),
+            ("2.5j + 1", "1 + 2.5i"),
+            ("3i^2", "-3"),
+            ("5∠90°", "5i"),
+            ("2∠60^{\\circ}", "1 + 1.73205080757i"),
+            ("\\sqrt{2} \\angle \\frac{\\pi}{4}", "1 + i"),
+            ("5 e^{i\\pi/4}", "3.53553390593 + 3.53553390593i"),
+            ("e^{i\\pi}", "-1"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(evaluate(input), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_shadowed_imaginary_unit() {
+        let tests = vec![
+            ("\\sum_{j=1}^{3} j", "6"),
+            ("\\sum_{i=1}^{3} i", "6"),
+            ("\\sum_{i=1}^{2} 3i", "6i"),
+            ("\\sum_{i=1}^{2} 1∠90°", "2i"),
+            ("\\prod_{j=1}^{2} 2j", "-4"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(evaluate(input), expected, "{input}");
+        }
+
+        let mut evaluator = Evaluator::<Complex>::new();
+        let lines = ["j = 5", "3i + j", "i j"];
+        let results: Vec<_> = lines.iter().map(|line| evaluate_line(Span::new(line), &mut evaluator).unwrap()).collect();
+        assert_eq!(results[1].as_ref().unwrap().to_string(), "5 + 3i");
+        assert_eq!(results[2].as_ref().unwrap().to_string(), "5i");
+        assert!(evaluate_line(Span::new("j"), &mut Evaluator::<Complex>::new()).is_err());
+        // Defining i would change e.g. e^{i\pi} on every other line of the sheet
+        assert!(evaluate_line(Span::new("i = 2"), &mut evaluator).is_err());
+        assert_eq!(evaluate_line(Span::new("i"), &mut evaluator).unwrap().unwrap().to_string(), "i");
+        assert_eq!(evaluate_line(Span::new("n = \\sum_{i=1}^{3} i"), &mut evaluator).unwrap().unwrap().to_string(), "6");
+    }
+
+    #[test]
+    fn test_functions() {
+        let tests = vec![
+            ("re(3 + 4i)", "3"),
+            ("im(3 + 4i)", "4"),
+            ("abs(3 - 4i)", "5"),
+            ("arg(0 - 2)", "3.14159265359"),
+            ("\\arg\\left(1 + i\\right)", "0.785398163397"),
+            ("conj(3 + 4i)", "3 - 4i"),
+            ("(1 + 2i) conj(1 + 2i)", "5"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(evaluate(input), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_derivatives() {
+        let mut eval = Evaluator::<Complex>::new();
+        evaluate_line(Span::new("x = 2"), &mut eval).unwrap();
+        let mut derivative = |input: &str| evaluate_line(Span::new(input), &mut eval).unwrap().unwrap().value;
+        // Complex steps are accurate to machine precision for analytic functions
+        assert_eq!(derivative("\\frac{d}{dx} \\ln(x)"), NumComplex::from(0.5));
+        assert!((derivative("\\frac{d}{dx} e^x") - 2.0_f64.exp()).norm() < 1e-14);
+        // Other functions use central differences
+        assert!((derivative("\\frac{d}{dx} abs(x)") - 1.0).norm() < 1e-9);
+        assert!((derivative("\\frac{d}{dx} (i x)^2") + 4.0).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_forms() {
+        assert_eq!(evaluate_in("3 + 4i", ComplexForm::Polar), "5 ∠ 53.1301023542°");
+        assert_eq!(evaluate_in("0 - 2i", ComplexForm::Polar), "2 ∠ -90°");
+        assert_eq!(evaluate_in("3 + 4i", ComplexForm::Exponential), "5 e^(0.927295218002i)");
+        assert_eq!(evaluate_in("0 - 2", ComplexForm::Exponential), "2 e^(3.14159265359i)");
+        assert_eq!(evaluate_in("2", ComplexForm::Exponential), "2");
+    }
+}
@@ -0,0 +1,365 @@
+use crate::error::Error;
+use crate::fields::units::*;
+use crate::fields::{ComplexForm, UnitVal};
+use crate::types::{self, BaseField, CResult};
+
+use num_complex::Complex as NumComplex;
+use serde::Serialize;
+
+
+/// A complex value with units, e.g. an impedance of `(3 + 4i) Ω` or a phasor in volts.
+///
+/// Absolute temperatures (e.g. `20 °C`) aren't supported, since they have no meaning as complex numbers
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComplexUnitVal {
+    /// The value in SI units
+    pub value: NumComplex<f64>,
+    pub quantity: Quantity,
+    /// Unit the value was explicitly converted to, if any
+    pub display_unit: Option<Unit>,
+}
+
+impl ComplexUnitVal {
+    pub fn new(value: NumComplex<f64>, quantity: Quantity) -> Self {
+        ComplexUnitVal { value, quantity, display_unit: None }
+    }
+
+    pub fn scalar(value: NumComplex<f64>) -> Self {
+        ComplexUnitVal::new(value, Quantity::unitless())
+    }
+
+    fn from_unit(unit: &Unit) -> CResult<Self> {
+        if unit.is_affine() {
+            return Err(Error::UnitError(format!("'{}' cannot be used with complex numbers, as it is an absolute temperature", unit.name)));
+        }
+        Ok(ComplexUnitVal::new(NumComplex::new(unit.si_scale, 0.0), unit.quantity.clone()))
+    }
+
+    pub fn is_scalar(&self) -> bool {
+        self.quantity == Quantity::unitless()
+    }
+
+    /// The value in radians. Unitless values are treated as radians too
+    fn as_angle(&self) -> CResult<NumComplex<f64>> {
+        if self.is_scalar() || self.quantity == Quantity::angle() {
+            Ok(self.value)
+        } else {
+            Err(Error::UnitError(format!("Expected an angle, but got {self}")))
+        }
+    }
+
+    fn check_scalar(&self, operation: &str) -> CResult<NumComplex<f64>> {
+        if self.is_scalar() {
+            Ok(self.value)
+        } else {
+            Err(Error::UnitError(format!("Cannot {operation} {self}, as it has units")))
+        }
+    }
+
+    /// Formats the value using the units of the given system, unless it was explicitly converted.
+    /// The unit (and its prefix) is chosen based on the magnitude of the value
+    pub fn to_string_in(&self, system: &UnitSystem, form: ComplexForm) -> String {
+        let magnitude = match self.value.norm() {
+            norm if norm == 0.0 || !norm.is_finite() => 1.0,
+            norm => norm,
+        };
+        let (_, scale, unit) = UnitVal { value: magnitude, display_unit: self.display_unit.clone(), ..UnitVal::new(0.0, self.quantity.clone()) }.display_parts(system);
+        form.format(self.value / scale, &unit, system)
+    }
+}
+
+
+impl std::fmt::Display for ComplexUnitVal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_string_in(&UnitSystem::default(), ComplexForm::default()))
+    }
+}
+
+impl<'a> BaseField<'a> for ComplexUnitVal {
+    fn as_scalar(&self) -> CResult<f64> {
+        let value = self.check_scalar("convert to a real number")?;
+        if value.im != 0.0 {
+            return Err(Error::EvalError(format!("{self} is not a real number")));
+        }
+        Ok(value.re)
+    }
+
+    fn powf(&self, exp: Self) -> CResult<Self> {
+        let exp_value = exp.check_scalar("raise to the power of")?;
+        if self.is_scalar() {
+            return Ok(ComplexUnitVal::scalar(self.value.powc(exp_value)));
+        }
+        let exp = exp.as_scalar().map_err(|_| Error::UnitError(format!("Cannot raise {self} to a complex power")))?;
+        if exp.fract() == 0.0 {
+            Ok(ComplexUnitVal::new(self.value.powi(exp as i32), self.quantity.powi(exp as i32)))
+        } else if let Some(rational_exp) = to_rational(exp) {
+            Ok(ComplexUnitVal::new(self.value.powf(exp), self.quantity.pow(rational_exp)))
+        } else {
+            Err(Error::UnitError(format!("Cannot raise {self} to the power of {exp}")))
+        }
+    }
+
+    fn root(&self, n: Self) -> CResult<Self> {
+        let n = n.as_scalar()?;
+        if self.is_scalar() {
+            return Ok(ComplexUnitVal::scalar(self.value.powf(1.0 / n)));
+        }
+        if n.fract() != 0.0 || n == 0.0 {
+            return Err(Error::UnitError(format!("Cannot take the {n}th root of {self}")));
+        }
+        Ok(ComplexUnitVal::new(self.value.powf(1.0 / n), self.quantity.root(n as i32)))
+    }
+
+    fn fract(&self) -> CResult<f64> {
+        Ok(self.as_scalar()?.fract())
+    }
+
+    fn sin(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::scalar(self.as_angle()?.sin()))
+    }
+
+    fn cos(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::scalar(self.as_angle()?.cos()))
+    }
+
+    fn tan(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::scalar(self.as_angle()?.tan()))
+    }
+
+    fn asin(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::new(self.check_scalar("take the asin of")?.asin(), Quantity::angle()))
+    }
+
+    fn acos(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::new(self.check_scalar("take the acos of")?.acos(), Quantity::angle()))
+    }
+
+    fn atan(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::new(self.check_scalar("take the atan of")?.atan(), Quantity::angle()))
+    }
+
+    fn abs(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::new(NumComplex::from(self.value.norm()), self.quantity.clone()))
+    }
+
+    fn sinh(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::scalar(self.check_scalar("take the sinh of")?.sinh()))
+    }
+
+    fn cosh(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::scalar(self.check_scalar("take the cosh of")?.cosh()))
+    }
+
+    fn tanh(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::scalar(self.check_scalar("take the tanh of")?.tanh()))
+    }
+
+    fn asinh(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::scalar(self.check_scalar("take the asinh of")?.asinh()))
+    }
+
+    fn acosh(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::scalar(self.check_scalar("take the acosh of")?.acosh()))
+    }
+
+    fn atanh(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::scalar(self.check_scalar("take the atanh of")?.atanh()))
+    }
+
+    fn ln(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::scalar(self.check_scalar("take the logarithm of")?.ln()))
+    }
+
+    fn exp(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::scalar(self.check_scalar("take the exponential of")?.exp()))
+    }
+
+    fn log(&self, base: &Self) -> CResult<Self> {
+        let (value, base) = (self.check_scalar("take the logarithm of")?, base.check_scalar("take a logarithm to the base")?);
+        if value.im == 0.0 && value.re > 0.0 && base.im == 0.0 {
+            return Ok(ComplexUnitVal::scalar(NumComplex::from(types::log(value.re, base.re))));
+        }
+        Ok(ComplexUnitVal::scalar(value.ln() / base.ln()))
+    }
+
+    fn re(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::new(NumComplex::from(self.value.re), self.quantity.clone()))
+    }
+
+    fn im(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::new(NumComplex::from(self.value.im), self.quantity.clone()))
+    }
+
+    fn conj(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::new(self.value.conj(), self.quantity.clone()))
+    }
+
+    fn arg(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::new(NumComplex::from(self.value.arg()), Quantity::angle()))
+    }
+
+    fn unit(&self) -> CResult<Self> {
+        Ok(ComplexUnitVal::new(NumComplex::from(1.0), self.quantity.clone()))
+    }
+
+    fn magnitude(&self) -> CResult<f64> {
+        Ok(self.value.norm())
+    }
+
+    /// Complex numbers aren't ordered, so only real values can be compared
+    fn compare(&self, other: &Self) -> CResult<Option<std::cmp::Ordering>> {
+        let is_zero = |value: &Self| value.is_scalar() && value.value == NumComplex::from(0.0);
+        if self.quantity != other.quantity && !is_zero(self) && !is_zero(other) {
+            return Err(Error::UnitError(format!("Cannot compare {self} with {other}, as they have different units")));
+        }
+        if self.value.im != 0.0 || other.value.im != 0.0 {
+            return Err(Error::EvalError(format!("Cannot compare {self} with {other}, as complex numbers aren't ordered")));
+        }
+        Ok(self.value.re.partial_cmp(&other.value.re))
+    }
+
+    fn convert(&self, unit: &str, units: &UnitRegistry) -> CResult<Self> {
+        let unit = UnitVal::from_unit_str_in(unit, units)?;
+        if unit.quantity != self.quantity {
+            return Err(Error::UnitError(format!("Cannot convert {} to '{}', the quantities don't match", self, unit.name)));
+        }
+        ComplexUnitVal::from_unit(&unit)?;
+        Ok(ComplexUnitVal { value: self.value, quantity: self.quantity.clone(), display_unit: Some(unit) })
+    }
+
+    fn define_unit(&self, name: &str) -> CResult<Unit> {
+        if self.value.im != 0.0 {
+            return Err(Error::UnitError(format!("Unit '{name}' cannot be defined as a complex number ({self})")));
+        }
+        UnitVal::new(self.value.re, self.quantity.clone()).define_unit(name)
+    }
+
+    fn unit_identity(unit: &str, units: &UnitRegistry) -> CResult<Self> {
+        ComplexUnitVal::from_unit(&UnitVal::from_unit_str_in(unit, units)?)
+    }
+
+    fn plus_minus(&self, _uncertainty: Self) -> CResult<Self> {
+        Err(Error::EvalError("Uncertainties (±) can only be used in uncertain mode".to_string()))
+    }
+}
+
+impl<'a> TryFrom<&'a str> for ComplexUnitVal {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        if value == "i" {
+            return Ok(ComplexUnitVal::scalar(NumComplex::i()));
+        }
+        Ok(ComplexUnitVal::from_unit(&UnitVal::from_unit_str(value)?)?)
+    }
+}
+
+impl From<f64> for ComplexUnitVal {
+    fn from(value: f64) -> Self {
+        ComplexUnitVal::scalar(NumComplex::new(value, 0.0))
+    }
+}
+
+impl std::ops::Add for ComplexUnitVal {
+    type Output = CResult<Self>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        if self.quantity != rhs.quantity {
+            return Err(Error::UnitError(format!("Cannot add units with different quantities: {:?} and {:?}", self.to_string(), rhs.to_string())));
+        }
+        Ok(ComplexUnitVal::new(self.value + rhs.value, self.quantity))
+    }
+}
+
+impl std::ops::Sub for ComplexUnitVal {
+    type Output = CResult<Self>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        if self.quantity != rhs.quantity {
+            return Err(Error::UnitError(format!("Cannot subtract units with different quantities: {:?} and {:?}", self.to_string(), rhs.to_string())));
+        }
+        Ok(ComplexUnitVal::new(self.value - rhs.value, self.quantity))
+    }
+}
+
+impl std::ops::Mul for ComplexUnitVal {
+    type Output = CResult<Self>;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Ok(ComplexUnitVal::new(self.value * rhs.value, self.quantity + rhs.quantity))
+    }
+}
+
+impl std::ops::Div for ComplexUnitVal {
+    type Output = CResult<Self>;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        Ok(ComplexUnitVal::new(self.value / rhs.value, self.quantity - rhs.quantity))
+    }
+}
+
+impl Serialize for ComplexUnitVal {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::Evaluator;
+    use crate::types::Span;
+    use crate::evaluate_line;
+
+    fn evaluate_in(input: &str, system: &UnitSystem, form: ComplexForm) -> String {
+        let result = evaluate_line(Span::new(input), &mut Evaluator::<ComplexUnitVal>::new());
+        result.unwrap().unwrap().to_string_in(system, form)
+    }
+
+    fn evaluate(input: &str) -> String {
+        evaluate_in(input, &UnitSystem::default(), ComplexForm::Rectangular)
+    }
+
+    #[test]
+    fn test_impedances() {
+        let tests = vec![
+            ("(3 + 4i) Ω", "(3 + 4i) Ω"),
+            ("3 Ω + (4i) Ω", "(3 + 4i) Ω"),
+            ("(3 - 4i) kΩ", "(3 - 4i) kΩ"),
+            ("(2i) V", "2i V"),
+            ("(1 + i) V / (1 - i) A", "i Ω"),
+            ("(2 + 2i) A * 5 Ω", "(10 + 10i) V"),
+            ("(3 + 4i)^2", "-7 + 24i"),
+            ("((3 + 4i) m)^2", "(-7 + 24i) m^2"),
+            ("(3000 + 4000i) Ω to kΩ", "(3 + 4i) kΩ"),
+            ("i * i", "-1"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(evaluate(input), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_polar() {
+        let si = UnitSystem::default();
+        let degrees = UnitSystem::default().prefer("deg").unwrap();
+        assert_eq!(evaluate_in("(3 + 4i) Ω", &si, ComplexForm::Polar), "5 Ω ∠ 0.927295218002 rad");
+        assert_eq!(evaluate_in("(0 - 2i) kV", &degrees, ComplexForm::Polar), "2 kV ∠ -90°");
+        assert_eq!(evaluate_in("1 + i", &degrees, ComplexForm::Polar), "1.41421356237 ∠ 45°");
+        assert_eq!(evaluate_in("10 V∠30°", &degrees, ComplexForm::Polar), "10 V ∠ 30°");
+        assert_eq!(evaluate_in("(3 + 4j) Ω", &si, ComplexForm::Exponential), "5 e^(0.927295218002i) Ω");
+        assert_eq!(evaluate_in("abs((3 + 4i) kΩ)", &si, ComplexForm::Rectangular), "5 kΩ");
+        assert_eq!(evaluate_in("arg(4i Ω)", &degrees, ComplexForm::Rectangular), "90 deg");
+    }
+
+    #[test]
+    fn test_invalid() {
+        for input in ["(3 + 4i) Ω + 1 V", "20 °C * i", "2^(1 m)", "\\sin(i m)", "((1 + i) m)^i"] {
+            assert!(evaluate_line(Span::new(input), &mut Evaluator::<ComplexUnitVal>::new()).is_err(), "{input} should be invalid");
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use crate::error::Error;
+use crate::fields::{Unit, UnitRegistry};
+use crate::types::{BaseField, CResult};
+
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use serde::Serialize;
+use std::str::FromStr;
+
+
+/// Exact powers whose result would need more bits than this are approximated instead
+const MAX_EXACT_BITS: u64 = 1 << 16;
+/// Rational exponents with a larger denominator are approximated instead
+const MAX_EXACT_ROOT: u32 = 1000;
+
+
+/// A number that is kept as an exact fraction for as long as possible (e.g. `0.1 + 0.2 = 3/10`).
+///
+/// Results that can't be represented exactly, such as `\sqrt{2}`, fall back to a decimal with
+/// `MAX_PRECISION` significant digits. Trig functions and irrational exponents are evaluated
+/// with `f64`, so they are only accurate to about 15 digits
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exact {
+    Rational(BigRational),
+    Decimal(BigDecimal),
+}
+
+impl Exact {
+    /// Significant digits kept by decimal approximations
+    pub const MAX_PRECISION: u64 = 100;
+    /// Significant digits shown for decimal approximations, unless another precision is requested
+    pub const DEFAULT_PRECISION: u64 = 20;
+
+    fn decimal(value: BigDecimal) -> Self {
+        Exact::Decimal(value.with_prec(Exact::MAX_PRECISION))
+    }
+
+    /// Converts a result that was computed with `f64`, which must be finite
+    fn approximate(value: f64, operation: &str) -> CResult<Self> {
+        if !value.is_finite() {
+            return Err(Error::EvalError(format!("The result of {operation} is not a real number")));
+        }
+        Ok(Exact::from(value))
+    }
+
+    fn to_decimal(&self) -> BigDecimal {
+        match self {
+            Exact::Rational(value) => BigDecimal::from(value.numer().clone()) / BigDecimal::from(value.denom().clone()),
+            Exact::Decimal(value) => value.clone(),
+        }
+    }
+
+    fn to_f64(&self) -> f64 {
+        match self {
+            Exact::Rational(value) => value.to_f64(),
+            Exact::Decimal(value) => value.to_f64(),
+        }.unwrap_or(f64::NAN)
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Exact::Rational(value) => value.is_zero(),
+            Exact::Decimal(value) => value.is_zero(),
+        }
+    }
+
+    /// Applies `rational_op` if both values are exact and `decimal_op` otherwise
+    fn combine(
+        self,
+        rhs: Self,
+        rational_op: impl Fn(BigRational, BigRational) -> BigRational,
+        decimal_op: impl Fn(BigDecimal, BigDecimal) -> BigDecimal,
+    ) -> Self {
+        match (self, rhs) {
+            (Exact::Rational(a), Exact::Rational(b)) => Exact::Rational(rational_op(a, b)),
+            (a, b) => Exact::decimal(decimal_op(a.to_decimal(), b.to_decimal())),
+        }
+    }
+
+    /// Shows fractions whose denominator is a power of ten as decimals (e.g. "0.3"), other fractions
+    /// as "n/d" (e.g. "1/2"), and approximations rounded to `precision` significant digits
+    pub fn to_string_with(&self, precision: u64) -> String {
+        match self {
+            Exact::Rational(value) if value.is_integer() => value.numer().to_string(),
+            Exact::Rational(value) => match decimal_fraction(value) {
+                Some(decimal) => decimal.to_plain_string(),
+                None => format!("{}/{}", value.numer(), value.denom()),
+            },
+            Exact::Decimal(value) => {
+                let value = value.with_prec(precision.clamp(1, Exact::MAX_PRECISION)).normalized();
+                if (-7..21).contains(&value.order_of_magnitude()) {
+                    value.to_plain_string()
+                } else {
+                    value.to_scientific_notation()
+                }
+            },
+        }
+    }
+}
+
+/// `value` as a decimal, if its denominator is a power of ten (e.g. 3/10 but not 1/2)
+fn decimal_fraction(value: &BigRational) -> Option<BigDecimal> {
+    let places = value.denom().to_string().len() as u32 - 1;
+    if places as u64 > Exact::MAX_PRECISION || *value.denom() != BigInt::from(10).pow(places) {
+        return None;
+    }
+    Some(BigDecimal::new(value.numer().clone(), places as i64))
+}
+
+/// `base^exp` if the result is rational and small enough to compute exactly
+fn exact_pow(base: &BigRational, exp: &BigRational) -> Option<BigRational> {
+    let (p, q) = (exp.numer().to_i32()?, exp.denom().to_u32()?);
+    let bits = u64::max(base.numer().bits(), base.denom().bits());
+    if q > MAX_EXACT_ROOT || bits * p.unsigned_abs() as u64 / q as u64 > MAX_EXACT_BITS {
+        return None;
+    }
+    let exact_root = |n: &BigInt| Some(n.nth_root(q)).filter(|root| root.pow(q) == *n);
+    let root = BigRational::new(exact_root(base.numer())?, exact_root(base.denom())?);
+    Some(root.pow(p))
+}
+
+/// The positive `n`th root of a positive `x`, found with Newton's method
+fn nth_root(x: &BigDecimal, n: u32) -> BigDecimal {
+    if x.is_zero() {
+        return x.clone();
+    }
+    // Start from the f64 root of the leading digits, as `x` can be out of range of an f64
+    let magnitude = x.order_of_magnitude();
+    let leading = (x * BigDecimal::new(BigInt::one(), magnitude)).to_f64().unwrap_or(1.0);
+    let (whole, rest) = (magnitude.div_euclid(n as i64), magnitude.rem_euclid(n as i64));
+    let guess = (leading * 10.0_f64.powi(rest as i32)).powf(1.0 / n as f64);
+    let mut root = BigDecimal::try_from(guess).unwrap_or(BigDecimal::one()) * BigDecimal::new(BigInt::one(), -whole);
+
+    let n_decimal = BigDecimal::from(n);
+    for _ in 0..50 {
+        let next: BigDecimal = ((&n_decimal - 1) * &root + x / root.powi(n as i64 - 1)) / &n_decimal;
+        let next = next.with_prec(Exact::MAX_PRECISION + 10);
+        if next == root {
+            break;
+        }
+        root = next;
+    }
+    root
+}
+
+
+impl std::fmt::Display for Exact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_string_with(Exact::DEFAULT_PRECISION))
+    }
+}
+
+impl<'a> BaseField<'a> for Exact {
+    fn as_scalar(&self) -> CResult<f64> {
+        Ok(self.to_f64())
+    }
+
+    fn powf(&self, exp: Self) -> CResult<Self> {
+        if self.is_zero() && exp.to_f64() < 0.0 {
+            return Err(Error::EvalError(format!("Cannot raise 0 to a negative power ({exp})")));
+        }
+        let negative = self.to_f64() < 0.0;
+        if let Exact::Rational(rational_exp) = &exp {
+            if negative && rational_exp.denom() % 2 == BigInt::zero() {
+                return Err(Error::EvalError(format!("{self}^({exp}) is not a real number")));
+            }
+            if let Exact::Rational(base) = self {
+                if let Some(result) = exact_pow(base, rational_exp) {
+                    return Ok(Exact::Rational(result));
+                }
+            }
+            if let (Some(p), Some(q)) = (rational_exp.numer().to_i64(), rational_exp.denom().to_u32()) {
+                if q <= MAX_EXACT_ROOT {
+                    let root = nth_root(&self.to_decimal().abs(), q);
+                    let root = if negative { -root } else { root };
+                    return Ok(Exact::decimal(root.powi(p)));
+                }
+            }
+        }
+        Exact::approximate(self.to_f64().powf(exp.to_f64()), &format!("{self}^({exp})"))
+    }
+
+    fn root(&self, n: Self) -> CResult<Self> {
+        if n.is_zero() {
+            return Err(Error::EvalError("Cannot take the 0th root".to_string()));
+        }
+        match n {
+            Exact::Rational(n) => self.powf(Exact::Rational(n.recip())),
+            n => Exact::approximate(self.to_f64().powf(1.0 / n.to_f64()), &format!("the {n}th root of {self}")),
+        }
+    }
+
+    fn fract(&self) -> CResult<f64> {
+        match self {
+            Exact::Rational(value) => Ok(value.fract().to_f64().unwrap_or(0.0)),
+            Exact::Decimal(value) if value.is_integer() => Ok(0.0),
+            Exact::Decimal(value) => Ok(value.to_f64().unwrap_or(0.0).fract()),
+        }
+    }
+
+    /// Exact values can be any size, even if they are too large for `f64`
+    fn overflowed(&self) -> bool {
+        false
+    }
+
+    fn sin(&self) -> CResult<Self> {
+        Exact::approximate(self.to_f64().sin(), &format!("sin({self})"))
+    }
+
+    fn cos(&self) -> CResult<Self> {
+        Exact::approximate(self.to_f64().cos(), &format!("cos({self})"))
+    }
+
+    fn tan(&self) -> CResult<Self> {
+        Exact::approximate(self.to_f64().tan(), &format!("tan({self})"))
+    }
+
+    fn asin(&self) -> CResult<Self> {
+        Exact::approximate(self.to_f64().asin(), &format!("asin({self})"))
+    }
+
+    fn acos(&self) -> CResult<Self> {
+        Exact::approximate(self.to_f64().acos(), &format!("acos({self})"))
+    }
+
+    fn atan(&self) -> CResult<Self> {
+        Exact::approximate(self.to_f64().atan(), &format!("atan({self})"))
+    }
+
+    fn abs(&self) -> CResult<Self> {
+        Ok(match self {
+            Exact::Rational(value) => Exact::Rational(value.abs()),
+            Exact::Decimal(value) => Exact::Decimal(value.abs()),
+        })
+    }
+
+    fn map_real(&self, name: &str, f: impl Fn(f64) -> f64) -> CResult<Self> {
+        Exact::approximate(f(self.to_f64()), &format!("{name}({self})"))
+    }
+
+    fn floor(&self) -> CResult<Self> {
+        match self {
+            Exact::Rational(value) => Ok(Exact::Rational(value.floor())),
+            _ => self.map_real("floor", f64::floor),
+        }
+    }
+
+    fn ceil(&self) -> CResult<Self> {
+        match self {
+            Exact::Rational(value) => Ok(Exact::Rational(value.ceil())),
+            _ => self.map_real("ceil", f64::ceil),
+        }
+    }
+
+    fn round(&self) -> CResult<Self> {
+        match self {
+            Exact::Rational(value) => Ok(Exact::Rational(value.round())),
+            _ => self.map_real("round", f64::round),
+        }
+    }
+
+    fn sign(&self) -> CResult<Self> {
+        Ok(match self {
+            Exact::Rational(value) => Exact::Rational(value.signum()),
+            Exact::Decimal(value) if value.is_negative() => Exact::Rational(-BigRational::one()),
+            Exact::Decimal(value) if value.is_positive() => Exact::Rational(BigRational::one()),
+            Exact::Decimal(_) => Exact::Rational(BigRational::zero()),
+        })
+    }
+
+    fn convert(&self, unit: &str, _units: &UnitRegistry) -> CResult<Self> {
+        Err(Error::UnitError(format!("Cannot convert to '{unit}' without units")))
+    }
+
+    fn define_unit(&self, name: &str) -> CResult<Unit> {
+        Err(Error::UnitError(format!("Cannot define unit '{name}' without units")))
+    }
+
+    fn unit_identity(unit: &str, _units: &UnitRegistry) -> CResult<Self> {
+        Err(Error::UnitError(format!("Cannot use unit '{unit}' without units")))
+    }
+
+    fn plus_minus(&self, _uncertainty: Self) -> CResult<Self> {
+        Err(Error::EvalError("Uncertainties (±) can only be used in uncertain mode".to_string()))
+    }
+
+    fn from_literal(literal: &str) -> Self {
+        Exact::try_from(literal).expect("Number literals are valid decimals")
+    }
+}
+
+impl TryFrom<&str> for Exact {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let (digits, scale) = BigDecimal::from_str(s)?.into_bigint_and_exponent();
+        let power = BigInt::from(10).pow(scale.unsigned_abs() as u32);
+        if scale >= 0 {
+            Ok(Exact::Rational(BigRational::new(digits, power)))
+        } else {
+            Ok(Exact::Rational(BigRational::from_integer(digits * power)))
+        }
+    }
+}
+
+impl From<f64> for Exact {
+    /// Whole numbers are exact, anything else is assumed to be an approximation (e.g. `pi`)
+    fn from(value: f64) -> Self {
+        if value.fract() == 0.0 && value.abs() < 2.0_f64.powi(53) {
+            Exact::Rational(BigRational::from_integer(BigInt::from(value as i64)))
+        } else {
+            Exact::decimal(BigDecimal::from_str(&value.to_string()).expect("Exact values must be finite"))
+        }
+    }
+}
+
+impl std::ops::Add for Exact {
+    type Output = CResult<Self>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Ok(self.combine(rhs, |a, b| a + b, |a, b| a + b))
+    }
+}
+
+impl std::ops::Sub for Exact {
+    type Output = CResult<Self>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Ok(self.combine(rhs, |a, b| a - b, |a, b| a - b))
+    }
+}
+
+impl std::ops::Mul for Exact {
+    type Output = CResult<Self>;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Ok(self.combine(rhs, |a, b| a * b, |a, b| a * b))
+    }
+}
+
+impl std::ops::Div for Exact {
+    type Output = CResult<Self>;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        if rhs.is_zero() {
+            return Err(Error::EvalError(format!("Cannot divide {self} by 0")));
+        }
+        Ok(self.combine(rhs, |a, b| a / b, |a, b| a / b))
+    }
+}
+
+impl Serialize for Exact {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::Evaluator;
+    use crate::types::Span;
+    use crate::evaluate_line;
+
+    fn evaluate(input: &str) -> CResult<Exact> {
+        Ok(evaluate_line(Span::new(input), &mut Evaluator::<Exact>::new())?.unwrap())
+    }
+
+    #[test]
+    fn test_exact_arithmetic() {
+        let tests = vec![
+            ("0.1 + 0.2", "0.3"),
+            ("0.03 \\cdot 3", "0.09"),
+            ("1/3", "1/3"),
+            ("\\frac{1}{3}+\\frac{1}{6}", "1/2"),
+            ("2/6 - 1", "-2/3"),
+            ("1/1024", "1/1024"),
+            ("(2/3)^3", "8/27"),
+            ("(8/27)^(2/3)", "4/9"),
+            ("0.25^(1/2)", "1/2"),
+            ("(0-8)^(1/3)", "-2"),
+            ("\\sqrt{0.0625}", "1/4"),
+            ("2^100", "1267650600228229401496703205376"),
+            ("\\prod_{i=1}^{25} i", "15511210043330985984000000"),
+            ("\\sum_{i=1}^{4} \\frac{1}{i}", "25/12"),
+            ("25!", "15511210043330985984000000"),
+            ("nCr(60, 30)", "118264581564861424"),
+            ("round(-5/2) + floor(7/2)", "0"),
+            ("mod(-7/2, 3)", "5/2"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(evaluate(input).unwrap().to_string(), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_approximations() {
+        let tests = vec![
+            ("\\sqrt{2}", "1.4142135623730950488"),
+            ("2^(1/3)", "1.2599210498948731648"),
+            ("\\sqrt{2}^2", "2"),
+            ("\\sqrt{2}/1000000000", "1.4142135623730950488e-9"),
+            ("1/\\sqrt{3}+1/3", "0.91068360252295909784"),
+            ("\\sin(0)", "0"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(evaluate(input).unwrap().to_string(), expected, "{input}");
+        }
+        assert_eq!(evaluate("\\sqrt{2}").unwrap().to_string_with(5), "1.4142");
+        assert_eq!(evaluate("\\sqrt{2}").unwrap().to_string_with(50), "1.4142135623730950488016887242096980785696718753769");
+    }
+
+    #[test]
+    fn test_invalid() {
+        for input in ["1/0", "0^(-1)", "(0-4)^(1/2)", "\\sqrt{0-1}", "\\arcsin(2)"] {
+            assert!(evaluate(input).is_err(), "{input} should be invalid");
+        }
+    }
+}
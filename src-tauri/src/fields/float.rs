@@ -1,3 +1,5 @@
+use crate::error::Error;
+use crate::fields::{Unit, UnitRegistry};
 use crate::types::{CResult, BaseField};
 use std::ops::{Add, Sub, Mul, Div};
 use std::convert::{TryFrom, From};
@@ -38,6 +40,38 @@ impl<'a> BaseField<'a> for Float {
     fn tan(&self) -> CResult<Self> {
         Ok(Float { value: self.value.tan() })
     }
+
+    fn asin(&self) -> CResult<Self> {
+        Ok(Float { value: self.value.asin() })
+    }
+
+    fn acos(&self) -> CResult<Self> {
+        Ok(Float { value: self.value.acos() })
+    }
+
+    fn atan(&self) -> CResult<Self> {
+        Ok(Float { value: self.value.atan() })
+    }
+
+    fn abs(&self) -> CResult<Self> {
+        Ok(Float { value: self.value.abs() })
+    }
+
+    fn convert(&self, unit: &str, _units: &UnitRegistry) -> CResult<Self> {
+        Err(Error::UnitError(format!("Cannot convert to '{unit}' without units")))
+    }
+
+    fn define_unit(&self, name: &str) -> CResult<Unit> {
+        Err(Error::UnitError(format!("Cannot define unit '{name}' without units")))
+    }
+
+    fn unit_identity(unit: &str, _units: &UnitRegistry) -> CResult<Self> {
+        Err(Error::UnitError(format!("Cannot use unit '{unit}' without units")))
+    }
+
+    fn plus_minus(&self, _uncertainty: Self) -> CResult<Self> {
+        Err(Error::EvalError("Uncertainties (±) can only be used in uncertain mode".to_string()))
+    }
 }
 
 impl TryFrom<&str> for Float {
@@ -72,18 +106,18 @@ impl Sub for Float {
 }
 
 impl Mul for Float {
-    type Output = Self;
+    type Output = CResult<Self>;
 
-    fn mul(self, other: Self) -> Self {
-        Float { value: self.value * other.value }
+    fn mul(self, other: Self) -> CResult<Self> {
+        Ok(Float { value: self.value * other.value })
     }
 }
 
 impl Div for Float {
-    type Output = Self;
+    type Output = CResult<Self>;
 
-    fn div(self, other: Self) -> Self {
-        Float { value: self.value / other.value }
+    fn div(self, other: Self) -> CResult<Self> {
+        Ok(Float { value: self.value / other.value })
     }
 }
 
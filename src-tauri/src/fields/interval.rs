@@ -0,0 +1,584 @@
+use crate::error::Error;
+use crate::fields::{Exact, Unit, UnitRegistry};
+use crate::types::{self, BaseField, CResult};
+
+use num_rational::BigRational;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::f64::consts::{FRAC_PI_2, PI, TAU};
+
+
+/// Widest interval (in ulps) that is still treated as a single value, see `Interval::as_point`
+const POINT_ULPS: u64 = 4;
+
+/// Below this, the rounding errors of products and quotients (around 2^-104 times smaller) may not be representable
+const MIN_EXACT: f64 = f64::MIN_POSITIVE / (f64::EPSILON * f64::EPSILON);
+
+/// A closed interval `[lo, hi]` that is guaranteed to contain the exact result, e.g. `10 ± 0.1`.
+///
+/// Every inexact operation rounds its bounds outwards by one ulp, so floating point rounding can only
+/// make an interval wider
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interval {
+    pub lo: f64,
+    pub hi: f64,
+}
+
+impl Interval {
+    pub fn new(lo: f64, hi: f64) -> Self {
+        Interval { lo: f64::min(lo, hi), hi: f64::max(lo, hi) }
+    }
+
+    pub fn point(value: f64) -> Self {
+        Interval { lo: value, hi: value }
+    }
+
+    /// The smallest interval containing all of `values`, rounded outwards
+    fn hull(values: &[f64]) -> CResult<Self> {
+        if values.iter().any(|value| value.is_nan()) {
+            return Err(Error::EvalError("The result is not a real number".to_string()));
+        }
+        let lo = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let hi = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        Ok(Interval { lo: next_down(lo), hi: next_up(hi) })
+    }
+
+    /// Like `hull`, for results whose rounding error is known (`(rounded, exact - rounded)`, see `two_sum`).
+    /// Bounds are only rounded outwards on the side the exact result lies, so e.g. 1 + 2 stays the point 3
+    fn hull_exact(values: &[(f64, f64)]) -> CResult<Self> {
+        if values.iter().any(|(value, error)| !value.is_finite() || !error.is_finite()) {
+            return Interval::hull(&values.iter().map(|(value, _)| *value).collect::<Vec<_>>());
+        }
+        let lo = values.iter().map(|&(value, error)| if error < 0.0 { next_down(value) } else { value }).fold(f64::INFINITY, f64::min);
+        let hi = values.iter().map(|&(value, error)| if error > 0.0 { next_up(value) } else { value }).fold(f64::NEG_INFINITY, f64::max);
+        Ok(Interval { lo, hi })
+    }
+
+    fn is_point(&self) -> bool {
+        self.lo == self.hi
+    }
+
+    fn contains(&self, value: f64) -> bool {
+        self.lo <= value && value <= self.hi
+    }
+
+    /// Whether `offset + k * period` is in the interval for some integer k
+    fn contains_periodic(&self, offset: f64, period: f64) -> bool {
+        let k = ((self.lo - offset) / period).ceil();
+        offset + k * period <= self.hi
+    }
+
+    /// The single value of an interval that is at most a few ulps wide, which rounding can leave behind even when the
+    /// exact result is a single number (e.g. √2 · √2). Whole numbers are preferred, so that they can be used as indices
+    fn as_point(&self) -> Option<f64> {
+        if self.is_point() {
+            return Some(self.lo);
+        }
+        if !self.lo.is_finite() || !self.hi.is_finite() || ulps_between(self.lo, self.hi) > POINT_ULPS {
+            return None;
+        }
+        let mid = self.lo + (self.hi - self.lo) / 2.0;
+        Some(if self.contains(mid.round()) { mid.round() } else { mid })
+    }
+
+    fn as_integer(&self) -> Option<i32> {
+        self.as_point().filter(|n| n.fract() == 0.0 && n.abs() <= i32::MAX as f64).map(|n| n as i32)
+    }
+
+    /// The range of a function with period 2π, a maximum of 1 at `max_at` and a minimum of -1 at `max_at + π`
+    fn periodic_range(&self, f: impl Fn(f64) -> f64, max_at: f64) -> CResult<Self> {
+        if self.hi - self.lo >= TAU {
+            return Ok(Interval::new(-1.0, 1.0));
+        }
+        let mut range = Interval::hull(&[f(self.lo), f(self.hi)])?;
+        if self.contains_periodic(max_at, TAU) {
+            range.hi = 1.0;
+        }
+        if self.contains_periodic(max_at + PI, TAU) {
+            range.lo = -1.0;
+        }
+        Ok(Interval::new(range.lo.max(-1.0), range.hi.min(1.0)))
+    }
+}
+
+impl std::fmt::Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_point() {
+            return write!(f, "{}", self.lo);
+        }
+        // Bounds that overflowed, e.g. e^{1000}
+        if !self.lo.is_finite() || !self.hi.is_finite() {
+            return write!(f, "[{:e}, {:e}]", self.lo, self.hi);
+        }
+        // Intervals only widened by rounding show 12 significant figures, which also shows e.g. sin(0) as 0
+        if let Some(value) = self.as_point() {
+            let place = if value == 0.0 { 0.0 } else { value.abs().log10().floor() - 11.0 };
+            let decimals = (-place).max(0.0) as usize;
+            let value = format!("{:.*}", decimals, value);
+            return write!(f, "{}", if decimals > 0 { value.trim_end_matches('0').trim_end_matches('.') } else { &value });
+        }
+        // Show two significant figures of the width (and at most 12 of the bounds), rounding the bounds
+        // outwards. The small slack stops the ulps added by outward rounding from showing up as a digit.
+        // Steps are kept above the smallest normal number, as smaller powers of 10 round to 0
+        let magnitude = f64::max(self.lo.abs(), self.hi.abs());
+        let place = f64::max((self.hi - self.lo).log10().floor() - 1.0, magnitude.log10().floor() - 11.0).max(f64::MIN_10_EXP as f64);
+        let step = 10.0_f64.powf(place);
+        let decimals = (-place).max(0.0) as usize;
+        let lo = (self.lo / step + 0.01).floor() * step;
+        let hi = (self.hi / step - 0.01).ceil() * step;
+        let (lo, hi) = (format!("{:.*}", decimals, lo), format!("{:.*}", decimals, hi));
+        if lo == hi {
+            write!(f, "{}", lo.trim_end_matches('0').trim_end_matches('.'))
+        } else {
+            write!(f, "[{lo}, {hi}]")
+        }
+    }
+}
+
+fn next_up(x: f64) -> f64 {
+    if x.is_nan() || x == f64::INFINITY {
+        x
+    } else if x == 0.0 {
+        f64::from_bits(1)
+    } else if x > 0.0 {
+        f64::from_bits(x.to_bits() + 1)
+    } else {
+        f64::from_bits(x.to_bits() - 1)
+    }
+}
+
+fn next_down(x: f64) -> f64 {
+    -next_up(-x)
+}
+
+/// How many floats there are from `lo` up to `hi`
+fn ulps_between(lo: f64, hi: f64) -> u64 {
+    // Maps floats to integers with the same order, where neighbouring floats are neighbouring integers
+    let ordered = |x: f64| {
+        let bits = x.to_bits() as i64;
+        if bits < 0 { i64::MIN - bits } else { bits }
+    };
+    ordered(hi).abs_diff(ordered(lo))
+}
+
+/// `a + b` and its rounding error, which can be represented exactly (Knuth's TwoSum)
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    let b_virtual = sum - a;
+    (sum, (a - (sum - b_virtual)) + (b - b_virtual))
+}
+
+/// `a * b` and its rounding error, which is exact unless the product is tiny
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let product = a * b;
+    if a != 0.0 && b != 0.0 && product.abs() < MIN_EXACT {
+        return (product, f64::NAN);
+    }
+    (product, a.mul_add(b, -product))
+}
+
+/// `a / b` and a value with the sign of its rounding error, from the exact remainder `a - q * b`
+fn two_quotient(a: f64, b: f64) -> (f64, f64) {
+    let quotient = a / b;
+    if a != 0.0 && f64::min(a.abs(), quotient.abs()) < MIN_EXACT {
+        return (quotient, f64::NAN);
+    }
+    (quotient, (-quotient).mul_add(b, a) * b.signum())
+}
+
+/// `√x` and a value with the sign of its rounding error, from the exact remainder `x - r * r`
+fn two_sqrt(x: f64) -> (f64, f64) {
+    let root = x.sqrt();
+    if x != 0.0 && x < MIN_EXACT {
+        return (root, f64::NAN);
+    }
+    (root, (-root).mul_add(root, x))
+}
+
+impl<'a> BaseField<'a> for Interval {
+    fn as_scalar(&self) -> CResult<f64> {
+        self.as_point().ok_or_else(|| Error::EvalError(format!("The interval {self} doesn't have a single value")))
+    }
+
+    fn powf(&self, exp: Self) -> CResult<Self> {
+        if let Some(n) = exp.as_integer() {
+            if n < 0 {
+                return Interval::point(1.0) / self.powf(Interval::point(-n as f64))?;
+            }
+            if self.contains(0.0) {
+                let (lo, hi) = (self.lo.powi(n), self.hi.powi(n));
+                if n % 2 == 0 {
+                    return Ok(Interval { lo: 0.0, hi: next_up(lo.max(hi)) });
+                }
+                return Interval::hull(&[lo, hi]);
+            }
+            // Away from 0, multiplying can't mix up the bounds, and it's exact where possible (e.g. 3^4)
+            let (mut power, mut base, mut n) = (Interval::point(1.0), self.clone(), n);
+            while n > 0 {
+                if n % 2 == 1 {
+                    power = (power * base.clone())?;
+                }
+                base = (base.clone() * base)?;
+                n /= 2;
+            }
+            return Ok(power);
+        }
+        if self.lo < 0.0 {
+            return Err(Error::EvalError(format!("{self}^{exp} is not a real number, as the base can be negative")));
+        }
+        if self.lo == 0.0 && exp.lo < 0.0 {
+            return Err(Error::EvalError(format!("{self}^{exp} is unbounded, as the base can be 0")));
+        }
+        // x^y is monotonic in both x and y for x > 0, so the extremes are at the corners
+        Interval::hull(&[self.lo.powf(exp.lo), self.lo.powf(exp.hi), self.hi.powf(exp.lo), self.hi.powf(exp.hi)])
+    }
+
+    fn root(&self, n: Self) -> CResult<Self> {
+        match n.as_integer() {
+            Some(n) if n > 0 && n % 2 == 1 => {
+                let root = |x: f64| x.signum() * x.abs().powf(1.0 / n as f64);
+                Interval::hull(&[root(self.lo), root(self.hi)])
+            },
+            Some(n) if n > 0 => {
+                if self.lo < 0.0 {
+                    return Err(Error::EvalError(format!("The root of {self} is not a real number, as it can be negative")));
+                }
+                if n == 2 {
+                    return Interval::hull_exact(&[two_sqrt(self.lo), two_sqrt(self.hi)]);
+                }
+                Interval::hull(&[self.lo.powf(1.0 / n as f64), self.hi.powf(1.0 / n as f64)]).map(|root| Interval::new(root.lo.max(0.0), root.hi))
+            },
+            _ => Err(Error::EvalError(format!("Only positive whole roots of intervals are supported, received {n}"))),
+        }
+    }
+
+    fn fract(&self) -> CResult<f64> {
+        Ok(self.as_scalar()?.fract())
+    }
+
+    fn sin(&self) -> CResult<Self> {
+        self.periodic_range(f64::sin, FRAC_PI_2)
+    }
+
+    fn cos(&self) -> CResult<Self> {
+        self.periodic_range(f64::cos, 0.0)
+    }
+
+    fn tan(&self) -> CResult<Self> {
+        if self.contains_periodic(FRAC_PI_2, PI) {
+            return Err(Error::EvalError(format!("tan({self}) is unbounded")));
+        }
+        Interval::hull(&[self.lo.tan(), self.hi.tan()])
+    }
+
+    fn asin(&self) -> CResult<Self> {
+        if self.lo < -1.0 || self.hi > 1.0 {
+            return Err(Error::EvalError(format!("asin({self}) is only defined between -1 and 1")));
+        }
+        Interval::hull(&[self.lo.asin(), self.hi.asin()])
+    }
+
+    fn acos(&self) -> CResult<Self> {
+        if self.lo < -1.0 || self.hi > 1.0 {
+            return Err(Error::EvalError(format!("acos({self}) is only defined between -1 and 1")));
+        }
+        Interval::hull(&[self.lo.acos(), self.hi.acos()])
+    }
+
+    fn atan(&self) -> CResult<Self> {
+        Interval::hull(&[self.lo.atan(), self.hi.atan()])
+    }
+
+    fn abs(&self) -> CResult<Self> {
+        if self.contains(0.0) {
+            Ok(Interval::new(0.0, f64::max(-self.lo, self.hi)))
+        } else {
+            Ok(Interval::new(self.lo.abs(), self.hi.abs()))
+        }
+    }
+
+    fn sinh(&self) -> CResult<Self> {
+        Interval::hull(&[self.lo.sinh(), self.hi.sinh()])
+    }
+
+    fn cosh(&self) -> CResult<Self> {
+        if self.contains(0.0) {
+            return Interval::hull(&[1.0, self.lo.cosh(), self.hi.cosh()]);
+        }
+        Interval::hull(&[self.lo.cosh(), self.hi.cosh()])
+    }
+
+    fn tanh(&self) -> CResult<Self> {
+        Interval::hull(&[self.lo.tanh(), self.hi.tanh()])
+    }
+
+    fn asinh(&self) -> CResult<Self> {
+        Interval::hull(&[self.lo.asinh(), self.hi.asinh()])
+    }
+
+    fn acosh(&self) -> CResult<Self> {
+        if self.lo < 1.0 {
+            return Err(Error::EvalError(format!("acosh({self}) is only defined from 1 upwards")));
+        }
+        Interval::hull(&[self.lo.acosh(), self.hi.acosh()])
+    }
+
+    fn atanh(&self) -> CResult<Self> {
+        if self.lo <= -1.0 || self.hi >= 1.0 {
+            return Err(Error::EvalError(format!("atanh({self}) is only defined between -1 and 1")));
+        }
+        Interval::hull(&[self.lo.atanh(), self.hi.atanh()])
+    }
+
+    fn ln(&self) -> CResult<Self> {
+        if self.lo <= 0.0 {
+            return Err(Error::EvalError(format!("ln({self}) is only defined for positive numbers")));
+        }
+        Interval::hull(&[self.lo.ln(), self.hi.ln()])
+    }
+
+    fn exp(&self) -> CResult<Self> {
+        Interval::hull(&[self.lo.exp(), self.hi.exp()])
+    }
+
+    fn log(&self, base: &Self) -> CResult<Self> {
+        if !base.is_point() {
+            return self.ln()? / base.ln()?;
+        }
+        self.ln()?;
+        Interval::hull(&[types::log(self.lo, base.lo), types::log(self.hi, base.lo)])
+    }
+
+    // Rounding is exact, so these don't need to round outwards
+    fn floor(&self) -> CResult<Self> {
+        Ok(Interval::new(self.lo.floor(), self.hi.floor()))
+    }
+
+    fn ceil(&self) -> CResult<Self> {
+        Ok(Interval::new(self.lo.ceil(), self.hi.ceil()))
+    }
+
+    fn round(&self) -> CResult<Self> {
+        Ok(Interval::new(self.lo.round(), self.hi.round()))
+    }
+
+    fn sign(&self) -> CResult<Self> {
+        let sign = |x: f64| if x == 0.0 { 0.0 } else { x.signum() };
+        Ok(Interval::new(sign(self.lo), sign(self.hi)))
+    }
+
+    fn min(&self, other: &Self) -> CResult<Self> {
+        Ok(Interval::new(self.lo.min(other.lo), self.hi.min(other.hi)))
+    }
+
+    fn max(&self, other: &Self) -> CResult<Self> {
+        Ok(Interval::new(self.lo.max(other.lo), self.hi.max(other.hi)))
+    }
+
+    fn arg(&self) -> CResult<Self> {
+        if self.lo >= 0.0 {
+            Ok(Interval::point(0.0))
+        } else if self.hi < 0.0 {
+            Interval::hull(&[PI])
+        } else {
+            Interval::hull(&[0.0, PI])
+        }
+    }
+
+    fn magnitude(&self) -> CResult<f64> {
+        Ok(f64::max(self.lo.abs(), self.hi.abs()))
+    }
+
+    /// Only intervals that don't overlap (or are the same point) have a definite order
+    fn compare(&self, other: &Self) -> CResult<Option<Ordering>> {
+        if self.hi < other.lo {
+            Ok(Some(Ordering::Less))
+        } else if self.lo > other.hi {
+            Ok(Some(Ordering::Greater))
+        } else if self.is_point() && self == other {
+            Ok(Some(Ordering::Equal))
+        } else {
+            Err(Error::EvalError(format!("Cannot compare {self} and {other}, as they overlap")))
+        }
+    }
+
+    fn convert(&self, unit: &str, _units: &UnitRegistry) -> CResult<Self> {
+        Err(Error::UnitError(format!("Cannot convert to '{unit}' without units")))
+    }
+
+    fn define_unit(&self, name: &str) -> CResult<Unit> {
+        Err(Error::UnitError(format!("Cannot define unit '{name}' without units")))
+    }
+
+    fn unit_identity(unit: &str, _units: &UnitRegistry) -> CResult<Self> {
+        Err(Error::UnitError(format!("Cannot use unit '{unit}' without units")))
+    }
+
+    /// A tolerance, e.g. `10 ± 0.1` is `[9.9, 10.1]`
+    fn plus_minus(&self, tolerance: Self) -> CResult<Self> {
+        let tolerance = f64::max(tolerance.lo.abs(), tolerance.hi.abs());
+        Interval::hull_exact(&[two_sum(self.lo, -tolerance), two_sum(self.hi, tolerance)])
+    }
+
+    fn from_literal(literal: &str) -> Self {
+        Interval::try_from(literal).expect("Number literals are valid decimals")
+    }
+}
+
+impl TryFrom<&str> for Interval {
+    type Error = Box<dyn std::error::Error>;
+
+    /// Literals that `f64` can't represent exactly, such as 0.1, are widened to the next `f64` on the side of the
+    /// exact value
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let rounded = s.parse::<f64>()?;
+        let Exact::Rational(exact) = Exact::try_from(s)? else {
+            return Ok(Interval::point(rounded));
+        };
+        let error = match BigRational::from_float(rounded).map(|rounded| exact.cmp(&rounded)) {
+            Some(Ordering::Less) => -1.0,
+            Some(Ordering::Greater) => 1.0,
+            _ => 0.0,
+        };
+        Ok(Interval::hull_exact(&[(rounded, error)])?)
+    }
+}
+
+impl From<f64> for Interval {
+    fn from(value: f64) -> Self {
+        Interval::point(value)
+    }
+}
+
+impl std::ops::Add for Interval {
+    type Output = CResult<Self>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Interval::hull_exact(&[two_sum(self.lo, rhs.lo), two_sum(self.hi, rhs.hi)])
+    }
+}
+
+impl std::ops::Sub for Interval {
+    type Output = CResult<Self>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Interval::hull_exact(&[two_sum(self.lo, -rhs.hi), two_sum(self.hi, -rhs.lo)])
+    }
+}
+
+impl std::ops::Mul for Interval {
+    type Output = CResult<Self>;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Interval::hull_exact(&[two_product(self.lo, rhs.lo), two_product(self.lo, rhs.hi), two_product(self.hi, rhs.lo), two_product(self.hi, rhs.hi)])
+    }
+}
+
+impl std::ops::Div for Interval {
+    type Output = CResult<Self>;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        if rhs.contains(0.0) {
+            return Err(Error::EvalError(format!("Cannot divide by {rhs}, as it contains 0")));
+        }
+        Interval::hull_exact(&[two_quotient(self.lo, rhs.lo), two_quotient(self.lo, rhs.hi), two_quotient(self.hi, rhs.lo), two_quotient(self.hi, rhs.hi)])
+    }
+}
+
+impl Serialize for Interval {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::Evaluator;
+    use crate::types::Span;
+    use crate::evaluate_line;
+
+    fn evaluate(input: &str) -> CResult<Interval> {
+        Ok(evaluate_line(Span::new(input), &mut Evaluator::<Interval>::new())?.unwrap())
+    }
+
+    #[test]
+    fn test_bounds() {
+        let tests = vec![
+            ("10 ± 0.1", 9.9, 10.1),
+            ("10 ± 0.1 + 5 ± 0.05", 14.85, 15.15),
+            ("10 ± 0.1 - 5 ± 0.05", 4.85, 5.15),
+            ("(2 ± 1) * (3 ± 1)", 2.0, 12.0),
+            ("(0 ± 1) * (3 ± 1)", -4.0, 4.0),
+            ("(6 ± 2) / (2 ± 1)", 4.0 / 3.0, 8.0),
+            ("(0 ± 2)^2", 0.0, 4.0),
+            ("(1 ± 1)^3", 0.0, 8.0),
+            ("2^(2 ± 1)", 2.0, 8.0),
+            ("\\sqrt{4 ± 2}", 2.0_f64.sqrt(), 6.0_f64.sqrt()),
+            ("\\sin(1.5 ± 0.5)", 1.0_f64.sin(), 1.0),
+            ("\\cos(3 ± 1)", -1.0, 2.0_f64.cos()),
+            ("\\sin(0 ± 10)", -1.0, 1.0),
+            ("\\tan(0 ± 1)", -1.0_f64.tan(), 1.0_f64.tan()),
+            ("\\ln(2 ± 1)", 0.0, 3.0_f64.ln()),
+            ("\\cosh(0 ± 1)", 1.0, 1.0_f64.cosh()),
+            ("\\log_2(6 ± 2)", 2.0, 3.0),
+            ("\\operatorname{floor}(2.5 ± 1)", 1.0, 3.0),
+            ("\\operatorname{max}(1 ± 1, 1.5 ± 0.2)", 1.5 - 0.2, 2.0),
+        ];
+        for (input, lo, hi) in tests {
+            let result = evaluate(input).unwrap();
+            assert!(result.lo <= lo && lo - result.lo < 1e-12, "{input}: {} should be just below {lo}", result.lo);
+            assert!(result.hi >= hi && result.hi - hi < 1e-12, "{input}: {} should be just above {hi}", result.hi);
+        }
+    }
+
+    #[test]
+    fn test_single_values() {
+        // Exact operations aren't rounded outwards
+        for (input, expected) in [("1 + 2", 3.0), ("1 - 1", 0.0), ("0.5 * 4", 2.0), ("3 / 4", 0.75), ("3^4", 81.0), ("\\sqrt{4}", 2.0)] {
+            assert_eq!(evaluate(input).unwrap(), Interval::point(expected), "{input}");
+        }
+        // Literals that f64 can't represent exactly contain the exact decimal, e.g. 0.1 is just below the f64 0.1
+        assert_eq!(evaluate("0.1").unwrap(), Interval { lo: next_down(0.1), hi: 0.1 });
+        assert_eq!(evaluate("0.7").unwrap(), Interval { lo: 0.7, hi: next_up(0.7) });
+        assert_eq!(evaluate("0.25").unwrap(), Interval::point(0.25));
+        // Inexact ones are only a few ulps wide, so they can still be used where a single value is needed
+        let tests = [("0.1 + 0.2", 0.3), ("\\sqrt{2}^2", 2.0), ("(0.1 \\cdot 30)!", 6.0), ("nCr(0.1 \\cdot 50, 2)", 10.0), ("solve(x^2 = 4, x, 0, 3)", 2.0)];
+        for (input, expected) in tests {
+            let result = evaluate(input).unwrap().as_scalar().unwrap();
+            assert!((result - expected).abs() < 1e-12, "{input}: {result}");
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let tests = vec![
+            ("0.1", "0.1"),
+            ("10 ± 0.1 + 5 ± 0.05", "[14.85, 15.15]"),
+            ("0.1 + 0.2", "0.3"),
+            ("1 ± 0.000000001", "[0.9999999990, 1.0000000010]"),
+            ("1000 ± 3", "[997.0, 1003.0]"),
+            ("(2 ± 1) * (3 ± 1)", "[2, 12]"),
+            ("1 - 1", "0"),
+            ("\\sin(0)", "0"),
+            ("solve(x^2 = 2, x, 0, 3)", "1.41421356237"),
+            ("e^{1000}", "[1.7976931348623157e308, inf]"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(evaluate(input).unwrap().to_string(), expected, "{input}");
+        }
+        let list = evaluate_line(Span::new("[1 - 1, \\sin(0)]"), &mut Evaluator::<crate::fields::Value<Interval>>::new()).unwrap().unwrap();
+        assert_eq!(list.to_string(), "[0, 0]");
+    }
+
+    #[test]
+    fn test_invalid() {
+        for input in ["1 / (0 ± 1)", "\\tan(1.5 ± 0.1)", "\\sqrt{0 ± 1}", "(0 ± 1)^0.5", "\\arcsin(1 ± 0.5)", "\\ln(0 ± 1)", "1000!"] {
+            assert!(evaluate(input).is_err(), "{input} should be invalid");
+        }
+    }
+}
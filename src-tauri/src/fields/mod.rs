@@ -1,8 +1,19 @@
 mod complex;
+mod complex_unit_value;
+mod exact;
 mod float;
+mod interval;
+mod uncertain;
+mod value;
 mod units;
 mod unit_value;
 
-pub use crate::fields::complex::Complex;
+pub use crate::fields::complex::{Complex, ComplexForm};
+pub use crate::fields::complex_unit_value::ComplexUnitVal;
+pub use crate::fields::exact::Exact;
 pub use crate::fields::float::Float;
+pub use crate::fields::interval::Interval;
+pub use crate::fields::uncertain::Uncertain;
 pub use crate::fields::unit_value::UnitVal;
+pub use crate::fields::value::Value;
+pub use crate::fields::units::{Unit, UnitRegistry, UnitSystem};
@@ -0,0 +1,434 @@
+use crate::error::Error;
+use crate::fields::{Unit, UnitRegistry, UnitSystem, UnitVal};
+use crate::calculus;
+use crate::types::{BaseField, CResult, Limit};
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+/// Identifies each independent source of uncertainty, such as each `±` in a sheet
+static NEXT_SOURCE: AtomicUsize = AtomicUsize::new(0);
+
+/// A value with units and a standard uncertainty, e.g. `(12.3 ± 0.1) mm`.
+///
+/// Uncertainties are propagated to first order. Each result keeps track of how much of its uncertainty comes from
+/// each independent source, so values that share a source are correlated (e.g. `x - x` is exact and `x \cdot x`
+/// is the same as `x^2`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Uncertain {
+    pub value: UnitVal,
+    /// Contribution of each independent source to the standard uncertainty, in the same (SI) units as `value`
+    sources: BTreeMap<usize, f64>,
+}
+
+impl Uncertain {
+    /// A value with an uncertainty that is independent of all other values
+    pub fn new(value: UnitVal, uncertainty: f64) -> Self {
+        if uncertainty == 0.0 {
+            return Uncertain::exact(value);
+        }
+        let source = NEXT_SOURCE.fetch_add(1, Ordering::Relaxed);
+        Uncertain { value, sources: BTreeMap::from([(source, uncertainty.abs())]) }
+    }
+
+    pub fn exact(value: UnitVal) -> Self {
+        Uncertain { value, sources: BTreeMap::new() }
+    }
+
+    /// Standard uncertainty, in the same (SI) units as `value`
+    pub fn uncertainty(&self) -> f64 {
+        self.sources.values().fold(0.0, |total, contribution| total.hypot(*contribution))
+    }
+
+    /// Result of a function of this value, given the function's derivative at the value
+    fn propagate(&self, value: UnitVal, derivative: f64) -> Self {
+        Uncertain::with_sources(value, [(self, derivative)])
+    }
+
+    /// Result of a function of several values, given its partial derivatives with respect to each of them.
+    /// Contributions from the same source are added before they are combined with the others
+    fn with_sources<'a>(value: UnitVal, derivatives: impl IntoIterator<Item = (&'a Self, f64)>) -> Self {
+        let mut sources = BTreeMap::new();
+        for (operand, derivative) in derivatives {
+            for (source, contribution) in &operand.sources {
+                *sources.entry(*source).or_insert(0.0) += derivative * contribution;
+            }
+        }
+        Uncertain { value, sources }
+    }
+
+    /// Formats the value and its uncertainty in the same units, e.g. "(12.30 ± 0.10) mm"
+    pub fn to_string_in(&self, system: &UnitSystem) -> String {
+        let (value, scale, unit) = self.value.display_parts(system);
+        let uncertainty = self.uncertainty();
+        if uncertainty == 0.0 {
+            return self.value.to_string_in(system);
+        }
+        let (value, uncertainty) = round_to_uncertainty(value, uncertainty / scale);
+        if unit.is_empty() {
+            format!("{value} ± {uncertainty}")
+        } else {
+            format!("({value} ± {uncertainty}) {unit}")
+        }
+    }
+}
+
+/// Rounds the uncertainty to two significant figures, and the value to the same decimal place
+fn round_to_uncertainty(value: f64, uncertainty: f64) -> (String, String) {
+    if !uncertainty.is_finite() || !value.is_finite() {
+        return (value.to_string(), uncertainty.to_string());
+    }
+    let last_digit = uncertainty.log10().floor() as i32 - 1;
+    let decimals = (-last_digit).max(0) as usize;
+    let round = |x: f64| (x / 10.0_f64.powi(last_digit)).round() * 10.0_f64.powi(last_digit);
+    (format!("{:.*}", decimals, round(value)), format!("{:.*}", decimals, round(uncertainty)))
+}
+
+
+impl std::fmt::Display for Uncertain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_string_in(&UnitSystem::default()))
+    }
+}
+
+impl<'a> BaseField<'a> for Uncertain {
+    fn as_scalar(&self) -> CResult<f64> {
+        self.value.as_scalar()
+    }
+
+    fn powf(&self, exp: Self) -> CResult<Self> {
+        let value = self.value.powf(exp.value.clone())?;
+        let (base, n) = (self.value.value, exp.value.as_scalar()?);
+        let base_derivative = n * base.powf(n - 1.0);
+        // Only uncertain exponents need the logarithm, which isn't defined for negative bases
+        let exp_derivative = if exp.sources.is_empty() { 0.0 } else { value.value * base.ln() };
+        Ok(Uncertain::with_sources(value, [(self, base_derivative), (&exp, exp_derivative)]))
+    }
+
+    fn root(&self, n: Self) -> CResult<Self> {
+        let value = self.value.root(n.value.clone())?;
+        let (base, n_value) = (self.value.value, n.value.as_scalar()?);
+        let base_derivative = value.value / (n_value * base);
+        let n_derivative = if n.sources.is_empty() { 0.0 } else { -value.value * base.ln() / n_value.powi(2) };
+        Ok(Uncertain::with_sources(value, [(self, base_derivative), (&n, n_derivative)]))
+    }
+
+    fn fract(&self) -> CResult<f64> {
+        self.value.fract()
+    }
+
+    fn as_exact_scalar(&self) -> CResult<f64> {
+        if self.uncertainty() != 0.0 {
+            return Err(Error::EvalError(format!("Expected an exact number, but {self} has an uncertainty")));
+        }
+        self.as_scalar()
+    }
+
+    fn sin(&self) -> CResult<Self> {
+        let angle = self.value.as_angle()?;
+        Ok(self.propagate(self.value.sin()?, angle.cos()))
+    }
+
+    fn cos(&self) -> CResult<Self> {
+        let angle = self.value.as_angle()?;
+        Ok(self.propagate(self.value.cos()?, angle.sin()))
+    }
+
+    fn tan(&self) -> CResult<Self> {
+        let angle = self.value.as_angle()?;
+        Ok(self.propagate(self.value.tan()?, 1.0 / angle.cos().powi(2)))
+    }
+
+    fn asin(&self) -> CResult<Self> {
+        let x = self.value.as_scalar()?;
+        Ok(self.propagate(self.value.asin()?, 1.0 / (1.0 - x * x).sqrt()))
+    }
+
+    fn acos(&self) -> CResult<Self> {
+        let x = self.value.as_scalar()?;
+        Ok(self.propagate(self.value.acos()?, 1.0 / (1.0 - x * x).sqrt()))
+    }
+
+    fn atan(&self) -> CResult<Self> {
+        let x = self.value.as_scalar()?;
+        Ok(self.propagate(self.value.atan()?, 1.0 / (1.0 + x * x)))
+    }
+
+    fn abs(&self) -> CResult<Self> {
+        Ok(self.propagate(self.value.abs()?, self.value.value.signum()))
+    }
+
+    fn sinh(&self) -> CResult<Self> {
+        let x = self.value.as_scalar()?;
+        Ok(self.propagate(self.value.sinh()?, x.cosh()))
+    }
+
+    fn cosh(&self) -> CResult<Self> {
+        let x = self.value.as_scalar()?;
+        Ok(self.propagate(self.value.cosh()?, x.sinh()))
+    }
+
+    fn tanh(&self) -> CResult<Self> {
+        let x = self.value.as_scalar()?;
+        Ok(self.propagate(self.value.tanh()?, 1.0 / (x.cosh() * x.cosh())))
+    }
+
+    fn asinh(&self) -> CResult<Self> {
+        let x = self.value.as_scalar()?;
+        Ok(self.propagate(self.value.asinh()?, 1.0 / (x * x + 1.0).sqrt()))
+    }
+
+    fn acosh(&self) -> CResult<Self> {
+        let x = self.value.as_scalar()?;
+        Ok(self.propagate(self.value.acosh()?, 1.0 / (x * x - 1.0).sqrt()))
+    }
+
+    fn atanh(&self) -> CResult<Self> {
+        let x = self.value.as_scalar()?;
+        Ok(self.propagate(self.value.atanh()?, 1.0 / (1.0 - x * x)))
+    }
+
+    fn ln(&self) -> CResult<Self> {
+        let x = self.value.as_scalar()?;
+        Ok(self.propagate(self.value.ln()?, 1.0 / x))
+    }
+
+    fn exp(&self) -> CResult<Self> {
+        let value = self.value.exp()?;
+        let derivative = value.as_scalar()?;
+        Ok(self.propagate(value, derivative))
+    }
+
+    /// Both the value and the base can be uncertain
+    fn log(&self, base: &Self) -> CResult<Self> {
+        let (x, b) = (self.value.as_scalar()?, base.value.as_scalar()?);
+        let value_derivative = 1.0 / (x * b.ln());
+        let base_derivative = -x.ln() / (b * b.ln() * b.ln());
+        Ok(Uncertain::with_sources(self.value.log(&base.value)?, [(self, value_derivative), (base, base_derivative)]))
+    }
+
+    fn floor(&self) -> CResult<Self> {
+        Ok(Uncertain::exact(self.value.floor()?))
+    }
+
+    fn ceil(&self) -> CResult<Self> {
+        Ok(Uncertain::exact(self.value.ceil()?))
+    }
+
+    fn round(&self) -> CResult<Self> {
+        Ok(Uncertain::exact(self.value.round()?))
+    }
+
+    fn sign(&self) -> CResult<Self> {
+        Ok(Uncertain::exact(self.value.sign()?))
+    }
+
+    fn im(&self) -> CResult<Self> {
+        Ok(Uncertain::exact(self.value.im()?))
+    }
+
+    fn arg(&self) -> CResult<Self> {
+        Ok(Uncertain::exact(self.value.arg()?))
+    }
+
+    /// The integral of the best estimates, with an uncertainty from the numerical error and the uncertainties of the
+    /// limits. Uncertainties of other values in the expression aren't included
+    fn integral(lower: &Limit<Self>, upper: &Limit<Self>, f: impl Fn(Self) -> CResult<Self>) -> CResult<Self> {
+        let best_estimate = |x: UnitVal| -> CResult<UnitVal> { Ok(f(Uncertain::exact(x))?.value) };
+        let best_limit = |limit: &Limit<Self>| limit.try_map(|x| Ok(x.value.clone()));
+        let (value, error) = calculus::integral(&best_limit(lower)?, &best_limit(upper)?, best_estimate)?;
+        // Moving a limit changes the integral by the value of the integrand there
+        let limit_term = |limit: &Limit<Self>, sign: f64| -> CResult<(Self, f64)> {
+            match limit {
+                Limit::Finite(x) if !x.sources.is_empty() => Ok((x.clone(), sign * best_estimate(x.value.clone())?.value)),
+                _ => Ok((Uncertain::from(0.0), 0.0)),
+            }
+        };
+        let (lower, lower_derivative) = limit_term(lower, -1.0)?;
+        let (upper, upper_derivative) = limit_term(upper, 1.0)?;
+        let numerical_error = Uncertain::new(UnitVal::from(0.0), error.magnitude()?);
+        Ok(Uncertain::with_sources(value, [(&lower, lower_derivative), (&upper, upper_derivative), (&numerical_error, 1.0)]))
+    }
+
+    fn unit(&self) -> CResult<Self> {
+        Ok(Uncertain::exact(self.value.unit()?))
+    }
+
+    fn magnitude(&self) -> CResult<f64> {
+        self.value.magnitude()
+    }
+
+    /// The derivative of the best estimate, with an uncertainty from how much it changes within the uncertainty of
+    /// this value. Uncertainties of other values in the expression aren't included
+    fn derivative(&self, f: impl Fn(Self) -> CResult<Self>) -> CResult<Self> {
+        let best_estimate = |x: &UnitVal| x.derivative(|x| Ok(f(Uncertain::exact(x))?.value));
+        let value = best_estimate(&self.value)?;
+        let uncertainty = self.uncertainty();
+        if uncertainty == 0.0 {
+            return Ok(Uncertain::exact(value));
+        }
+        let step = UnitVal::new(uncertainty, self.value.quantity.clone());
+        let above = best_estimate(&(self.value.clone() + step.clone())?)?;
+        let below = best_estimate(&(self.value.clone() - step)?)?;
+        Ok(self.propagate(value, (above.value - below.value) / (2.0 * uncertainty)))
+    }
+
+    /// Compares the best estimates, ignoring the uncertainties
+    fn compare(&self, other: &Self) -> CResult<Option<std::cmp::Ordering>> {
+        self.value.compare(&other.value)
+    }
+
+    fn convert(&self, unit: &str, units: &UnitRegistry) -> CResult<Self> {
+        Ok(Uncertain { value: self.value.convert(unit, units)?, sources: self.sources.clone() })
+    }
+
+    fn define_unit(&self, name: &str) -> CResult<Unit> {
+        if self.uncertainty() != 0.0 {
+            return Err(Error::UnitError(format!("Unit '{name}' cannot be defined with an uncertainty ({self})")));
+        }
+        self.value.define_unit(name)
+    }
+
+    fn unit_identity(unit: &str, units: &UnitRegistry) -> CResult<Self> {
+        Ok(Uncertain::exact(UnitVal::unit_identity(unit, units)?))
+    }
+
+    fn plus_minus(&self, uncertainty: Self) -> CResult<Self> {
+        if uncertainty.value.is_absolute() || uncertainty.value.quantity != self.value.quantity {
+            return Err(Error::UnitError(format!("The uncertainty {} doesn't have the same units as {}", uncertainty.value, self.value)));
+        }
+        let added = Uncertain::new(UnitVal::from(0.0), uncertainty.value.value);
+        Ok(Uncertain::with_sources(self.value.clone(), [(self, 1.0), (&added, 1.0)]))
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Uncertain {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        Ok(Uncertain::exact(UnitVal::try_from(value)?))
+    }
+}
+
+impl From<f64> for Uncertain {
+    fn from(value: f64) -> Self {
+        Uncertain::exact(UnitVal::from(value))
+    }
+}
+
+impl std::ops::Add for Uncertain {
+    type Output = CResult<Self>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Ok(Uncertain::with_sources((self.value.clone() + rhs.value.clone())?, [(&self, 1.0), (&rhs, 1.0)]))
+    }
+}
+
+impl std::ops::Sub for Uncertain {
+    type Output = CResult<Self>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Ok(Uncertain::with_sources((self.value.clone() - rhs.value.clone())?, [(&self, 1.0), (&rhs, -1.0)]))
+    }
+}
+
+impl std::ops::Mul for Uncertain {
+    type Output = CResult<Self>;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let (a, b) = (self.value.value, rhs.value.value);
+        Ok(Uncertain::with_sources((self.value.clone() * rhs.value.clone())?, [(&self, b), (&rhs, a)]))
+    }
+}
+
+impl std::ops::Div for Uncertain {
+    type Output = CResult<Self>;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        let (a, b) = (self.value.value, rhs.value.value);
+        Ok(Uncertain::with_sources((self.value.clone() / rhs.value.clone())?, [(&self, 1.0 / b), (&rhs, -a / (b * b))]))
+    }
+}
+
+impl Serialize for Uncertain {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::Evaluator;
+    use crate::types::Span;
+    use crate::evaluate_line;
+
+    fn evaluate(input: &str) -> CResult<Uncertain> {
+        Ok(evaluate_line(Span::new(input), &mut Evaluator::<Uncertain>::new())?.unwrap())
+    }
+
+    #[test]
+    fn test_propagation() {
+        let tests = vec![
+            ("9.81 ± 0.02", "9.810 ± 0.020"),
+            ("9.81 \\pm 0.02", "9.810 ± 0.020"),
+            ("(12.3 ± 0.1) mm", "(0.01230 ± 0.00010) m"),
+            ("(12.3 ± 0.1) mm to mm", "(12.30 ± 0.10) mm"),
+            ("(1 ± 0.3) m + (2 ± 0.4) m", "(3.00 ± 0.50) m"),
+            ("(5 ± 0.3) m - (2 ± 0.4) m", "(3.00 ± 0.50) m"),
+            ("(2 ± 0.02) m * (3 ± 0.06) m", "(6.00 ± 0.13) m^2"),
+            ("(6 ± 0.3) m / (2 ± 0.1) s", "(3.00 ± 0.21) m/s"),
+            ("(3 ± 0.1)^2", "9.00 ± 0.60"),
+            ("\\sqrt{(4 ± 0.4) m^2}", "(2.00 ± 0.10) m"),
+            ("\\sin((30 ± 1) deg)", "0.500 ± 0.015"),
+            ("\\ln(10 ± 0.5)", "2.303 ± 0.050"),
+            ("\\exp(1 ± 0.1)", "2.72 ± 0.27"),
+            ("\\log_2(8 ± 0.8)", "3.00 ± 0.14"),
+            ("\\operatorname{round}(2.6 ± 0.1)", "3"),
+            ("\\int_0^{2 ± 0.1} x dx", "2.00 ± 0.20"),
+            ("(1000 ± 20) m to km", "(1.000 ± 0.020) km"),
+            ("2 ± 30", "2 ± 30"),
+            ("1250 ± 120", "1250 ± 120"),
+            ("5 mm", "5 mm"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(evaluate(input).unwrap().to_string(), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_correlation() {
+        let mut eval = Evaluator::<Uncertain>::new();
+        for definition in ["x = 1 ± 0.1", "y = 2 ± 0.1", "z = x + y"] {
+            evaluate_line(Span::new(definition), &mut eval).unwrap();
+        }
+        let tests = vec![
+            ("x - x", "0"),
+            ("x \\cdot x", "1.00 ± 0.20"),
+            ("x^2", "1.00 ± 0.20"),
+            ("x / x", "1"),
+            ("x - y", "-1.00 ± 0.14"),
+            ("z - y", "1.00 ± 0.10"),
+            ("(x ± 0.1) - x", "0.00 ± 0.10"),
+        ];
+        for (input, expected) in tests {
+            let result = evaluate_line(Span::new(input), &mut eval).unwrap().unwrap();
+            assert_eq!(result.to_string(), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        for input in ["1 m ± 1 s", "1 ± 1 m", "20 °C ± 1 °C", "(5 ± 1)!", "nCr(5 ± 1, 2)"] {
+            assert!(evaluate(input).is_err(), "{input} should be invalid");
+        }
+        let mut eval = Evaluator::<Uncertain>::new();
+        evaluate_line(Span::new("unit x = (1 ± 0.1) m"), &mut eval).unwrap_err();
+    }
+}
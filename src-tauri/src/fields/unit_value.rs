@@ -2,6 +2,9 @@ use crate::error::Error;
 use crate::types::{BaseField, CResult};
 use crate::fields::units::*;
 
+use itertools::Itertools;
+use num_rational::Rational32;
+use num_traits::Signed;
 use serde::Serialize;
 
 
@@ -9,22 +12,41 @@ use serde::Serialize;
 pub struct UnitVal {
     pub value: f64,
     pub quantity: Quantity,
+    /// Zero point of an absolute temperature (in K). The SI value is `value + offset`
+    pub offset: f64,
+    /// Unit the value was explicitly converted to, if any
+    pub display_unit: Option<Unit>,
 }
 
 
 impl UnitVal {
     pub fn new(value: f64, quantity: Quantity) -> Self {
-        UnitVal { value, quantity }
+        UnitVal { value, quantity, offset: 0.0, display_unit: None }
     }
 
     pub fn new_value(value: f64, unit: &str) -> Self {
         if unit.is_empty() {
             return UnitVal::scalar(value);
         }
-        let (exp, base_unit) = UnitVal::from_unit_str(unit).unwrap();
-        let scale_factor = 10.0_f64.powf(exp as f64);
-        let value = value * scale_factor;
-        UnitVal::new(base_unit.to_si(value), base_unit.quantity.clone())
+        UnitVal::from_unit(value, &UnitVal::from_unit_str(unit).unwrap())
+    }
+
+    fn from_unit(value: f64, unit: &Unit) -> Self {
+        if unit.is_affine() {
+            // Keep the offset separate so that scaling (e.g. 20 * 1 C) happens relative to the unit's zero point
+            return UnitVal { value: value * unit.si_scale, quantity: unit.quantity.clone(), offset: unit.si_offset, display_unit: None }
+        }
+        UnitVal::new(unit.to_si(value), unit.quantity.clone())
+    }
+
+    /// The value in SI units, including the offset of absolute temperatures
+    pub fn si_value(&self) -> f64 {
+        self.value + self.offset
+    }
+
+    /// Whether this is an absolute temperature on an affine scale (e.g. 20 C), rather than a difference
+    pub fn is_absolute(&self) -> bool {
+        self.offset != 0.0
     }
 
     pub fn is_valid_unit(unit: &str) -> bool {
@@ -36,34 +58,65 @@ impl UnitVal {
     }
 
     pub fn to_string(&self) -> String {
+        self.to_string_in(&UnitSystem::default())
+    }
+
+    /// Formats the value using the units of the given system, unless it was explicitly converted
+    pub fn to_string_in(&self, system: &UnitSystem) -> String {
+        let (value, _, unit) = self.display_parts(system);
+        if unit.is_empty() {
+            value.to_string()
+        } else {
+            format!("{} {}", value, unit)
+        }
+    }
+
+    /// The value as it is displayed: the number, the size of the displayed unit (in SI units) and the unit's name
+    pub fn display_parts(&self, system: &UnitSystem) -> (f64, f64, String) {
+        if let Some(unit) = &self.display_unit {
+            return (round_significant(self.value_in(unit), 12), unit.si_scale, unit.name.clone())
+        }
         if self.is_scalar() {
-            return self.value.to_string()
+            return (self.value, 1.0, String::new())
         }
-        let used_units = Unit::compile_used_units(&self.quantity, "SI").unwrap();
+        if self.is_absolute() {
+            return match unit_map().values().find(|unit| unit.si_offset == self.offset) {
+                Some(unit) => (self.value / unit.si_scale, unit.si_scale, unit.name.clone()),
+                None => (self.si_value(), 1.0, "K".to_string()),
+            }
+        }
+        let used_units = Unit::compile_used_units(&self.quantity, system).unwrap();
         let base_unit = Unit::compose(&used_units, &self.quantity);
         let val = base_unit.from_si(self.value);
 
-        let numerator_units: Vec<(&&str, &i32)> = used_units.iter().filter(|(_, exp)| **exp > 0).collect();
+        let numerator_units: Vec<(&&str, &Rational32)> = used_units.iter().filter(|(_, exp)| exp.is_positive()).collect();
         let has_units_multiplied: bool = numerator_units.len() > 1;
         if has_units_multiplied || base_unit.name == "kg" { // TODO: Make working with grams more ergonomic
-            return format!("{} {}", self.value, base_unit.name)
+            return (val, base_unit.si_scale, base_unit.name)
         }
 
-        if let Some((_, numerator_unit_exp)) = numerator_units.get(0) {
+        if let Some((numerator_unit, numerator_unit_exp)) = numerator_units.get(0).filter(|(_, exp)| exp.is_integer()) {
+            let numerator_unit_exp = numerator_unit_exp.to_integer();
             let val_exp = val.log10().floor() as i32;
             // Reduce the exponent to the nearest multiple of 3
             let val_exp = val_exp / 3 * 3;
             let reduced_val = val / 10.0_f64.powf(val_exp as f64);
             // Account for the exponent of the unit it's being applied to
-            let val_exp = val_exp / *numerator_unit_exp;
-            if let Some(prefix) = prefix_map().get_by_right(&val_exp) {
-                format!("{} {}{}", reduced_val, prefix, base_unit.name)
+            let prefix_exp = val_exp / numerator_unit_exp;
+            // Only use engineering prefixes that exactly account for the exponent (e.g. 1000 m^2 isn't 10 dam^2)
+            let prefix = if val_exp % numerator_unit_exp == 0 && prefix_exp % 3 == 0 && unit_map()[**numerator_unit].allows_prefix(prefix_exp) {
+                prefix_map().get_by_right(&prefix_exp)
             } else {
-                format!("{} {}", val, base_unit.name)
+                None
+            };
+            if let Some(prefix) = prefix {
+                (reduced_val, base_unit.si_scale * 10.0_f64.powi(val_exp), format!("{}{}", prefix, base_unit.name))
+            } else {
+                (val, base_unit.si_scale, base_unit.name)
             }
         } else {
             // TODO: Allow prefixes when there is a single denominator units
-            format!("{} {}", val, base_unit.name)
+            (val, base_unit.si_scale, base_unit.name)
         }
     }
 
@@ -71,34 +124,77 @@ impl UnitVal {
         self.quantity == Quantity::unitless()
     }
 
-    /// Parsing the Unit and exponential from a base unit's shortand and it's prefix e.g. "kN" -> (3, NewtonUnit).
+    /// Parses a unit expression into a single Unit, e.g. "kN" or "W/(m^2*K)".
     /// 
-    /// Cannot handle composed units like "kg*m/s^2"
-    /// 
-    /// TODO: Doesn't work with mols or psi since they start with prefix letters m & p
-    fn from_unit_str(unit: &str) -> Result<(i32, Unit), Error> {
+    /// Supports products (`*`, `·` or spaces), quotients, exponents (`s^-2`, `s^(-2)` or `Hz^(1/2)`),
+    /// square roots (`√Hz`) and parentheses.
+    /// Affine units (e.g. C) can only be used on their own
+    pub fn from_unit_str(unit: &str) -> Result<Unit, Error> {
+        UnitVal::from_unit_str_in(unit, &UnitRegistry::new())
+    }
+
+    /// Same as `from_unit_str`, but also resolves the units defined in the document
+    pub fn from_unit_str_in(unit: &str, units: &UnitRegistry) -> Result<Unit, Error> {
         if unit.is_empty() {
             return Err(Error::UnitError("No units given. Value is scalar".to_string()));
         }
-        let possible_prefix = unit.chars().next().expect("Unit was empty but not caught by is_empty check");
-        if unit == "kg" {
-            Ok((0, unit_map().get("kg").unwrap().clone()))
-        } else if unit.len() > 1 && prefix_map().contains_left(&possible_prefix) {
-            let prefix = possible_prefix;
-            let unit_shorthand = &unit[1..];
-            let exp = prefix_map().get_by_left(&prefix);
-            let base_unit = unit_map().get(unit_shorthand);
-            match (exp, base_unit) {
-                (Some(e), Some(q)) => Ok((e.clone(), q.clone())),
-                (None, _) => Err(Error::UnitError(format!("Invalid unit prefix '{prefix}'"))),
-                (_, None) => Err(Error::DefinitionNotFoundError(unit_shorthand.to_string()))
+        if let Ok((exp, base_unit)) = UnitVal::from_unit_token(unit, units) {
+            // Aliases are shown with the unit's own name (e.g. °C as C)
+            let name = if exp == 0 { base_unit.name.as_str() } else { unit };
+            let si_scale = base_unit.si_scale * 10.0_f64.powi(exp);
+            if !base_unit.is_affine() {
+                return Ok(Unit::new(name, si_scale, base_unit.quantity));
             }
-        } else {
-            let base_unit = unit_map().get(unit);
-            match base_unit {
-                Some(q) => Ok((0, q.clone())),
-                None => Err(Error::DefinitionNotFoundError(unit.to_string()))
+            if exp != 0 {
+                return Err(Error::UnitError(format!("Prefixes cannot be applied to '{}', as it is an absolute temperature", base_unit.name)));
             }
+            return Ok(Unit::new_affine(name, si_scale, base_unit.si_offset, base_unit.quantity));
+        }
+        let mut parser = UnitParser { unit, units, pos: 0 };
+        let (si_scale, quantity) = parser.parse_product()?;
+        if let Some(c) = parser.peek() {
+            return Err(Error::UnitError(format!("Unexpected '{c}' in unit '{unit}'")));
+        }
+        Ok(Unit::new(unit, si_scale, quantity))
+    }
+
+    /// Parsing the Unit and exponential from a base unit's shortand and it's prefix e.g. "kN" -> (3, NewtonUnit).
+    /// 
+    /// Exact matches are tried first so that units like "mol" and "psi" aren't read as prefixed units.
+    /// Otherwise the unit is split into a prefix and a base unit (longest prefix first), and an error is
+    /// returned if there is more than one way to split it
+    fn from_unit_token(unit: &str, units: &UnitRegistry) -> Result<(i32, Unit), Error> {
+        if unit.is_empty() {
+            return Err(Error::UnitError("No units given. Value is scalar".to_string()));
+        }
+        if let Some(base_unit) = units.get(unit) {
+            return Ok((0, base_unit.clone()));
+        }
+        let candidates = prefixes()
+            .filter_map(|(prefix, exp)| {
+                let shorthand = unit.strip_prefix(prefix)?;
+                Some((prefix, exp, shorthand, units.get_prefixed(shorthand)?))
+            })
+            .sorted_by_key(|(prefix, ..)| std::cmp::Reverse(prefix.len()))
+            .collect_vec();
+        let (candidates, disallowed): (Vec<_>, Vec<_>) = candidates.into_iter().partition(|(_, exp, _, base_unit)| base_unit.allows_prefix(*exp));
+        match (candidates.as_slice(), disallowed.first()) {
+            ([], None) => Err(Error::DefinitionNotFoundError(unit.to_string())),
+            ([], Some((prefix, _, shorthand, _))) => Err(Error::UnitError(format!("Prefix '{prefix}' cannot be applied to '{shorthand}'"))),
+            ([(_, exp, _, base_unit)], _) => Ok((*exp, (*base_unit).clone())),
+            _ => {
+                let options = candidates.iter().map(|(prefix, _, shorthand, _)| format!("'{prefix}' + '{shorthand}'")).join(" or ");
+                Err(Error::UnitError(format!("Ambiguous unit '{unit}', it could be {options}")))
+            },
+        }
+    }
+
+    /// Value expressed in the given unit
+    fn value_in(&self, unit: &Unit) -> f64 {
+        if self.offset == unit.si_offset {
+            self.value / unit.si_scale
+        } else {
+            unit.from_si(self.si_value())
         }
     }
 
@@ -106,10 +202,178 @@ impl UnitVal {
         UnitVal::new(self.value.powi(n), self.quantity.powi(n))
     }
 
+    /// C and F are easily mistaken for coulombs and farads, so errors about them say which is meant
+    fn ambiguity_hint(&self) -> &'static str {
+        match self.display_parts(&UnitSystem::default()).2.as_str() {
+            "C" | "F" => ". C and F are Celsius and Fahrenheit, write coulomb or farad (or e.g. mC or uF) for charge or capacitance",
+            _ => "",
+        }
+    }
+
+    /// Scales an absolute temperature relative to its zero point (e.g. 2 * 10 C = 20 C)
+    fn scale_absolute(&self, factor: f64) -> Self {
+        UnitVal { value: self.value * factor, quantity: self.quantity.clone(), offset: self.offset, display_unit: None }
+    }
+
+    fn check_not_absolute(&self, operation: &str) -> CResult<()> {
+        if self.is_absolute() {
+            Err(Error::UnitError(format!("Cannot {operation} an absolute temperature ({self}). Use a temperature difference instead{}", self.ambiguity_hint())))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The value in radians. Scalars are treated as radians too
+    pub fn as_angle(&self) -> CResult<f64> {
+        if self.is_scalar() || self.quantity == Quantity::angle() {
+            Ok(self.value)
+        } else {
+            Err(Error::UnitError(format!("Expected an angle, but got {self}")))
+        }
+    }
+
     pub fn scalar(value: f64) -> UnitVal { UnitVal::new(value, Quantity::unitless()) }
 }
 
 
+/// Recursive descent parser for composed units:
+/// 
+/// ```text
+/// product  := power (('*' | '·' | ' ' | '/') power)*
+/// power    := factor ('^' exponent)?
+/// factor   := '(' product ')' | '1' | '√' factor | unit
+/// exponent := '-'? digits | '(' '-'? digits ('/' digits)? ')'
+/// ```
+struct UnitParser<'a> {
+    unit: &'a str,
+    units: &'a UnitRegistry,
+    pos: usize,
+}
+
+impl<'a> UnitParser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.unit[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(Error::UnitError(format!("Expected '{expected}' but found '{c}' in unit '{}'", self.unit))),
+            None => Err(Error::UnitError(format!("Expected '{expected}' at the end of unit '{}'", self.unit))),
+        }
+    }
+
+    fn skip_spaces(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek() == Some(' ') {
+            self.next();
+        }
+        self.pos != start
+    }
+
+    fn parse_product(&mut self) -> Result<(f64, Quantity), Error> {
+        self.skip_spaces();
+        let (mut si_scale, mut quantity) = self.parse_power()?;
+        loop {
+            let skipped_spaces = self.skip_spaces();
+            let sign = match self.peek() {
+                Some('*') | Some('·') => { self.next(); 1 },
+                Some('/') => { self.next(); -1 },
+                Some('√') => 1,
+                Some(c) if skipped_spaces && (c == '(' || is_unit_char(c)) => 1,
+                _ => break,
+            };
+            self.skip_spaces();
+            let (scale, q) = self.parse_power()?;
+            si_scale *= scale.powi(sign);
+            quantity = quantity + q.powi(sign);
+        }
+        Ok((si_scale, quantity))
+    }
+
+    fn parse_power(&mut self) -> Result<(f64, Quantity), Error> {
+        let (si_scale, quantity) = self.parse_factor()?;
+        if self.peek() != Some('^') {
+            return Ok((si_scale, quantity));
+        }
+        self.next();
+        let parenthesized = self.peek() == Some('(');
+        if parenthesized {
+            self.next();
+        }
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.next();
+        }
+        // Fractional exponents need parentheses, since m^1/2 reads as m/2
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || (parenthesized && c == '/')) {
+            self.next();
+        }
+        let exp_str = &self.unit[start..self.pos];
+        let exp = exp_str.parse::<Rational32>().map_err(|_| Error::UnitError(format!("Invalid exponent '{exp_str}' in unit '{}'", self.unit)))?;
+        if parenthesized {
+            self.expect(')')?;
+        }
+        Ok((pow_rational(si_scale, exp), quantity.pow(exp)))
+    }
+
+    fn parse_factor(&mut self) -> Result<(f64, Quantity), Error> {
+        match self.peek() {
+            Some('(') => {
+                self.next();
+                let factor = self.parse_product()?;
+                self.skip_spaces();
+                self.expect(')')?;
+                Ok(factor)
+            },
+            Some('1') => {
+                self.next();
+                Ok((1.0, Quantity::unitless()))
+            },
+            Some('√') => {
+                self.next();
+                let (si_scale, quantity) = self.parse_factor()?;
+                Ok((si_scale.sqrt(), quantity.root(2)))
+            },
+            Some(c) if is_unit_char(c) => {
+                let start = self.pos;
+                while self.peek().is_some_and(is_unit_char) {
+                    self.next();
+                }
+                let name = &self.unit[start..self.pos];
+                let (exp, base_unit) = UnitVal::from_unit_token(name, self.units)?;
+                if base_unit.is_affine() {
+                    return Err(Error::UnitError(format!("'{name}' cannot be combined with other units")));
+                }
+                Ok((10.0_f64.powi(exp) * base_unit.si_scale, base_unit.quantity))
+            },
+            Some(c) => Err(Error::UnitError(format!("Unexpected '{c}' in unit '{}'", self.unit))),
+            None => Err(Error::UnitError(format!("Unit '{}' ended unexpectedly", self.unit))),
+        }
+    }
+}
+
+fn is_unit_char(c: char) -> bool {
+    c.is_alphabetic() || c == '°'
+}
+
+/// Rounds away floating point noise from conversions (e.g. 12.000000000000002 in)
+pub(crate) fn round_significant(value: f64, digits: i32) -> f64 {
+    if value == 0.0 || !value.is_finite() {
+        return value;
+    }
+    let magnitude = value.abs().log10().floor() as i32;
+    let factor = 10.0_f64.powi(digits - 1 - magnitude);
+    (value * factor).round() / factor
+}
+
+
 impl std::fmt::Display for UnitVal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.to_string())
@@ -127,12 +391,15 @@ impl<'a> BaseField<'a> for UnitVal {
 
     fn powf(&self, exp: UnitVal) -> CResult<Self> {
         let exp: f64 = exp.as_scalar()?;
+        if exp == 1.0 {
+            return Ok(self.clone());
+        }
+        self.check_not_absolute("take the power of")?;
         if exp.fract() == 0.0 {
             let exp = exp as i32;
             Ok(self.powi(exp))
-        } else if exp.abs() < 1.0 && (1.0 / exp).fract() == 0.0 {
-            let n  = 1.0 / exp;
-            self.root(UnitVal::scalar(n))
+        } else if let Some(rational_exp) = to_rational(exp) {
+            Ok(UnitVal::new(self.value.powf(exp), self.quantity.pow(rational_exp)))
         } else {
             let value = self.as_scalar()?.powf(exp);
             Ok(UnitVal::scalar(value))
@@ -141,13 +408,11 @@ impl<'a> BaseField<'a> for UnitVal {
 
     fn root(&self, n: Self) -> CResult<Self> {
         let n = n.as_scalar()?;
-        if n.fract() != 0.0 {
+        self.check_not_absolute("take the root of")?;
+        if n.fract() != 0.0 || n == 0.0 {
             Err(Error::UnitError(format!("Cannot take the {n}th root of {self}")))
-        } else if let Ok(new_quantity) = self.quantity.clone().root(n as i32) {
-            let exp = 1.0 / n;
-            Ok(UnitVal::new(self.value.powf(exp), new_quantity))
         } else {
-            Err(Error::UnitError(format!("Cannot take the {n}th root of {self}")))
+            Ok(UnitVal::new(self.value.powf(1.0 / n), self.quantity.root(n as i32)))
         }
     }
 
@@ -158,32 +423,132 @@ impl<'a> BaseField<'a> for UnitVal {
     }
 
     fn sin(&self) -> CResult<Self> {
-        Ok(UnitVal::scalar(self.as_scalar()?.sin()))
+        Ok(UnitVal::scalar(self.as_angle()?.sin()))
     }
     fn cos(&self) -> CResult<Self> {
-        Ok(UnitVal::scalar(self.as_scalar()?.cos()))
+        Ok(UnitVal::scalar(self.as_angle()?.cos()))
     }
     fn tan(&self) -> CResult<Self> {
-        Ok(UnitVal::scalar(self.as_scalar()?.tan()))
+        Ok(UnitVal::scalar(self.as_angle()?.tan()))
+    }
+    fn asin(&self) -> CResult<Self> {
+        Ok(UnitVal::new(self.as_scalar()?.asin(), Quantity::angle()))
+    }
+    fn acos(&self) -> CResult<Self> {
+        Ok(UnitVal::new(self.as_scalar()?.acos(), Quantity::angle()))
+    }
+    fn atan(&self) -> CResult<Self> {
+        Ok(UnitVal::new(self.as_scalar()?.atan(), Quantity::angle()))
+    }
+
+
+    fn abs(&self) -> CResult<Self> {
+        self.check_not_absolute("take the absolute value of")?;
+        Ok(UnitVal::new(self.value.abs(), self.quantity.clone()))
+    }
+
+    /// Unitless, as the sign of a length is the same in every unit
+    fn sign(&self) -> CResult<Self> {
+        self.check_not_absolute("take the sign of")?;
+        Ok(UnitVal::from(if self.value == 0.0 { 0.0 } else { self.value.signum() }))
+    }
+
+    fn im(&self) -> CResult<Self> {
+        Ok(UnitVal::new(0.0, self.quantity.clone()))
+    }
+
+    fn arg(&self) -> CResult<Self> {
+        self.check_not_absolute("take the argument of")?;
+        Ok(UnitVal::new(if self.value < 0.0 { std::f64::consts::PI } else { 0.0 }, Quantity::angle()))
+    }
+
+    /// A difference, so that it can be added to absolute temperatures too
+    fn unit(&self) -> CResult<Self> {
+        Ok(UnitVal::new(1.0, self.quantity.clone()))
+    }
+
+    fn magnitude(&self) -> CResult<f64> {
+        Ok(self.si_value().abs())
+    }
+
+    fn compare(&self, other: &Self) -> CResult<Option<std::cmp::Ordering>> {
+        // Zero is the same in every unit, so e.g. `x < 0` works for lengths too
+        if self.is_scalar() && self.value == 0.0 || other.is_scalar() && other.value == 0.0 {
+            self.check_not_absolute("compare")?;
+            other.check_not_absolute("compare")?;
+            return Ok(self.value.partial_cmp(&other.value));
+        }
+        if self.quantity != other.quantity {
+            return Err(Error::UnitError(format!("Cannot compare {self} with {other}, as they have different units")));
+        }
+        // Absolute temperatures are compared in K
+        Ok((self.value + self.offset).partial_cmp(&(other.value + other.offset)))
+    }
+
+    fn convert(&self, unit: &str, units: &UnitRegistry) -> CResult<Self> {
+        let unit = UnitVal::from_unit_str_in(unit, units)?;
+        if unit.quantity != self.quantity {
+            return Err(Error::UnitError(format!("Cannot convert {} to '{}', the quantities don't match", self, unit.name)));
+        }
+        // Values converted to an affine unit are treated as absolute temperatures
+        let (value, offset) = if unit.is_affine() {
+            (self.si_value() - unit.si_offset, unit.si_offset)
+        } else {
+            (self.si_value(), 0.0)
+        };
+        Ok(UnitVal { value, quantity: self.quantity.clone(), offset, display_unit: Some(unit) })
+    }
+
+    fn define_unit(&self, name: &str) -> CResult<Unit> {
+        self.check_not_absolute("define a unit from")?;
+        if !name.chars().all(is_unit_char) {
+            return Err(Error::UnitError(format!("Unit name '{name}' can only contain letters")));
+        }
+        if self.value == 0.0 || !self.value.is_finite() {
+            return Err(Error::UnitError(format!("Unit '{name}' cannot be defined as {self}")));
+        }
+        Ok(Unit::new(name, self.value, self.quantity.clone()))
+    }
+
+    fn unit_identity(unit: &str, units: &UnitRegistry) -> CResult<Self> {
+        Ok(UnitVal::from_unit(1.0, &UnitVal::from_unit_str_in(unit, units)?))
+    }
+
+    fn plus_minus(&self, _uncertainty: Self) -> CResult<Self> {
+        Err(Error::EvalError("Uncertainties (±) can only be used in uncertain mode".to_string()))
     }
 }
 
 
 impl std::ops::Mul for UnitVal {
-    type Output = Self;
+    type Output = CResult<Self>;
 
     fn mul(self, rhs: Self) -> Self::Output {
+        if self.is_absolute() && rhs.is_absolute() {
+            return Err(Error::UnitError(format!("Cannot multiply two absolute temperatures: {} and {}{}", self, rhs, self.ambiguity_hint())));
+        } else if self.is_absolute() && rhs.is_scalar() {
+            return Ok(self.scale_absolute(rhs.value));
+        } else if rhs.is_absolute() && self.is_scalar() {
+            return Ok(rhs.scale_absolute(self.value));
+        }
+        self.check_not_absolute("multiply")?;
+        rhs.check_not_absolute("multiply")?;
         let value = self.value * rhs.value;
-        UnitVal::new(value, self.quantity + rhs.quantity)
+        Ok(UnitVal::new(value, self.quantity + rhs.quantity))
     }
 }
 
 impl std::ops::Div for UnitVal {
-    type Output = Self;
+    type Output = CResult<Self>;
 
     fn div(self, rhs: Self) -> Self::Output {
+        if self.is_absolute() && rhs.is_scalar() {
+            return Ok(self.scale_absolute(1.0 / rhs.value));
+        }
+        self.check_not_absolute("divide")?;
+        rhs.check_not_absolute("divide by")?;
         let value = self.value / rhs.value;
-        UnitVal::new(value, self.quantity - rhs.quantity)
+        Ok(UnitVal::new(value, self.quantity - rhs.quantity))
     }
 }
 
@@ -194,9 +559,13 @@ impl std::ops::Add for UnitVal {
         if self.quantity != rhs.quantity {
             return Err(Error::UnitError(format!("Cannot add units with different quantities: {:?} and {:?}", self.to_string(), rhs.to_string())));
         }
+        if self.is_absolute() && rhs.is_absolute() {
+            return Err(Error::UnitError(format!("Cannot add two absolute temperatures: {} and {}", self, rhs)));
+        }
         let value = self.value + rhs.value;
+        let offset = self.offset + rhs.offset;
         let quantity = self.quantity;
-        Ok(UnitVal { value, quantity })
+        Ok(UnitVal { value, quantity, offset, display_unit: None })
     }
 }
 
@@ -207,9 +576,17 @@ impl std::ops::Sub for UnitVal {
         if self.quantity != rhs.quantity {
             return Err(Error::UnitError(format!("Cannot subtract units with different quantities: {:?} and {:?}", self.to_string(), rhs.to_string())));
         }
-        let value = self.value - rhs.value;
+        if !self.is_absolute() && rhs.is_absolute() {
+            return Err(Error::UnitError(format!("Cannot subtract an absolute temperature ({}) from a temperature difference ({})", rhs, self)));
+        }
+        // The difference between two absolute temperatures is a temperature difference, so the offsets cancel
+        let (value, offset) = if rhs.is_absolute() {
+            ((self.value - rhs.value) + (self.offset - rhs.offset), 0.0)
+        } else {
+            (self.value - rhs.value, self.offset)
+        };
         let quantity = self.quantity;
-        Ok(UnitVal { value, quantity })
+        Ok(UnitVal { value, quantity, offset, display_unit: None })
     }
 }
 
@@ -273,10 +650,14 @@ mod tests {
                 assert_eq!(UnitVal::new_value(1.0, base_unit).to_string(), "1 kg");
                 continue;
             }
-            for (prefix, _) in prefix_map() {
+            for (prefix, exp) in prefix_map() {
+                // Only engineering prefixes are used for display (e.g. 1 cm is shown as 10 mm)
+                if exp % 3 != 0 || !unit_map()[base_unit].allows_prefix(*exp) {
+                    continue;
+                }
                 let unit_str = format!("{}{}", prefix, base_unit);
                 let val = UnitVal::new_value(1.0, &unit_str);
-                let expected = format!("1 {}", unit_str);
+                let expected = format!("1 {}{}", prefix, unit_map()[base_unit].name);
                 println!("(Expected) {} = {:?} (Received)", expected, val);
                 assert_eq!(expected, val.to_string());
             }
@@ -289,6 +670,9 @@ mod tests {
             ("1 N/kg", "1 m/s^2"),
             ("1 kPa/N", "1000 /m^2"),
             ("0.01 km^2", "10000 m^2"),
+            ("1 hm^2", "10000 m^2"),
+            ("100 km^2", "100 km^2"),
+            ("1 cm", "0.01 m"),
         ];
         for (input, expected) in tests {
             let input = Span::new(input);
@@ -310,4 +694,342 @@ mod tests {
             assert_eq!(response.to_string(), expected);
         }
     }
+
+    #[test]
+    fn test_display_systems() {
+        let us = UnitSystem::named("US").unwrap();
+        let tests = vec![
+            ("1 m", "3.280839895013123 ft"),
+            ("1 yd", "3 ft"),
+            ("1 mi", "5280 ft"),
+            ("1000 ft", "1000 ft"),
+            ("1 lbf", "1 lbf"),
+            ("1 N", "0.2248089430997105 lbf"),
+            ("2 lbf * 4 ft", "8 ft·lbf"),
+            ("1 psi", "1 psi"),
+            ("20 °C", "20 C"),
+            ("1 km to m", "1000 m"),
+        ];
+        for (input, expected) in tests {
+            let response = evaluate_line(Span::new(input), &mut Evaluator::<UnitVal>::new()).unwrap().unwrap();
+            assert_eq!(response.to_string_in(&us), expected);
+        }
+
+        let custom = UnitSystem::custom(&["mi".to_string(), "h".to_string()]).unwrap();
+        let tests = vec![
+            ("60 mph", "60 mi/h"),
+            ("1 kg", "1 kg"),
+            ("1 N", "1 N"),
+        ];
+        for (input, expected) in tests {
+            let response = evaluate_line(Span::new(input), &mut Evaluator::<UnitVal>::new()).unwrap().unwrap();
+            assert_eq!(response.to_string_in(&custom), expected);
+        }
+        UnitSystem::named("Imperial").unwrap_err();
+        UnitSystem::custom(&["xyz".to_string()]).unwrap_err();
+        UnitSystem::custom(&["°C".to_string()]).unwrap_err();
+    }
+
+    #[test]
+    fn test_rational_exponents() {
+        let tests = vec![
+            ("(4 m^3)^(1/2)", "2 m^(3/2)"),
+            ("\\sqrt{9 Hz}", "3 /s^(1/2)"),
+            ("2 V / \\sqrt{1 Hz}", "2 V·s^(1/2)"),
+            ("1 MPa * \\sqrt{1 m}", "1000000 Pa·m^(1/2)"),
+            ("(\\sqrt{4 m})^2", "4 m"),
+            ("(8 m^3)^(1/3)", "2 m"),
+            ("1 V/√Hz to nV/√Hz", "1000000000 nV/√Hz"),
+            ("1 MPa \\cdot \\sqrt{m} to MPa·m^(1/2)", "1 MPa·m^(1/2)"),
+            ("1 V \\cdot \\sqrt{s} to V/\\sqrt{Hz}", "1 V/√Hz"),
+            ("1 V \\cdot \\sqrt{s} to V/Hz^{\\frac{1}{2}}", "1 V/Hz^(1/2)"),
+        ];
+        for (input, expected) in tests {
+            let response = evaluate_line(Span::new(input), &mut Evaluator::<UnitVal>::new()).unwrap().unwrap();
+            assert_eq!(response.to_string(), expected);
+        }
+        let composite = UnitVal::from_unit_str("√(m^3)").unwrap();
+        assert_eq!(composite.quantity, Quantity::length().pow(Rational32::new(3, 2)));
+        // Only affine units keep an offset, and they can't have prefixes
+        assert!(!UnitVal::from_unit_str("km").unwrap().is_affine());
+        assert_eq!(UnitVal::from_unit_str("°C").unwrap().si_offset, 273.15);
+        assert!(UnitVal::from_unit_str("m°C").is_err());
+        for invalid in ["m^1/2", "m^(1/0)", "√"] {
+            assert!(!UnitVal::is_valid_unit(invalid), "{invalid} should be invalid");
+        }
+        // Irrational powers only make sense for scalars
+        evaluate_line(Span::new("(1 m)^pi"), &mut Evaluator::<UnitVal>::new()).unwrap_err();
+    }
+
+    #[test]
+    fn test_angles() {
+        let tests = vec![
+            ("\\sin(30 deg)", "0.49999999999999994"),
+            ("\\cos(0.5 rad)", "0.8775825618903728"),
+            ("\\tan(45°)", "0.9999999999999999"),
+            ("\\sin(1)", "0.8414709848078965"),
+            ("180 deg to rad", "3.14159265359 rad"),
+            ("1 rev to deg", "360 deg"),
+            ("30 arcmin to deg", "0.5 deg"),
+            ("1 mrad", "1 mrad"),
+            ("\\arcsin(1) to deg", "90 deg"),
+            ("\\arctan(1)", "0.7853981633974483 rad"),
+            ("2 rad / 1 s", "2 rad/s"),
+            ("1 cd * 1 sr", "1 cd·rad^2"),
+            ("1 lm to cd*sr", "1 cd*sr"),
+        ];
+        for (input, expected) in tests {
+            let response = evaluate_line(Span::new(input), &mut Evaluator::<UnitVal>::new()).unwrap().unwrap();
+            assert_eq!(response.to_string(), expected);
+        }
+        let degrees = UnitSystem::default().prefer("deg").unwrap();
+        let response = evaluate_line(Span::new("\\arccos(0.5)"), &mut Evaluator::<UnitVal>::new()).unwrap().unwrap();
+        assert_eq!(response.to_string_in(&degrees), "60.00000000000001 deg");
+        UnitSystem::default().prefer("xyz").unwrap_err();
+
+        let invalid = vec![
+            "\\sin(1 m)",
+            "\\arcsin(1 deg)",
+            "1 deg + 1",
+            "1 rad to m",
+        ];
+        for input in invalid {
+            evaluate_line(Span::new(input), &mut Evaluator::<UnitVal>::new()).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_temperatures() {
+        let tests = vec![
+            ("20 C", "20 C"),
+            ("20 C + 5 K", "25 C"),
+            ("5 K + 20 C", "25 C"),
+            ("30 C - 5 K", "25 C"),
+            ("100 C - 20 C", "80 K"),
+            ("2 * 10 C", "20 C"),
+            ("100 F / 2", "50 F"),
+            ("20 C - 0 C", "20 K"),
+        ];
+        for (input, expected) in tests {
+            let input = Span::new(input);
+            let response = evaluate_line(input, &mut Evaluator::<UnitVal>::new()).unwrap().unwrap();
+            assert_eq!(response.to_string(), expected);
+        }
+        let invalid = vec![
+            "20 C * 30 C",
+            "20 C + 30 C",
+            "5 K - 20 C",
+            "20 C * 1 m",
+            "1 m / 20 C",
+            "(20 C)^2",
+        ];
+        for input in invalid {
+            let input = Span::new(input);
+            evaluate_line(input, &mut Evaluator::<UnitVal>::new()).unwrap_err();
+        }
+        assert!((UnitVal::new_value(212.0, "F").si_value() - 373.15).abs() < 1e-9);
+        assert!((UnitVal::new_value(-40.0, "F").si_value() - UnitVal::new_value(-40.0, "C").si_value()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_explicit_conversions() {
+        let tests = vec![
+            ("1 km to m", "1000 m"),
+            ("1 ft to in", "12 in"),
+            ("2 in in mm", "50.8 mm"),
+            ("1 bar to kPa", "100 kPa"),
+            ("1 kpsi to psi", "1000 psi"),
+            ("1 psi to kPa", "6.89475729317 kPa"),
+            ("2 min to s", "120 s"),
+            ("1 ft^2 to in^2", "144 in^2"),
+            ("3 kN * 2 m to kN*m", "6 kN*m"),
+            ("1 N\\cdot m to kN\\cdot m", "0.001 kN*m"),
+            ("36 km / 1 s to m/s", "36000 m/s"),
+            ("100 C to F", "212 F"),
+            ("20 C to K", "293.15 K"),
+            ("300 K to C", "26.85 C"),
+            ("x = 1 lbf to N", "4.44822161526 N"),
+            ("3 ft in m", "0.9144 m"),
+            ("5 in to mm", "127 mm"),
+        ];
+        for (input, expected) in tests {
+            let input = Span::new(input);
+            let response = evaluate_line(input, &mut Evaluator::<UnitVal>::new()).unwrap().unwrap();
+            assert_eq!(response.to_string(), expected);
+        }
+        let invalid = vec![
+            "1 km to s",
+            "1 m to C",
+            "20 C to C*m",
+            "1 m to m^x",
+            // The first "in" is inches, so there's nothing left to convert with
+            "5 in mm",
+            "to = 3",
+        ];
+        for input in invalid {
+            let input = Span::new(input);
+            evaluate_line(input, &mut Evaluator::<UnitVal>::new()).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_composite_units() {
+        let tests = vec![
+            ("kg*m/s^2", "N"),
+            ("kg·m·s^-2", "N"),
+            ("kg m s^(-2)", "N"),
+            ("N/(m^2*K)", "Pa/K"),
+            ("1/s", "Hz"),
+            ("(km/s)^2", "Mm*m/s^2"),
+        ];
+        for (composite, equivalent) in tests {
+            let composite = UnitVal::from_unit_str(composite).unwrap();
+            let equivalent = UnitVal::from_unit_str(equivalent).unwrap();
+            assert_eq!(composite.quantity, equivalent.quantity);
+            assert!((composite.si_scale - equivalent.si_scale).abs() < 1e-9 * equivalent.si_scale);
+        }
+        for invalid in ["kg*", "m/(s", "m^x", "C*m", "xyz/s", "m)"] {
+            assert!(!UnitVal::is_valid_unit(invalid), "{invalid} should be invalid");
+        }
+
+        let tests = vec![
+            ("5 N·m", "5 J"),
+            ("20 °C", "20 C"),
+            ("1 Pa to \\frac{N}{m^{2}}", "1 N/m^2"),
+            ("1 Pa\\ in\\ \\frac{kN}{\\left(mm\\cdot m\\right)}", "0.000001 kN/(mm*m)"),
+            ("1 Hz to s^{-1}", "1 s^-1"),
+        ];
+        for (input, expected) in tests {
+            let input = Span::new(input);
+            let response = evaluate_line(input, &mut Evaluator::<UnitVal>::new()).unwrap().unwrap();
+            assert_eq!(response.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_derived_units() {
+        let tests = vec![
+            ("2 N * 3 m", "6 J"),
+            ("2 V * 3 A", "6 W"),
+            ("6 J / 2 s", "3 W"),
+            ("6 coulomb / 2 s", "3 A"),
+            ("2 A * 3 s", "6 C"),
+            ("1 coulomb / 1 V", "1 F"),
+            ("5 mC / 1 ms", "5 A"),
+            ("10 nF * 100 V", "1 μC"),
+            ("1 uF", "1 μF"),
+            ("1 \u{b5}F + 1 \u{3bc}F", "2 μF"),
+            ("1 μF + 500 nF", "1.5 μF"),
+            ("1500 pF to nF", "1.5 nF"),
+            ("1 Ω * 1 m", "1 m·Ω"),
+            ("1 kWh", "3.6 MJ"),
+            ("1 L", "0.001 m^3"),
+            ("1 ohm * 2 A", "2 V"),
+            ("2 A to V/ohm", "2 V/ohm"),
+            ("1 h to min", "60 min"),
+            ("1 gal to L", "3.785411784 L"),
+            // Heat transfer coefficient, thermal conductivity and specific heat
+            ("1 W/(m^2*K)", "1 W/(K·m^2)"),
+            ("1 W/(m*K)", "1 W/(K·m)"),
+            ("1 J/(kg*K)", "1 J/(K·kg)"),
+            ("1 W/m^2", "1 W/m^2"),
+            ("1 m/s^2", "1 m/s^2"),
+        ];
+        for (input, expected) in tests {
+            let input = Span::new(input);
+            let response = evaluate_line(input, &mut Evaluator::<UnitVal>::new()).unwrap().unwrap();
+            assert_eq!(response.to_string(), expected);
+        }
+
+        // C and F are temperatures, so using them as coulombs or farads explains the clash
+        for input in ["6 C / 2 s", "2 F * 3 V", "20 C * 30 C"] {
+            let error = evaluate_line(Span::new(input), &mut Evaluator::<UnitVal>::new()).unwrap_err();
+            assert!(error.to_string().contains("write coulomb or farad"), "{input}: {error}");
+        }
+    }
+
+    #[test]
+    fn test_user_defined_units() {
+        let mut eval = Evaluator::<UnitVal>::new();
+        let lines = vec![
+            ("unit furlong = 201.168 m", "201.168 m"),
+            ("unit fortnight = 14 d", "1.2096 Ms"),
+            ("unit rpm = 1/min", "0.016666666666666666 /s"),
+            ("2 furlong to m", "402.336 m"),
+            ("1 km to furlong", "4.9709695379 furlong"),
+            ("1 furlong/fortnight to mm/h", "598.714285714 mm/h"),
+            ("3 kfurlong to km", "603.504 km"),
+            ("60 rpm to Hz", "1 Hz"),
+        ];
+        for (input, expected) in lines {
+            let response = evaluate_line(Span::new(input), &mut eval).unwrap().unwrap();
+            assert_eq!(response.to_string(), expected);
+        }
+        let invalid = vec![
+            "unit furlong = 1 m",
+            "unit m = 2 m",
+            "unit x2 = 2 m",
+            "unit warm = 20 °C",
+        ];
+        for input in invalid {
+            evaluate_line(Span::new(input), &mut eval).unwrap_err();
+        }
+        // Units don't leak into other documents
+        evaluate_line(Span::new("1 furlong"), &mut Evaluator::<UnitVal>::new()).unwrap_err();
+    }
+
+    #[test]
+    fn test_prefix_resolution() {
+        let registry = UnitRegistry::new();
+        for name in unit_map().keys() {
+            // The unit that a prefix is applied to, e.g. farads for the F in "uF"
+            let base_unit = registry.get_prefixed(name).unwrap();
+            for (prefix, exp) in prefixes() {
+                let unit = format!("{prefix}{name}");
+                let resolved = UnitVal::from_unit_token(&unit, &registry);
+                if let Some(exact) = unit_map().get(unit.as_str()) {
+                    assert_eq!(resolved.unwrap(), (0, exact.clone()), "{unit} should resolve to itself");
+                } else if !base_unit.allows_prefix(exp) {
+                    assert!(resolved.is_err(), "{unit} should not accept a prefix");
+                } else {
+                    assert_eq!(resolved.unwrap(), (exp, base_unit.clone()), "{unit} resolved incorrectly");
+                }
+            }
+        }
+
+        // No built-in unit can be split two ways, but user defined units can make that happen
+        let mut registry = UnitRegistry::new();
+        registry.define(Unit::new("am", 2.0, Quantity::length()).with_prefixes(ALL_PREFIXES)).unwrap();
+        let error = UnitVal::from_unit_token("dam", &registry).unwrap_err();
+        assert_eq!(error.to_string(), "unit error: Ambiguous unit 'dam', it could be 'da' + 'm' or 'd' + 'am'");
+        assert_eq!(UnitVal::from_unit_token("kam", &registry).unwrap(), (3, registry.get("am").unwrap().clone()));
+
+        let tests = vec![
+            ("mol", 0, "mol"),
+            ("mmol", -3, "mol"),
+            ("psi", 0, "psi"),
+            ("kpsi", 3, "psi"),
+            ("min", 0, "min"),
+            ("mi", 0, "mi"),
+            ("Pa", 0, "Pa"),
+            ("mPa", -3, "Pa"),
+            ("hPa", 2, "Pa"),
+            ("cm", -2, "m"),
+            ("dam", 1, "m"),
+            ("μs", -6, "s"),
+            ("us", -6, "s"),
+            ("\u{b5}s", -6, "s"),
+            ("fs", -15, "s"),
+            ("EHz", 18, "Hz"),
+            ("mC", -3, "coulomb"),
+            ("uF", -6, "farad"),
+            ("pF", -12, "farad"),
+        ];
+        for (unit, exp, base_unit) in tests {
+            assert_eq!(UnitVal::from_unit_token(unit, &UnitRegistry::new()).unwrap(), (exp, unit_map()[base_unit].clone()));
+        }
+        for invalid in ["Mkg", "cs", "kft", "cC", "cPa"] {
+            assert!(UnitVal::from_unit_token(invalid, &UnitRegistry::new()).is_err(), "{invalid} should be invalid");
+        }
+    }
 }
@@ -3,6 +3,8 @@ use crate::types::CResult;
 
 use bimap::BiMap;
 use itertools::Itertools;
+use num_rational::Rational32;
+use num_traits::{One, Signed, Zero};
 use std::{collections::HashMap, sync::OnceLock, vec};
 
 
@@ -10,74 +12,124 @@ use std::{collections::HashMap, sync::OnceLock, vec};
 pub struct Unit {
     pub name: String,
     pub si_scale: f64,
+    /// Offset (in SI units) of the unit's zero point, only non-zero for affine units like Celsius
+    pub si_offset: f64,
     pub quantity: Quantity,
-    // TODO: Add optional max and min prefixes (e.g. can't have megametres)
+    /// Exponents of the prefixes that can be applied to the unit (e.g. no centiseconds or megakilograms)
+    pub prefixes: &'static [i32],
 }
 
 impl Unit {
     pub fn new(name: &str, si_scale: f64, quantity: Quantity) -> Self {
-        Unit { name: name.to_string(), si_scale, quantity }
+        Unit { name: name.to_string(), si_scale, si_offset: 0.0, quantity, prefixes: ENGINEERING_PREFIXES }
     }
 
-    pub fn compose(units: &HashMap<&str, i32>, quantity: &Quantity) -> Self {
+    /// Creates a unit whose zero point doesn't line up with the SI unit's (e.g. 0 C = 273.15 K)
+    pub fn new_affine(name: &str, si_scale: f64, si_offset: f64, quantity: Quantity) -> Self {
+        Unit { name: name.to_string(), si_scale, si_offset, quantity, prefixes: NO_PREFIXES }
+    }
+
+    pub fn with_prefixes(mut self, prefixes: &'static [i32]) -> Self {
+        self.prefixes = prefixes;
+        self
+    }
+
+    pub fn allows_prefix(&self, exp: i32) -> bool {
+        self.prefixes.contains(&exp)
+    }
+
+    pub fn is_affine(&self) -> bool {
+        self.si_offset != 0.0
+    }
+
+    pub fn compose(units: &HashMap<&str, Rational32>, quantity: &Quantity) -> Self {
         // TODO: Doesn't check that the unit map matches the quantity
         let unit_vec = units.iter().map(|(name, exp)| (unit_map().get(name).unwrap(), *exp)).collect_vec();
-        let si_scale  = unit_vec.iter().fold(1.0, |acc, (unit, exp)| acc * (unit.si_scale.powi(*exp)));
+        let si_scale  = unit_vec.iter().fold(1.0, |acc, (unit, exp)| acc * pow_rational(unit.si_scale, *exp));
         let name = Unit::get_unit_str(units);
         Unit::new(&name, si_scale, quantity.clone())
     }
 
-    fn get_unit_str(units: &HashMap<&str, i32>) -> String {
-        let mut unit = String::new();
-        let mut seen_negatives = false;
-        for (base_unit, power) in units.iter().sorted() {
-            if *power < 0 && !seen_negatives {
-                seen_negatives = true;
-                unit.push_str("/");
-            }
-            // TODO: If there are only negative exponents, show them as negatives instead of /x
+    fn get_unit_str(units: &HashMap<&str, Rational32>) -> String {
+        let format_unit = |(base_unit, power): (&&str, &Rational32)| {
+            // Shown by the unit's symbol, e.g. C for coulomb
+            let base_unit = unit_map().get(base_unit).map_or(*base_unit, |unit| unit.name.as_str());
             let power = power.abs();
-            if power == 1 {
-                unit.push_str(base_unit);
+            if power.is_one() {
+                base_unit.to_string()
+            } else if power.is_integer() {
+                format!("{}^{}", base_unit, power)
             } else {
-                unit.push_str(&format!("{}^{}", base_unit, power));
+                format!("{}^({})", base_unit, power)
             }
+        };
+        let numerator = units.iter().filter(|(_, power)| power.is_positive()).sorted().map(format_unit).join("·");
+        let denominator = units.iter().filter(|(_, power)| power.is_negative()).sorted().map(format_unit).collect_vec();
+        // TODO: If there are only negative exponents, show them as negatives instead of /x
+        match denominator.len() {
+            0 => numerator,
+            1 => format!("{}/{}", numerator, denominator[0]),
+            _ => format!("{}/({})", numerator, denominator.join("·")),
         }
-        unit
     }
 
-    /// Returns a map of string units and their exponents that summarize the given quantity
-    pub fn compile_used_units(quantity: &Quantity, system: &str) -> CResult<HashMap<&'static str, i32>> {
+    /// Returns a map of string units and their exponents that summarize the given quantity.
+    /// 
+    /// Units are greedily taken out of the quantity, picking the one that leaves the fewest exponents in total: its own
+    /// and those of the base units the rest would need (e.g. W/(m^2·K) with 4 rather than kg/(K·s^3) with 5).
+    /// Fractional exponents that are left over are given to the system's base units (e.g. MPa·√m is shown as Pa·m^(1/2))
+    pub fn compile_used_units(quantity: &Quantity, system: &UnitSystem) -> CResult<HashMap<&'static str, Rational32>> {
         let max_iter = 5;
-        let system = &unit_system()[system];
         let mut avail_units = unit_map().clone();
         let mut used_units = HashMap::new();
         let mut iterations = 0;
         let mut current_quantity = quantity.clone();
 
-        while current_quantity.dimensionality() != 0 && iterations < max_iter {
+        while !current_quantity.dimensionality().is_zero() && iterations < max_iter {
             let mut best_unit = ""; // TODO: Allow multiple potential matches (e.g. s and Hz)
-            let mut best_match = 0;
+            let mut best_match = (Rational32::zero(), 0);
             let mut remaining_quantity = current_quantity.clone();
             let mut exp = 0;
-            for (name, unit) in avail_units.clone().iter() {
-                if system.contains(name) && unit.quantity.is_subset(&current_quantity) {
-                    let match_score = unit.quantity.quantity.iter().map(|x| x.abs()).sum();
-                    if match_score > best_match {
-                        best_unit = name;
-                        best_match = match_score;
-                        exp = unit.quantity.find_subset_power(&current_quantity).expect("Exponent not found");
-                        remaining_quantity = Quantity::new(unit.quantity.zip(&current_quantity).map(|(a, b)| b - a * exp).collect());
-                    }
-                } else {
+            // Named units can bring in dimensions the quantity doesn't have when that leaves fewer exponents, as in
+            // W/(m^2·K). Simple quantities without a mass read better in base units though, e.g. m/s^2 rather than N/kg
+            let has_mass = Quantity::mass().dimensions_within(&current_quantity);
+            let allow_new_dimensions = has_mass || current_quantity.dimension_count() >= 3;
+            // Named derived units cover more dimensions, so they win over their base units (e.g. J over N·m).
+            // Remaining ties are broken by the order of the system so the result is deterministic
+            let candidates = avail_units.clone().into_iter().sorted_by_key(|(name, _)| system.position(name));
+            for (name, unit) in candidates {
+                let subset_power = unit.quantity.find_subset_power(&current_quantity, allow_new_dimensions);
+                if system.position(name).is_none() || unit.is_affine() || subset_power.is_none() {
                     avail_units.remove(name); // No need to check it ever again
+                    continue;
+                }
+                let (unit_exp, remaining) = subset_power.unwrap();
+                let reduction = current_quantity.dimensionality() - remaining.dimensionality() - unit_exp.abs();
+                if reduction <= Rational32::zero() && !unit.quantity.dimensions_within(&current_quantity) {
+                    continue;
+                }
+                let match_score = (reduction, unit.quantity.dimensionality().to_integer());
+                // On a tie, a positive exponent reads better (S rather than /Ω)
+                if match_score > best_match || (match_score == best_match && exp < 0 && unit_exp > 0) {
+                    best_unit = name;
+                    best_match = match_score;
+                    exp = unit_exp;
+                    remaining_quantity = remaining;
                 }
             }
-            used_units.insert(best_unit,  exp);
+            if best_unit.is_empty() {
+                for (base_quantity, base_exp) in current_quantity.base_exponents() {
+                    let base_unit = system.base_unit(&base_quantity).ok_or_else(|| Error::UnitError(format!("Unit system has no unit for {base_quantity:?}")))?;
+                    *used_units.entry(base_unit).or_insert(Rational32::zero()) += base_exp;
+                }
+                break;
+            }
+            *used_units.entry(best_unit).or_insert(Rational32::zero()) += exp;
             iterations += 1;
             current_quantity = remaining_quantity;
             // println!("Cost at end of round: {} ({current_quantity:?})", Unit::dimensionality(&current_quantity));
         }
+        used_units.retain(|_, exp| !exp.is_zero());
         if iterations >= max_iter {
             Err(Error::UnitError(format!("Unable to compile units to string given {quantity:?}. {current_quantity:?} still remains")))
         } else {
@@ -86,89 +138,127 @@ impl Unit {
     }
 
     pub fn to_si(&self, value: f64) -> f64 {
-        value * self.si_scale
+        value * self.si_scale + self.si_offset
     }
 
     pub fn from_si(&self, value: f64) -> f64 {
-        value / self.si_scale
+        (value - self.si_offset) / self.si_scale
     }
 }
 
 
+/// Exponents of the SI base dimensions in the order [length, time, mass, current, temperature, amount, luminosity],
+/// followed by angle. Angles are dimensionless in SI, but are kept separate so that e.g. degrees can't be mixed up with scalars.
+/// 
+/// Exponents are rational so that roots (e.g. noise spectral density in V/√Hz) keep their units
 #[derive(Debug, Clone, PartialEq)]
 pub struct Quantity {
-    quantity: vec::Vec<i32>,
+    quantity: vec::Vec<Rational32>,
 }
 
 impl Quantity {
-    pub fn new(quantity: vec::Vec<i32>) -> Self {
-        if quantity.len() != 7 {
-            panic!("Invalid quantity. Should have a length of 7: {:?}", quantity)
+    pub fn new<E: Into<Rational32>>(quantity: vec::Vec<E>) -> Self {
+        if quantity.len() != 8 {
+            panic!("Invalid quantity. Should have a length of 8: {} given", quantity.len())
         }
-        Quantity { quantity }
+        Quantity { quantity: quantity.into_iter().map(Into::into).collect() }
     }
 
-    pub fn zip<'a>(&'a self, q2: &'a Self) -> impl Iterator<Item = (&'a i32, &'a i32)> {
+    pub fn zip<'a>(&'a self, q2: &'a Self) -> impl Iterator<Item = (&'a Rational32, &'a Rational32)> {
         self.quantity.iter().zip(q2.quantity.iter())
     }
 
-    pub fn map(&self, f: impl Fn(i32) -> i32) -> Self {
+    pub fn map(&self, f: impl Fn(Rational32) -> Rational32) -> Self {
         Quantity::new(self.quantity.iter().map(|x| f(*x)).collect())
     }
 
-    pub fn dimensionality(&self) -> i32 {
+    pub fn dimensionality(&self) -> Rational32 {
         self.quantity.iter().map(|x| x.abs()).sum()
     }
 
-    pub fn is_subset(&self, q2: &Quantity) -> bool {
-        let sign = match self.find_subset_power(q2) {
-            Some(e) => e.signum(),
-            None => return false
-        };
-        self.zip(q2).all(|(sub_unit, set_unit)| {
-            let sub_unit = *sub_unit;
-            let set_unit = *set_unit;
-            // If the signs are consistently the same (or consistently opposite) and the sub_unit is less than the set_unit
-            let consistent_sign = (sub_unit * sign).signum() == set_unit.signum();
-            let not_too_big = sub_unit.abs() <= set_unit.abs();
-            (sub_unit == 0) || (consistent_sign && not_too_big)
-        })
+    /// Whether the quantity only uses dimensions that are also used by `q2`
+    pub fn dimensions_within(&self, q2: &Quantity) -> bool {
+        self.zip(q2).all(|(a, b)| a.is_zero() || !b.is_zero())
     }
 
-    pub fn find_subset_power(&self, larger_quantity: &Quantity) -> Option<i32> {
-        let mut possible_exps = vec![];
-        for (a, b) in self.zip(larger_quantity) {
-            if *a != 0 && *b != 0 {
-                possible_exps.push(b / a);
-            }
+    /// Number of base dimensions that the quantity uses, e.g. 3 for W/(m^2·K)
+    pub fn dimension_count(&self) -> usize {
+        self.quantity.iter().filter(|exp| !exp.is_zero()).count()
+    }
+
+    /// The whole power of `self` that leaves the smallest remainder when taken out of `larger_quantity`,
+    /// along with that remainder (e.g. m for m^2·s gives (2, s)). None if no power reduces the dimensionality,
+    /// or if `self` uses dimensions that `larger_quantity` doesn't and those aren't allowed
+    pub fn find_subset_power(&self, larger_quantity: &Quantity, allow_new_dimensions: bool) -> Option<(i32, Quantity)> {
+        if !allow_new_dimensions && !self.dimensions_within(larger_quantity) {
+            return None;
         }
-        possible_exps.iter().min().copied()
+        let remainder = |exp: i32| Quantity::new(self.zip(larger_quantity).map(|(a, b)| b - a * exp).collect());
+        self.zip(larger_quantity)
+            .filter(|(a, _)| !a.is_zero())
+            .flat_map(|(a, b)| {
+                let ratio = b / a;
+                [ratio.floor().to_integer(), ratio.ceil().to_integer()]
+            })
+            .filter(|exp| *exp != 0)
+            .unique()
+            .map(|exp| (exp, remainder(exp)))
+            .filter(|(_, remaining)| remaining.dimensionality() < larger_quantity.dimensionality())
+            .min_by_key(|(exp, remaining)| (remaining.dimensionality(), exp.abs()))
     }
 
     pub fn powi(&self, n: i32) -> Self {
         self.map(|a| a * n)
     }
 
-    pub fn root(&self, n: i32) -> Result<Self, &str> {
-        for magnitude in self.quantity.iter() {
-            if magnitude % n != 0 {
-                return Err("Failed to take the root of a quantity");
-            }
-        }
-        Ok(self.map(|a| a / n))
+    pub fn pow(&self, exp: Rational32) -> Self {
+        self.map(|a| a * exp)
+    }
+
+    pub fn root(&self, n: i32) -> Self {
+        self.map(|a| a / n)
     }
 
-    pub fn length() -> Self { Quantity::new(vec![1, 0, 0, 0, 0, 0, 0]) }
-    pub fn time() -> Self { Quantity::new(vec![0, 1, 0, 0, 0, 0, 0]) }
-    pub fn frequency() -> Self { Quantity::new(vec![0, -1, 0, 0, 0, 0, 0]) }
-    pub fn mass() -> Self { Quantity::new(vec![0, 0, 1, 0, 0, 0, 0]) }
-    pub fn current() -> Self { Quantity::new(vec![0, 0, 0, 1, 0, 0, 0]) }
-    pub fn temp() -> Self { Quantity::new(vec![0, 0, 0, 0, 1, 0, 0]) }
-    pub fn amount() -> Self { Quantity::new(vec![0, 0, 0, 0, 0, 1, 0]) }
-    pub fn lumenous() -> Self { Quantity::new(vec![0, 0, 0, 0, 0, 0, 1]) }
-    pub fn force() -> Self { Quantity::new(vec![1, -2, 1, 0, 0, 0, 0]) }
-    pub fn pressure() -> Self { Quantity::new(vec![-1, -2, 1, 0, 0, 0, 0]) }
-    pub fn unitless() -> Quantity { Quantity::new(vec![0, 0, 0, 0, 0, 0, 0]) }
+    /// Exponent of each base dimension, for quantities that can't be written with whole powers of named units
+    pub fn base_exponents(&self) -> impl Iterator<Item = (Quantity, Rational32)> + '_ {
+        self.quantity.iter().enumerate().filter(|(_, exp)| !exp.is_zero()).map(|(i, exp)| {
+            let mut base = vec![0; 8];
+            base[i] = 1;
+            (Quantity::new(base), *exp)
+        })
+    }
+
+    pub fn length() -> Self { Quantity::new(vec![1, 0, 0, 0, 0, 0, 0, 0]) }
+    pub fn time() -> Self { Quantity::new(vec![0, 1, 0, 0, 0, 0, 0, 0]) }
+    pub fn frequency() -> Self { Quantity::new(vec![0, -1, 0, 0, 0, 0, 0, 0]) }
+    pub fn mass() -> Self { Quantity::new(vec![0, 0, 1, 0, 0, 0, 0, 0]) }
+    pub fn current() -> Self { Quantity::new(vec![0, 0, 0, 1, 0, 0, 0, 0]) }
+    pub fn temp() -> Self { Quantity::new(vec![0, 0, 0, 0, 1, 0, 0, 0]) }
+    pub fn amount() -> Self { Quantity::new(vec![0, 0, 0, 0, 0, 1, 0, 0]) }
+    pub fn lumenous() -> Self { Quantity::new(vec![0, 0, 0, 0, 0, 0, 1, 0]) }
+    pub fn area() -> Self { Quantity::new(vec![2, 0, 0, 0, 0, 0, 0, 0]) }
+    pub fn volume() -> Self { Quantity::new(vec![3, 0, 0, 0, 0, 0, 0, 0]) }
+    pub fn velocity() -> Self { Quantity::new(vec![1, -1, 0, 0, 0, 0, 0, 0]) }
+    pub fn force() -> Self { Quantity::new(vec![1, -2, 1, 0, 0, 0, 0, 0]) }
+    pub fn pressure() -> Self { Quantity::new(vec![-1, -2, 1, 0, 0, 0, 0, 0]) }
+    pub fn energy() -> Self { Quantity::new(vec![2, -2, 1, 0, 0, 0, 0, 0]) }
+    pub fn power() -> Self { Quantity::new(vec![2, -3, 1, 0, 0, 0, 0, 0]) }
+    pub fn charge() -> Self { Quantity::new(vec![0, 1, 0, 1, 0, 0, 0, 0]) }
+    pub fn voltage() -> Self { Quantity::new(vec![2, -3, 1, -1, 0, 0, 0, 0]) }
+    pub fn resistance() -> Self { Quantity::new(vec![2, -3, 1, -2, 0, 0, 0, 0]) }
+    pub fn conductance() -> Self { Quantity::new(vec![-2, 3, -1, 2, 0, 0, 0, 0]) }
+    pub fn capacitance() -> Self { Quantity::new(vec![-2, 4, -1, 2, 0, 0, 0, 0]) }
+    pub fn magnetic_flux() -> Self { Quantity::new(vec![2, -2, 1, -1, 0, 0, 0, 0]) }
+    pub fn magnetic_flux_density() -> Self { Quantity::new(vec![0, -2, 1, -1, 0, 0, 0, 0]) }
+    pub fn inductance() -> Self { Quantity::new(vec![2, -2, 1, -2, 0, 0, 0, 0]) }
+    pub fn luminous_flux() -> Self { Quantity::new(vec![0, 0, 0, 0, 0, 0, 1, 2]) }
+    pub fn illuminance() -> Self { Quantity::new(vec![-2, 0, 0, 0, 0, 0, 1, 2]) }
+    pub fn specific_energy() -> Self { Quantity::new(vec![2, -2, 0, 0, 0, 0, 0, 0]) }
+    pub fn catalytic_activity() -> Self { Quantity::new(vec![0, -1, 0, 0, 0, 1, 0, 0]) }
+    pub fn concentration() -> Self { Quantity::new(vec![-3, 0, 0, 0, 0, 1, 0, 0]) }
+    pub fn angle() -> Self { Quantity::new(vec![0, 0, 0, 0, 0, 0, 0, 1]) }
+    pub fn solid_angle() -> Self { Quantity::new(vec![0, 0, 0, 0, 0, 0, 0, 2]) }
+    pub fn unitless() -> Quantity { Quantity::new(vec![0, 0, 0, 0, 0, 0, 0, 0]) }
 }
 
 impl std::ops::Add for Quantity {
@@ -188,60 +278,257 @@ impl std::ops::Sub for Quantity {
     }
 }
 
+/// Raises a value to a rational power, e.g. for the scale of km^(1/2)
+pub fn pow_rational(value: f64, exp: Rational32) -> f64 {
+    if exp.is_integer() {
+        value.powi(exp.to_integer())
+    } else {
+        value.powf(*exp.numer() as f64 / *exp.denom() as f64)
+    }
+}
+
+/// The simplest fraction (with a denominator of at most 12) that is equal to the value, e.g. 0.5 -> 1/2
+pub fn to_rational(value: f64) -> Option<Rational32> {
+    (1..=12).find_map(|denom| {
+        let numer = (value * denom as f64).round();
+        let is_exact = (value * denom as f64 - numer).abs() < 1e-9 && numer.abs() < i32::MAX as f64;
+        is_exact.then(|| Rational32::new(numer as i32, denom))
+    })
+}
+
+pub const ALL_PREFIXES: &[i32] = &[-18, -15, -12, -9, -6, -3, -2, -1, 1, 2, 3, 6, 9, 12, 15, 18];
+/// Prefixes that are multiples of 1000
+pub const ENGINEERING_PREFIXES: &[i32] = &[-18, -15, -12, -9, -6, -3, 3, 6, 9, 12, 15, 18];
+pub const NO_PREFIXES: &[i32] = &[];
+
 // Implementation rom https://crates.io/crates/lazy_static
-pub fn prefix_map() -> &'static BiMap<char, i32> {
-    static HASHMAP: OnceLock<BiMap<char, i32>> = OnceLock::new();
+pub fn prefix_map() -> &'static BiMap<&'static str, i32> {
+    static HASHMAP: OnceLock<BiMap<&str, i32>> = OnceLock::new();
     HASHMAP.get_or_init(|| {
         let mut m = BiMap::new();
-        m.insert('p', -12);
-        m.insert('n', -9);
-        m.insert('u', -6);
-        m.insert('m', -3);
-        m.insert('k', 3);
-        m.insert('M', 6);
-        m.insert('G', 9);
-        m.insert('T', 12);
+        m.insert("a", -18);
+        m.insert("f", -15);
+        m.insert("p", -12);
+        m.insert("n", -9);
+        m.insert("μ", -6);
+        m.insert("m", -3);
+        m.insert("c", -2);
+        m.insert("d", -1);
+        m.insert("da", 1);
+        m.insert("h", 2);
+        m.insert("k", 3);
+        m.insert("M", 6);
+        m.insert("G", 9);
+        m.insert("T", 12);
+        m.insert("P", 15);
+        m.insert("E", 18);
         m
     })
 }
 
+/// All accepted spellings of the prefixes, including ones that are easier to type (e.g. "u" for micro) and the micro sign "µ" that keyboards produce
+pub fn prefixes() -> impl Iterator<Item = (&'static str, i32)> {
+    prefix_map().iter().map(|(prefix, exp)| (*prefix, *exp)).chain([("u", -6), ("µ", -6)])
+}
+
 pub fn unit_map() -> &'static HashMap<&'static str, Unit> {
     static HASHMAP: OnceLock<HashMap<&str, Unit>> = OnceLock::new();
     HASHMAP.get_or_init(|| {
         let mut m = HashMap::new();
-        // m.insert("C", Unit::new("C", 272.15, 1.0, Unit::temp())); // Celsius
-        // m.insert("F", Unit::new("F", 255.3722, 2.2, Unit::temp())); // Fahrenheit
-        m.insert("Pa", Unit::new("Pa", 1.0, Quantity::pressure())); // Pascals
-        m.insert("psi", Unit::new("psi", 6894.757, Quantity::pressure())); // PSI
-        m.insert("bar", Unit::new("bar", 100000.0, Quantity::pressure())); // Bars
-        m.insert("N", Unit::new("N", 1.0, Quantity::force())); // Newton
-        m.insert("lbf", Unit::new("lbf", 4.448222, Quantity::force())); // Newton
-        m.insert("m", Unit::new("m", 1.0, Quantity::length())); // Meter
-        m.insert("ft", Unit::new("ft", 0.3048, Quantity::length())); // Meter
-        m.insert("in", Unit::new("in", 0.0254, Quantity::length())); // Meter
+        // SI base units
+        m.insert("m", Unit::new("m", 1.0, Quantity::length()).with_prefixes(ALL_PREFIXES)); // Meter
         m.insert("s", Unit::new("s", 1.0, Quantity::time())); // Second
-        m.insert("lb", Unit::new("lb", 0.4535924, Quantity::mass())); // Gram
-        m.insert("kg", Unit::new("kg", 1.0, Quantity::mass())); // Gram
+        m.insert("kg", Unit::new("kg", 1.0, Quantity::mass()).with_prefixes(NO_PREFIXES)); // Kilogram
         m.insert("g", Unit::new("g", 0.001, Quantity::mass())); // Gram
-        m.insert("Hz", Unit::new("Hz", 1.0, Quantity::frequency())); // Hertz
         m.insert("A", Unit::new("A", 1.0, Quantity::current())); // Ampere
         m.insert("K", Unit::new("K", 1.0, Quantity::temp())); // Kelvin
         m.insert("mol", Unit::new("mol", 1.0, Quantity::amount())); // Mole
         m.insert("cd", Unit::new("cd", 1.0, Quantity::lumenous())); // Candela
+        // SI derived units
+        m.insert("Hz", Unit::new("Hz", 1.0, Quantity::frequency())); // Hertz
+        m.insert("N", Unit::new("N", 1.0, Quantity::force())); // Newton
+        m.insert("Pa", Unit::new("Pa", 1.0, Quantity::pressure()).with_prefixes(&[-18, -15, -12, -9, -6, -3, 2, 3, 6, 9, 12, 15, 18])); // Pascal
+        m.insert("J", Unit::new("J", 1.0, Quantity::energy())); // Joule
+        m.insert("W", Unit::new("W", 1.0, Quantity::power())); // Watt
+        m.insert("coulomb", Unit::new("C", 1.0, Quantity::charge())); // Coulomb, only "C" with a prefix since C is Celsius
+        m.insert("V", Unit::new("V", 1.0, Quantity::voltage())); // Volt
+        m.insert("Ω", Unit::new("Ω", 1.0, Quantity::resistance())); // Ohm
+        m.insert("S", Unit::new("S", 1.0, Quantity::conductance())); // Siemens
+        m.insert("farad", Unit::new("F", 1.0, Quantity::capacitance())); // Farad, only "F" with a prefix since F is Fahrenheit
+        m.insert("Wb", Unit::new("Wb", 1.0, Quantity::magnetic_flux())); // Weber
+        m.insert("T", Unit::new("T", 1.0, Quantity::magnetic_flux_density())); // Tesla
+        m.insert("H", Unit::new("H", 1.0, Quantity::inductance())); // Henry
+        m.insert("lm", Unit::new("lm", 1.0, Quantity::luminous_flux())); // Lumen
+        m.insert("lx", Unit::new("lx", 1.0, Quantity::illuminance())); // Lux
+        m.insert("Bq", Unit::new("Bq", 1.0, Quantity::frequency())); // Becquerel
+        m.insert("Gy", Unit::new("Gy", 1.0, Quantity::specific_energy())); // Gray
+        m.insert("Sv", Unit::new("Sv", 1.0, Quantity::specific_energy())); // Sievert
+        m.insert("kat", Unit::new("kat", 1.0, Quantity::catalytic_activity())); // Katal
+        // Angles
+        m.insert("rad", Unit::new("rad", 1.0, Quantity::angle()).with_prefixes(&[-6, -3])); // Radian
+        m.insert("sr", Unit::new("sr", 1.0, Quantity::solid_angle()).with_prefixes(NO_PREFIXES)); // Steradian
+        m.insert("deg", Unit::new("deg", std::f64::consts::PI / 180.0, Quantity::angle()).with_prefixes(NO_PREFIXES)); // Degree
+        m.insert("arcmin", Unit::new("arcmin", std::f64::consts::PI / 10800.0, Quantity::angle()).with_prefixes(NO_PREFIXES)); // Minute of arc
+        m.insert("arcsec", Unit::new("arcsec", std::f64::consts::PI / 648000.0, Quantity::angle()).with_prefixes(&[-3, -6])); // Second of arc
+        m.insert("rev", Unit::new("rev", std::f64::consts::TAU, Quantity::angle()).with_prefixes(NO_PREFIXES)); // Revolution
+        // Temperatures
+        m.insert("C", Unit::new_affine("C", 1.0, 273.15, Quantity::temp())); // Celsius
+        m.insert("F", Unit::new_affine("F", 5.0 / 9.0, 273.15 - 32.0 * 5.0 / 9.0, Quantity::temp())); // Fahrenheit
+        m.insert("R", Unit::new("R", 5.0 / 9.0, Quantity::temp()).with_prefixes(NO_PREFIXES)); // Rankine
+        // Non-SI metric units
+        m.insert("min", Unit::new("min", 60.0, Quantity::time()).with_prefixes(NO_PREFIXES)); // Minute
+        m.insert("h", Unit::new("h", 3600.0, Quantity::time()).with_prefixes(NO_PREFIXES)); // Hour
+        m.insert("d", Unit::new("d", 86400.0, Quantity::time()).with_prefixes(NO_PREFIXES)); // Day
+        m.insert("wk", Unit::new("wk", 604800.0, Quantity::time()).with_prefixes(NO_PREFIXES)); // Week
+        m.insert("yr", Unit::new("yr", 31557600.0, Quantity::time()).with_prefixes(&[3, 6, 9])); // Julian year
+        m.insert("L", Unit::new("L", 0.001, Quantity::volume()).with_prefixes(ALL_PREFIXES)); // Litre
+        m.insert("t", Unit::new("t", 1000.0, Quantity::mass()).with_prefixes(&[3, 6, 9])); // Tonne
+        m.insert("ha", Unit::new("ha", 10000.0, Quantity::area()).with_prefixes(NO_PREFIXES)); // Hectare
+        m.insert("Å", Unit::new("Å", 1e-10, Quantity::length()).with_prefixes(NO_PREFIXES)); // Ångström
+        m.insert("bar", Unit::new("bar", 100000.0, Quantity::pressure()).with_prefixes(&[-3, 3])); // Bar
+        m.insert("atm", Unit::new("atm", 101325.0, Quantity::pressure()).with_prefixes(NO_PREFIXES)); // Standard atmosphere
+        m.insert("Torr", Unit::new("Torr", 101325.0 / 760.0, Quantity::pressure()).with_prefixes(&[-3])); // Torr
+        m.insert("mmHg", Unit::new("mmHg", 133.322387415, Quantity::pressure()).with_prefixes(NO_PREFIXES)); // Millimetre of mercury
+        m.insert("eV", Unit::new("eV", 1.602176634e-19, Quantity::energy())); // Electronvolt
+        m.insert("cal", Unit::new("cal", 4.184, Quantity::energy()).with_prefixes(&[3])); // Thermochemical calorie
+        m.insert("Wh", Unit::new("Wh", 3600.0, Quantity::energy()).with_prefixes(&[3, 6, 9, 12])); // Watt-hour
+        m.insert("M", Unit::new("M", 1000.0, Quantity::concentration()).with_prefixes(&[-15, -12, -9, -6, -3])); // Molar
+        m.insert("Da", Unit::new("Da", 1.66053906660e-27, Quantity::mass()).with_prefixes(&[3, 6])); // Dalton
+        // Imperial and US customary units
+        m.insert("in", Unit::new("in", 0.0254, Quantity::length()).with_prefixes(NO_PREFIXES)); // Inch
+        m.insert("mil", Unit::new("mil", 0.0000254, Quantity::length()).with_prefixes(NO_PREFIXES)); // Thousandth of an inch
+        m.insert("ft", Unit::new("ft", 0.3048, Quantity::length()).with_prefixes(NO_PREFIXES)); // Foot
+        m.insert("yd", Unit::new("yd", 0.9144, Quantity::length()).with_prefixes(NO_PREFIXES)); // Yard
+        m.insert("mi", Unit::new("mi", 1609.344, Quantity::length()).with_prefixes(NO_PREFIXES)); // Mile
+        m.insert("nmi", Unit::new("nmi", 1852.0, Quantity::length()).with_prefixes(NO_PREFIXES)); // Nautical mile
+        m.insert("acre", Unit::new("acre", 4046.8564224, Quantity::area()).with_prefixes(NO_PREFIXES)); // Acre
+        m.insert("floz", Unit::new("floz", 2.95735295625e-5, Quantity::volume()).with_prefixes(NO_PREFIXES)); // US fluid ounce
+        m.insert("pt", Unit::new("pt", 4.73176473e-4, Quantity::volume()).with_prefixes(NO_PREFIXES)); // US pint
+        m.insert("qt", Unit::new("qt", 9.46352946e-4, Quantity::volume()).with_prefixes(NO_PREFIXES)); // US quart
+        m.insert("gal", Unit::new("gal", 3.785411784e-3, Quantity::volume()).with_prefixes(NO_PREFIXES)); // US gallon
+        m.insert("oz", Unit::new("oz", 0.028349523125, Quantity::mass()).with_prefixes(NO_PREFIXES)); // Ounce
+        m.insert("lb", Unit::new("lb", 0.45359237, Quantity::mass()).with_prefixes(NO_PREFIXES)); // Pound
+        m.insert("ton", Unit::new("ton", 907.18474, Quantity::mass()).with_prefixes(NO_PREFIXES)); // Short ton
+        m.insert("slug", Unit::new("slug", 14.593902937, Quantity::mass()).with_prefixes(NO_PREFIXES)); // Slug
+        m.insert("lbf", Unit::new("lbf", 4.4482216152605, Quantity::force()).with_prefixes(NO_PREFIXES)); // Pound-force
+        m.insert("kip", Unit::new("kip", 4448.2216152605, Quantity::force()).with_prefixes(NO_PREFIXES)); // Kilopound-force
+        m.insert("psi", Unit::new("psi", 6894.757293168, Quantity::pressure()).with_prefixes(&[3, 6])); // Pound-force per square inch
+        m.insert("mph", Unit::new("mph", 0.44704, Quantity::velocity()).with_prefixes(NO_PREFIXES)); // Miles per hour
+        m.insert("kn", Unit::new("kn", 1852.0 / 3600.0, Quantity::velocity()).with_prefixes(NO_PREFIXES)); // Knot
+        m.insert("BTU", Unit::new("BTU", 1055.05585262, Quantity::energy()).with_prefixes(NO_PREFIXES)); // British thermal unit
+        m.insert("hp", Unit::new("hp", 745.6998715822702, Quantity::power()).with_prefixes(NO_PREFIXES)); // Mechanical horsepower
+        // Aliases
+        m.insert("ohm", m["Ω"].clone());
+        m.insert("l", m["L"].clone());
+        m.insert("Btu", m["BTU"].clone());
+        m.insert("degC", m["C"].clone());
+        m.insert("°C", m["C"].clone());
+        m.insert("degF", m["F"].clone());
+        m.insert("°F", m["F"].clone());
+        m.insert("°", m["deg"].clone());
         m
     })
 }
 
+/// C and F alone are Celsius and Fahrenheit, but with a prefix (e.g. "mC" or "uF") they are coulombs and farads
+const PREFIXED_SYMBOLS: &[(&str, &str)] = &[("C", "coulomb"), ("F", "farad")];
+
+/// Units declared within a document (e.g. `unit furlong = 201.168 m`), layered over the built-in `unit_map()`
+#[derive(Debug, Clone, Default)]
+pub struct UnitRegistry {
+    units: HashMap<String, Unit>,
+}
+
+impl UnitRegistry {
+    pub fn new() -> Self {
+        UnitRegistry::default()
+    }
+
+    /// Looks up a unit by its exact name, user defined units first
+    pub fn get(&self, name: &str) -> Option<&Unit> {
+        self.units.get(name).or_else(|| unit_map().get(name))
+    }
+
+    /// Looks up the unit that follows a prefix, e.g. the farad in "uF"
+    pub fn get_prefixed(&self, symbol: &str) -> Option<&Unit> {
+        match PREFIXED_SYMBOLS.iter().find(|(prefixed, _)| *prefixed == symbol) {
+            Some((_, name)) => self.get(name),
+            None => self.get(symbol),
+        }
+    }
+
+    pub fn define(&mut self, unit: Unit) -> CResult<()> {
+        if self.get(&unit.name).is_some() {
+            return Err(Error::UnitError(format!("Unit '{}' already defined", unit.name)));
+        }
+        self.units.insert(unit.name.clone(), unit);
+        Ok(())
+    }
+}
+
+/// The units that values are displayed in, either one of the named `unit_system()`s or a custom list
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitSystem {
+    units: Vec<String>,
+}
+
+impl UnitSystem {
+    pub fn named(name: &str) -> CResult<Self> {
+        match unit_system().get(name) {
+            Some(units) => Ok(UnitSystem { units: units.iter().map(|unit| unit.to_string()).collect() }),
+            None => Err(Error::UnitError(format!("Unknown unit system '{name}'. Expected one of: {}", unit_system().keys().sorted().join(", ")))),
+        }
+    }
+
+    /// A system of preferred units (e.g. ["ft", "lbf", "psi"]). Quantities they can't express fall back to SI units
+    pub fn custom(units: &[String]) -> CResult<Self> {
+        for unit in units {
+            match unit_map().get(unit.as_str()) {
+                Some(base_unit) if base_unit.is_affine() => return Err(Error::UnitError(format!("'{unit}' cannot be used in a unit system"))),
+                Some(_) => (),
+                None => return Err(Error::UnitError(format!("Unknown unit '{unit}' in unit system"))),
+            }
+        }
+        let fallback = unit_system()["SI"].iter().map(|unit| unit.to_string());
+        Ok(UnitSystem { units: units.iter().cloned().chain(fallback).unique().collect() })
+    }
+
+    /// Puts the unit ahead of the others in the system, e.g. to show angles in degrees
+    pub fn prefer(self, unit: &str) -> CResult<Self> {
+        let mut preferred = UnitSystem::custom(&[unit.to_string()])?;
+        preferred.units = preferred.units.into_iter().take(1).chain(self.units).unique().collect();
+        Ok(preferred)
+    }
+
+    /// Index of the unit in the system, earlier units are preferred when two are equally good
+    pub fn position(&self, unit: &str) -> Option<usize> {
+        self.units.iter().position(|u| u == unit)
+    }
+
+    /// The first unit in the system with exactly the given quantity
+    pub fn base_unit(&self, quantity: &Quantity) -> Option<&'static str> {
+        self.units.iter()
+            .filter_map(|name| unit_map().get_key_value(name.as_str()))
+            .find(|(_, unit)| &unit.quantity == quantity)
+            .map(|(name, _)| *name)
+    }
+}
+
+impl Default for UnitSystem {
+    fn default() -> Self {
+        UnitSystem::named("SI").unwrap()
+    }
+}
+
 /// Used to determine which units are shown in the UI
 /// 
 /// TODO: Use systems on the frontend. Have the background return all possible units and the frontend filters them.
-/// TODO: User should be able to specify a custom system (on frontend)
 pub fn unit_system() -> &'static HashMap<&'static str, Vec<&'static str>> {
     static HASHMAP: OnceLock<HashMap<&'static str, Vec<&'static str>>> = OnceLock::new();
     HASHMAP.get_or_init(|| {
         let mut m = HashMap::new();
-        m.insert("SI", vec!["m",  "s", "kg", "A", "K", "cd", "N", "Pa"]);
-        m.insert("US", vec!["ft", "s", "lb", "A", "K", "cd", "lbf"]);
+        m.insert("SI", vec!["m",  "s", "kg", "A", "K", "mol", "cd", "rad", "N", "Pa", "J", "W", "coulomb", "V", "Ω", "S", "farad", "Wb", "T", "H"]);
+        m.insert("US", vec!["ft", "s", "lb", "A", "K", "mol", "cd", "deg", "lbf", "psi"]);
         m
     })
 }
@@ -0,0 +1,688 @@
+use crate::error::Error;
+use crate::fields::{Unit, UnitRegistry};
+use crate::types::{BaseField, CResult, Limit};
+
+use serde::Serialize;
+
+
+/// Largest matrix that can be inverted or have its determinant taken, as cofactor expansion takes O(n!) steps
+const MAX_EXPANSION_SIZE: usize = 8;
+
+/// A number from another field, or a list, vector or matrix of them. Matrices of `UnitVal`s keep the units of each element.
+///
+/// Vectors are matrices with a single row or column. Functions such as `\sin` are applied element-wise
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<T> {
+    Scalar(T),
+    /// A list as in Desmos, e.g. `[1, 2, 3]`. Arithmetic with a list is applied to each of its elements
+    List(Vec<T>),
+    /// Rows of a matrix, which are never empty and all have the same length
+    Matrix(Vec<Vec<T>>),
+}
+
+impl<T> Value<T> {
+    /// Applies `f` to the value or to each element of the matrix, e.g. to format them
+    pub fn map<U>(&self, f: impl Fn(&T) -> U) -> Value<U> {
+        match self {
+            Value::Scalar(value) => Value::Scalar(f(value)),
+            Value::List(values) => Value::List(values.iter().map(f).collect()),
+            Value::Matrix(rows) => Value::Matrix(rows.iter().map(|row| row.iter().map(&f).collect()).collect()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Value::Scalar(_) => "a number".to_string(),
+            Value::List(values) => format!("a list of length {}", values.len()),
+            Value::Matrix(rows) => format!("a {}×{} matrix", rows.len(), rows[0].len()),
+        }
+    }
+}
+
+impl<T> Value<T> where for<'a> T: BaseField<'a> {
+    fn try_map(&self, f: impl Fn(&T) -> CResult<T>) -> CResult<Self> {
+        match self {
+            Value::Scalar(value) => Ok(Value::Scalar(f(value)?)),
+            Value::List(values) => Ok(Value::List(values.iter().map(f).collect::<CResult<_>>()?)),
+            Value::Matrix(rows) => Ok(Value::Matrix(rows.iter().map(|row| row.iter().map(&f).collect()).collect::<CResult<_>>()?)),
+        }
+    }
+
+    /// Combines values of the same shape element by element. A number is combined with every element of a list
+    fn zip_with(self, rhs: Self, operation: &str, f: impl Fn(T, T) -> CResult<T>) -> CResult<Self> {
+        match (self, rhs) {
+            (Value::Scalar(lhs), Value::Scalar(rhs)) => Ok(Value::Scalar(f(lhs, rhs)?)),
+            (Value::List(lhs), Value::List(rhs)) if lhs.len() == rhs.len() => {
+                Ok(Value::List(lhs.into_iter().zip(rhs).map(|(lhs, rhs)| f(lhs, rhs)).collect::<CResult<_>>()?))
+            },
+            (Value::List(lhs), Value::Scalar(rhs)) => Ok(Value::List(lhs.into_iter().map(|lhs| f(lhs, rhs.clone())).collect::<CResult<_>>()?)),
+            (Value::Scalar(lhs), Value::List(rhs)) => Ok(Value::List(rhs.into_iter().map(|rhs| f(lhs.clone(), rhs)).collect::<CResult<_>>()?)),
+            (Value::Matrix(lhs), Value::Matrix(rhs)) if shape(&lhs) == shape(&rhs) => {
+                let rows = lhs.into_iter().zip(rhs).map(|(lhs, rhs)| lhs.into_iter().zip(rhs).map(|(lhs, rhs)| f(lhs, rhs)).collect());
+                Ok(Value::Matrix(rows.collect::<CResult<_>>()?))
+            },
+            (lhs, rhs) => Err(Error::EvalError(format!("Cannot {operation} {} and {}", lhs.describe(), rhs.describe()))),
+        }
+    }
+
+    fn as_vector(&self, operation: &str) -> CResult<Vec<T>> {
+        match self {
+            Value::List(values) => Ok(values.clone()),
+            Value::Matrix(rows) if rows.len() == 1 => Ok(rows[0].clone()),
+            Value::Matrix(rows) if rows[0].len() == 1 => Ok(rows.iter().map(|row| row[0].clone()).collect()),
+            value => Err(Error::EvalError(format!("{operation} expects vectors, but got {}", value.describe()))),
+        }
+    }
+
+    fn as_square_matrix(&self, operation: &str) -> CResult<&Vec<Vec<T>>> {
+        match self {
+            Value::Matrix(rows) if rows.len() != rows[0].len() => {
+                Err(Error::EvalError(format!("Cannot take the {operation} of {}, as it isn't square", self.describe())))
+            },
+            Value::Matrix(rows) if rows.len() > MAX_EXPANSION_SIZE => {
+                Err(Error::EvalError(format!("Cannot take the {operation} of matrices larger than {MAX_EXPANSION_SIZE}×{MAX_EXPANSION_SIZE}")))
+            },
+            Value::Matrix(rows) => Ok(rows),
+            value => Err(Error::EvalError(format!("Cannot take the {operation} of {}", value.describe()))),
+        }
+    }
+
+    /// A vector with the same orientation (row or column) as this one, or a list
+    fn with_orientation(&self, elements: Vec<T>) -> Self {
+        match self {
+            Value::List(_) => Value::List(elements),
+            Value::Matrix(rows) if rows.len() == 1 => Value::Matrix(vec![elements]),
+            _ => Value::Matrix(elements.into_iter().map(|element| vec![element]).collect()),
+        }
+    }
+}
+
+fn shape<T>(rows: &[Vec<T>]) -> (usize, usize) {
+    (rows.len(), rows[0].len())
+}
+
+/// Applies `f` to each element of a matrix, e.g. to scale it by a number
+fn broadcast<T>(rows: Vec<Vec<T>>, f: impl Fn(T) -> CResult<T>) -> CResult<Vec<Vec<T>>> {
+    rows.into_iter().map(|row| row.into_iter().map(&f).collect()).collect()
+}
+
+/// Adds up the terms in the field itself, so they keep their units
+fn sum<T>(terms: impl IntoIterator<Item = CResult<T>>) -> CResult<T> where for<'a> T: BaseField<'a> {
+    let mut terms = terms.into_iter();
+    let first = terms.next().unwrap_or_else(|| Ok(T::from(0.0)))?;
+    terms.try_fold(first, |total, term| total + term?)
+}
+
+fn matrix_product<T>(lhs: &[Vec<T>], rhs: &[Vec<T>]) -> CResult<Vec<Vec<T>>> where for<'a> T: BaseField<'a> {
+    let ((rows, inner), (rhs_rows, cols)) = (shape(lhs), shape(rhs));
+    if inner != rhs_rows {
+        return Err(Error::EvalError(format!("Cannot multiply a {rows}×{inner} matrix by a {rhs_rows}×{cols} matrix")));
+    }
+    (0..rows).map(|i| {
+        (0..cols).map(|j| sum((0..inner).map(|k| lhs[i][k].clone() * rhs[k][j].clone()))).collect()
+    }).collect()
+}
+
+fn identity<T>(size: usize) -> Vec<Vec<T>> where for<'a> T: BaseField<'a> {
+    (0..size).map(|i| (0..size).map(|j| T::from(if i == j { 1.0 } else { 0.0 })).collect()).collect()
+}
+
+/// The matrix without row `i` and column `j`
+fn minor<T: Clone>(rows: &[Vec<T>], i: usize, j: usize) -> Vec<Vec<T>> {
+    rows.iter().enumerate().filter(|(row, _)| *row != i).map(|(_, row)| {
+        row.iter().enumerate().filter(|(col, _)| *col != j).map(|(_, value)| value.clone()).collect()
+    }).collect()
+}
+
+/// Cofactor expansion along the first row, which only needs the field's own arithmetic
+fn determinant<T>(rows: &[Vec<T>]) -> CResult<T> where for<'a> T: BaseField<'a> {
+    if rows.len() == 1 {
+        return Ok(rows[0][0].clone());
+    }
+    let mut det = (rows[0][0].clone() * determinant(&minor(rows, 0, 0))?)?;
+    for j in 1..rows.len() {
+        let term = (rows[0][j].clone() * determinant(&minor(rows, 0, j))?)?;
+        det = if j % 2 == 0 { (det + term)? } else { (det - term)? };
+    }
+    Ok(det)
+}
+
+/// Whether `value` is zero. Values with units can't be compared with a number, but dividing by
+/// themselves leaves a number (or an error, for fields that reject division by zero)
+fn is_zero<T>(value: &T) -> bool where for<'a> T: BaseField<'a> {
+    match value.clone() / value.clone() {
+        Err(_) => true,
+        Ok(ratio) => ratio.abs().and_then(|ratio| ratio.as_scalar()).is_ok_and(f64::is_nan),
+    }
+}
+
+/// The inverse as the adjugate divided by the determinant, which keeps exact fields exact
+fn inverse<T>(rows: &[Vec<T>]) -> CResult<Vec<Vec<T>>> where for<'a> T: BaseField<'a> {
+    let det = determinant(rows)?;
+    if is_zero(&det) {
+        return Err(Error::EvalError("Cannot invert a singular matrix (its determinant is 0)".to_string()));
+    }
+    let size = rows.len();
+    if size == 1 {
+        return Ok(vec![vec![(T::from(1.0) / det)?]]);
+    }
+    (0..size).map(|i| {
+        (0..size).map(|j| {
+            let cofactor = determinant(&minor(rows, j, i))?;
+            // Negated by subtracting from a zero with the same units, rather than multiplying by -1, which gives -0
+            let cofactor = if (i + j) % 2 == 0 { cofactor } else { ((cofactor.clone() * T::from(0.0))? - cofactor)? };
+            cofactor / det.clone()
+        }).collect()
+    }).collect()
+}
+
+
+impl<T: std::fmt::Display> std::fmt::Display for Value<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Scalar(value) => write!(f, "{value}"),
+            Value::List(values) => write!(f, "[{}]", values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(", ")),
+            Value::Matrix(rows) => {
+                let rows = rows.iter().map(|row| row.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(", "));
+                write!(f, "[{}]", rows.collect::<Vec<_>>().join("; "))
+            },
+        }
+    }
+}
+
+/// Matrices are serialized as a list of rows, so the frontend can show them as a grid
+impl<T: Serialize> Serialize for Value<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
+        match self {
+            Value::Scalar(value) => value.serialize(serializer),
+            Value::List(values) => values.serialize(serializer),
+            Value::Matrix(rows) => rows.serialize(serializer),
+        }
+    }
+}
+
+impl<'a, T> BaseField<'a> for Value<T> where for<'b> T: BaseField<'b> {
+    fn as_scalar(&self) -> CResult<f64> {
+        match self {
+            Value::Scalar(value) => value.as_scalar(),
+            value => Err(Error::EvalError(format!("Expected a number, but got {}", value.describe()))),
+        }
+    }
+
+    fn powf(&self, exp: Self) -> CResult<Self> {
+        if matches!(self, Value::List(_)) || matches!(exp, Value::List(_)) {
+            return self.clone().zip_with(exp, "raise", |base, exp| base.powf(exp));
+        }
+        match (self, exp) {
+            (Value::Scalar(base), Value::Scalar(exp)) => Ok(Value::Scalar(base.powf(exp)?)),
+            (Value::Matrix(rows), Value::Scalar(exp)) => {
+                let n = exp.as_scalar()?;
+                if n.fract() != 0.0 {
+                    return Err(Error::EvalError(format!("Matrices can only be raised to whole powers, not {n}")));
+                }
+                if rows.len() != rows[0].len() {
+                    return Err(Error::EvalError(format!("Cannot raise {} to a power, as it isn't square", self.describe())));
+                }
+                // Exponentiation by squaring
+                let mut square = if n < 0.0 { inverse(self.as_square_matrix("inverse")?)? } else { rows.clone() };
+                let mut result = identity(rows.len());
+                let mut n = n.abs() as u64;
+                while n > 0 {
+                    if n % 2 == 1 {
+                        result = matrix_product(&result, &square)?;
+                    }
+                    square = matrix_product(&square, &square)?;
+                    n /= 2;
+                }
+                Ok(Value::Matrix(result))
+            },
+            (base, exp) => Err(Error::EvalError(format!("Cannot raise {} to the power of {}", base.describe(), exp.describe()))),
+        }
+    }
+
+    fn root(&self, n: Self) -> CResult<Self> {
+        if matches!(self, Value::List(_)) || matches!(n, Value::List(_)) {
+            return self.clone().zip_with(n, "take the root of", |value, n| value.root(n));
+        }
+        match (self, n) {
+            (Value::Scalar(value), Value::Scalar(n)) => Ok(Value::Scalar(value.root(n)?)),
+            (value, _) => Err(Error::EvalError(format!("Cannot take the root of {}", value.describe()))),
+        }
+    }
+
+    fn fract(&self) -> CResult<f64> {
+        match self {
+            Value::Scalar(value) => value.fract(),
+            value => Err(Error::EvalError(format!("Expected a number, but got {}", value.describe()))),
+        }
+    }
+
+    fn as_exact_scalar(&self) -> CResult<f64> {
+        match self {
+            Value::Scalar(value) => value.as_exact_scalar(),
+            value => Err(Error::EvalError(format!("Expected a number, but got {}", value.describe()))),
+        }
+    }
+
+    fn sin(&self) -> CResult<Self> {
+        self.try_map(T::sin)
+    }
+
+    fn cos(&self) -> CResult<Self> {
+        self.try_map(T::cos)
+    }
+
+    fn tan(&self) -> CResult<Self> {
+        self.try_map(T::tan)
+    }
+
+    fn asin(&self) -> CResult<Self> {
+        self.try_map(T::asin)
+    }
+
+    fn acos(&self) -> CResult<Self> {
+        self.try_map(T::acos)
+    }
+
+    fn atan(&self) -> CResult<Self> {
+        self.try_map(T::atan)
+    }
+
+    fn abs(&self) -> CResult<Self> {
+        self.try_map(T::abs)
+    }
+
+    fn sinh(&self) -> CResult<Self> {
+        self.try_map(T::sinh)
+    }
+
+    fn cosh(&self) -> CResult<Self> {
+        self.try_map(T::cosh)
+    }
+
+    fn tanh(&self) -> CResult<Self> {
+        self.try_map(T::tanh)
+    }
+
+    fn asinh(&self) -> CResult<Self> {
+        self.try_map(T::asinh)
+    }
+
+    fn acosh(&self) -> CResult<Self> {
+        self.try_map(T::acosh)
+    }
+
+    fn atanh(&self) -> CResult<Self> {
+        self.try_map(T::atanh)
+    }
+
+    fn ln(&self) -> CResult<Self> {
+        self.try_map(T::ln)
+    }
+
+    fn exp(&self) -> CResult<Self> {
+        self.try_map(T::exp)
+    }
+
+    fn floor(&self) -> CResult<Self> {
+        self.try_map(T::floor)
+    }
+
+    fn ceil(&self) -> CResult<Self> {
+        self.try_map(T::ceil)
+    }
+
+    fn round(&self) -> CResult<Self> {
+        self.try_map(T::round)
+    }
+
+    fn sign(&self) -> CResult<Self> {
+        self.try_map(T::sign)
+    }
+
+    fn factorial(&self) -> CResult<Self> {
+        self.try_map(T::factorial)
+    }
+
+    fn log(&self, base: &Self) -> CResult<Self> {
+        self.clone().zip_with(base.clone(), "take the logarithm of", |lhs, rhs| lhs.log(&rhs))
+    }
+
+    fn modulo(&self, rhs: &Self) -> CResult<Self> {
+        self.clone().zip_with(rhs.clone(), "take the remainder of", |lhs, rhs| lhs.modulo(&rhs))
+    }
+
+    fn gcd(&self, rhs: &Self) -> CResult<Self> {
+        self.clone().zip_with(rhs.clone(), "take the gcd of", |lhs, rhs| lhs.gcd(&rhs))
+    }
+
+    fn lcm(&self, rhs: &Self) -> CResult<Self> {
+        self.clone().zip_with(rhs.clone(), "take the lcm of", |lhs, rhs| lhs.lcm(&rhs))
+    }
+
+    fn permutations(&self, r: &Self) -> CResult<Self> {
+        self.clone().zip_with(r.clone(), "count the permutations of", |lhs, rhs| lhs.permutations(&rhs))
+    }
+
+    fn combinations(&self, r: &Self) -> CResult<Self> {
+        self.clone().zip_with(r.clone(), "count the combinations of", |lhs, rhs| lhs.combinations(&rhs))
+    }
+
+    fn min(&self, other: &Self) -> CResult<Self> {
+        self.clone().zip_with(other.clone(), "take the minimum of", |lhs, rhs| lhs.min(&rhs))
+    }
+
+    fn max(&self, other: &Self) -> CResult<Self> {
+        self.clone().zip_with(other.clone(), "take the maximum of", |lhs, rhs| lhs.max(&rhs))
+    }
+
+    fn re(&self) -> CResult<Self> {
+        self.try_map(T::re)
+    }
+
+    fn im(&self) -> CResult<Self> {
+        self.try_map(T::im)
+    }
+
+    fn conj(&self) -> CResult<Self> {
+        self.try_map(T::conj)
+    }
+
+    fn arg(&self) -> CResult<Self> {
+        self.try_map(T::arg)
+    }
+
+    /// Lists are differentiated element by element, like other functions of lists
+    fn derivative(&self, f: impl Fn(Self) -> CResult<Self>) -> CResult<Self> {
+        self.try_map(|x| x.derivative(|x| match f(Value::Scalar(x))? {
+            Value::Scalar(y) => Ok(y),
+            y => Err(Error::EvalError(format!("Only numbers can be differentiated, but got {}", y.describe()))),
+        }))
+    }
+
+    fn integral(lower: &Limit<Self>, upper: &Limit<Self>, f: impl Fn(Self) -> CResult<Self>) -> CResult<Self> {
+        let scalar_limit = |limit: &Limit<Self>| limit.try_map(|value| match value {
+            Value::Scalar(value) => Ok(value.clone()),
+            value => Err(Error::EvalError(format!("The limits of integrals must be numbers, but got {}", value.describe()))),
+        });
+        let integral = T::integral(&scalar_limit(lower)?, &scalar_limit(upper)?, |x| match f(Value::Scalar(x))? {
+            Value::Scalar(y) => Ok(y),
+            y => Err(Error::EvalError(format!("Only numbers can be integrated, but got {}", y.describe()))),
+        })?;
+        Ok(Value::Scalar(integral))
+    }
+
+    fn unit(&self) -> CResult<Self> {
+        self.try_map(T::unit)
+    }
+
+    /// The largest magnitude of any element
+    fn magnitude(&self) -> CResult<f64> {
+        let elements: Vec<&T> = match self {
+            Value::Scalar(value) => vec![value],
+            Value::List(values) => values.iter().collect(),
+            Value::Matrix(rows) => rows.iter().flatten().collect(),
+        };
+        elements.into_iter().try_fold(0.0, |largest, value| Ok(value.magnitude()?.max(largest)))
+    }
+
+    fn compare(&self, other: &Self) -> CResult<Option<std::cmp::Ordering>> {
+        match (self, other) {
+            (Value::Scalar(lhs), Value::Scalar(rhs)) => lhs.compare(rhs),
+            (lhs, rhs) => Err(Error::EvalError(format!("Cannot compare {} with {}", lhs.describe(), rhs.describe()))),
+        }
+    }
+
+    fn convert(&self, unit: &str, units: &UnitRegistry) -> CResult<Self> {
+        self.try_map(|value| value.convert(unit, units))
+    }
+
+    fn define_unit(&self, name: &str) -> CResult<Unit> {
+        match self {
+            Value::Scalar(value) => value.define_unit(name),
+            value => Err(Error::UnitError(format!("Unit '{name}' cannot be defined as {}", value.describe()))),
+        }
+    }
+
+    fn unit_identity(unit: &str, units: &UnitRegistry) -> CResult<Self> {
+        Ok(Value::Scalar(T::unit_identity(unit, units)?))
+    }
+
+    fn plus_minus(&self, uncertainty: Self) -> CResult<Self> {
+        self.clone().zip_with(uncertainty, "combine", |value, uncertainty| value.plus_minus(uncertainty))
+    }
+
+    fn from_literal(literal: &str) -> Self {
+        Value::Scalar(T::from_literal(literal))
+    }
+
+    fn matrix(rows: Vec<Vec<Self>>) -> CResult<Self> {
+        if rows.is_empty() || rows.iter().any(|row| row.len() != rows[0].len()) {
+            return Err(Error::EvalError("All rows of a matrix must have the same number of elements".to_string()));
+        }
+        let rows = rows.into_iter().map(|row| row.into_iter().map(|value| match value {
+            Value::Scalar(value) => Ok(value),
+            value => Err(Error::EvalError(format!("Matrix elements must be numbers, but got {}", value.describe()))),
+        }).collect()).collect::<CResult<_>>()?;
+        Ok(Value::Matrix(rows))
+    }
+
+    fn list(elements: Vec<Self>) -> CResult<Self> {
+        let elements = elements.into_iter().map(|value| match value {
+            Value::Scalar(value) => Ok(value),
+            value => Err(Error::EvalError(format!("List elements must be numbers, but got {}", value.describe()))),
+        }).collect::<CResult<_>>()?;
+        Ok(Value::List(elements))
+    }
+
+    fn as_list(&self) -> Option<Vec<Self>> {
+        match self {
+            Value::List(values) => Some(values.iter().cloned().map(Value::Scalar).collect()),
+            _ => None,
+        }
+    }
+
+    fn hadamard(self, rhs: Self) -> CResult<Self> {
+        match (self, rhs) {
+            (Value::Scalar(lhs), Value::Matrix(rows)) => Ok(Value::Matrix(broadcast(rows, |value| lhs.clone() * value)?)),
+            (Value::Matrix(rows), Value::Scalar(rhs)) => Ok(Value::Matrix(broadcast(rows, |value| value * rhs.clone())?)),
+            (lhs, rhs) => lhs.zip_with(rhs, "multiply", |lhs, rhs| lhs * rhs),
+        }
+    }
+
+    fn transpose(&self) -> CResult<Self> {
+        match self {
+            Value::Scalar(value) => Ok(Value::Scalar(value.transpose()?)),
+            Value::List(values) => Ok(Value::Matrix(values.iter().map(|value| vec![value.clone()]).collect())),
+            Value::Matrix(rows) => Ok(Value::Matrix((0..rows[0].len()).map(|j| rows.iter().map(|row| row[j].clone()).collect()).collect())),
+        }
+    }
+
+    fn det(&self) -> CResult<Self> {
+        match self {
+            Value::Scalar(value) => Ok(Value::Scalar(value.det()?)),
+            matrix => Ok(Value::Scalar(determinant(matrix.as_square_matrix("determinant")?)?)),
+        }
+    }
+
+    fn inv(&self) -> CResult<Self> {
+        match self {
+            Value::Scalar(value) => Ok(Value::Scalar(value.inv()?)),
+            Value::List(_) => self.try_map(T::inv),
+            matrix => Ok(Value::Matrix(inverse(matrix.as_square_matrix("inverse")?)?)),
+        }
+    }
+
+    fn dot(&self, rhs: &Self) -> CResult<Self> {
+        if let (Value::Scalar(lhs), Value::Scalar(rhs)) = (self, rhs) {
+            return Ok(Value::Scalar(lhs.dot(rhs)?));
+        }
+        let (lhs, rhs) = (self.as_vector("The dot product")?, rhs.as_vector("The dot product")?);
+        if lhs.len() != rhs.len() {
+            return Err(Error::EvalError(format!("Cannot take the dot product of vectors of length {} and {}", lhs.len(), rhs.len())));
+        }
+        Ok(Value::Scalar(sum(lhs.into_iter().zip(rhs).map(|(lhs, rhs)| lhs * rhs))?))
+    }
+
+    fn cross(&self, rhs: &Self) -> CResult<Self> {
+        let (a, b) = (self.as_vector("The cross product")?, rhs.as_vector("The cross product")?);
+        if a.len() != 3 || b.len() != 3 {
+            return Err(Error::EvalError(format!("The cross product is only defined for 3D vectors, but got lengths {} and {}", a.len(), b.len())));
+        }
+        let component = |i: usize, j: usize| (a[i].clone() * b[j].clone())? - (a[j].clone() * b[i].clone())?;
+        Ok(self.with_orientation(vec![component(1, 2)?, component(2, 0)?, component(0, 1)?]))
+    }
+}
+
+impl<'a, T> TryFrom<&'a str> for Value<T> where for<'b> T: BaseField<'b> {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        Ok(Value::Scalar(T::try_from(value)?))
+    }
+}
+
+impl<T> From<f64> for Value<T> where for<'a> T: BaseField<'a> {
+    fn from(value: f64) -> Self {
+        Value::Scalar(T::from(value))
+    }
+}
+
+impl<T> std::ops::Add for Value<T> where for<'a> T: BaseField<'a> {
+    type Output = CResult<Self>;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        self.zip_with(rhs, "add", |lhs, rhs| lhs + rhs)
+    }
+}
+
+impl<T> std::ops::Sub for Value<T> where for<'a> T: BaseField<'a> {
+    type Output = CResult<Self>;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.zip_with(rhs, "subtract", |lhs, rhs| lhs - rhs)
+    }
+}
+
+impl<T> std::ops::Mul for Value<T> where for<'a> T: BaseField<'a> {
+    type Output = CResult<Self>;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        match (self, rhs) {
+            (Value::Matrix(lhs), Value::Matrix(rhs)) => Ok(Value::Matrix(matrix_product(&lhs, &rhs)?)),
+            (lhs, rhs) => lhs.hadamard(rhs),
+        }
+    }
+}
+
+impl<T> std::ops::Div for Value<T> where for<'a> T: BaseField<'a> {
+    type Output = CResult<Self>;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        match (self, rhs) {
+            (Value::Scalar(lhs), Value::Scalar(rhs)) => Ok(Value::Scalar((lhs / rhs)?)),
+            (Value::Matrix(rows), Value::Scalar(rhs)) => Ok(Value::Matrix(broadcast(rows, |value| value / rhs.clone())?)),
+            (lhs, rhs) if matches!(lhs, Value::List(_)) || matches!(rhs, Value::List(_)) => lhs.zip_with(rhs, "divide", |lhs, rhs| lhs / rhs),
+            // A / B = A B^-1
+            (lhs, rhs) => lhs * rhs.inv()?,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::Evaluator;
+    use crate::fields::{Exact, UnitVal};
+    use crate::types::Span;
+    use crate::evaluate_line;
+
+    fn evaluate<T>(inputs: &[&str]) -> String where for<'a> T: BaseField<'a> + 'a {
+        let mut eval = Evaluator::<Value<T>>::new();
+        let mut result = None;
+        for input in inputs {
+            result = evaluate_line(Span::new(input), &mut eval).unwrap();
+        }
+        result.unwrap().to_string()
+    }
+
+    #[test]
+    fn test_matrix_arithmetic() {
+        let tests = vec![
+            (vec!["[1, 2; 3, 4] + [1, 1; 1, 1]"], "[2, 3; 4, 5]"),
+            (vec!["\\begin{bmatrix}1&2\\\\3&4\\end{bmatrix} [5; 6]"], "[17; 39]"),
+            (vec!["B = [1, 2; 3, 4]", "B * B"], "[7, 10; 15, 22]"),
+            (vec!["B = [1, 2; 3, 4]", "B^2 - B \\odot B"], "[6, 6; 6, 6]"),
+            (vec!["B = [1, 2; 3, 4]", "B^T"], "[1, 3; 2, 4]"),
+            (vec!["2 [1, 2, 3] / 4"], "[1/2, 1, 3/2]"),
+            (vec!["\\sin([0, 0])"], "[0, 0]"),
+            (vec!["\\det([1, 2; 3, 4])"], "-2"),
+            (vec!["dot([1, 2, 3], [4; 5; 6])"], "32"),
+            (vec!["cross([1, 0, 0], [0, 1, 0])"], "[0, 0, 1]"),
+            (vec!["D = [4, 7; 2, 6]", "D \\cdot D^{-1}"], "[1, 0; 0, 1]"),
+        ];
+        for (inputs, expected) in tests {
+            assert_eq!(evaluate::<Exact>(&inputs), expected, "{inputs:?}");
+        }
+    }
+
+    #[test]
+    fn test_units() {
+        assert_eq!(evaluate::<UnitVal>(&["[1 m, 2 m] + [50 cm, 1 km]"]), "[1.5 m, 1.002 km]");
+        assert_eq!(evaluate::<UnitVal>(&["\\det([2 m, 0 m; 0 s, 3 s])"]), "6 m·s");
+        assert_eq!(evaluate::<UnitVal>(&["dot([3 N, 4 N], [2 m, 1 m])"]), "10 J");
+        assert_eq!(evaluate::<UnitVal>(&["inv([2 s, 0 s; 0 s, 4 s])"]), "[0.5 /s, 0 /s; 0 /s, 0.25 /s]");
+        assert_eq!(evaluate::<UnitVal>(&["[1, 2] km to m"]), "[1000 m, 2000 m]");
+    }
+
+    #[test]
+    fn test_lists() {
+        let tests = vec![
+            (vec!["L = [1, 2, 3]", "L^2"], "[1, 4, 9]"),
+            (vec!["L = [1, 2, 3]", "L + [10, 20, 30]"], "[11, 22, 33]"),
+            (vec!["L = [1, 2, 3]", "sum(L)"], "6"),
+            (vec!["L = [1, 2, 4]", "\\operatorname{mean}\\left(L\\right)"], "7/3"),
+            (vec!["length([1...10])"], "10"),
+            (vec!["[5...1]"], "[5, 4, 3, 2, 1]"),
+            (vec!["[1, 3, ..., 9]"], "[1, 3, 5, 7, 9]"),
+            (vec!["[0, \\frac{1}{4}, \\ldots, 1]"], "[0, 1/4, 1/2, 3/4, 1]"),
+            (vec!["f(x) = 2x + 1", "f([1, 2, 3])"], "[3, 5, 7]"),
+            (vec!["L = [1, 2, 3]", "\\sum_{i=1}^{3} L[i]^2"], "14"),
+            (vec!["L = [4, 5, 6]", "L\\left[2\\right]"], "5"),
+            (vec!["[x^2 for x=[1...4]]"], "[1, 4, 9, 16]"),
+            (vec!["L = [1, 2]", "[L[n] + n \\operatorname{for} n=[1, 2]]"], "[2, 4]"),
+        ];
+        for (inputs, expected) in tests {
+            assert_eq!(evaluate::<Exact>(&inputs), expected, "{inputs:?}");
+        }
+        assert_eq!(evaluate::<UnitVal>(&["[1 m, 2 m, 3 m] + 1 km"]), "[1.001 km, 1.002 km, 1.003 km]");
+        assert_eq!(evaluate::<UnitVal>(&["[0 s, 0.5 s, ..., 2 s]"]), "[0 s, 0.5 s, 1 s, 1.5 s, 2 s]");
+        assert_eq!(evaluate::<UnitVal>(&["[1 m...3 m]"]), "[1 m, 2 m, 3 m]");
+        assert_eq!(evaluate::<UnitVal>(&["[3 s...1 s]"]), "[3 s, 2 s, 1 s]");
+        assert_eq!(evaluate::<UnitVal>(&["[20 °C...22 °C]"]), "[20 C, 21 C, 22 C]");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let invalid = [
+            "[1, 2] + [1, 2, 3]",
+            "[1, 2; 3]",
+            "[1, 2; 3, 4] [1; 2; 3]",
+            "[1, 2; 2, 4]^{-1}",
+            "\\det([1, 2, 3])",
+            "cross([1, 2], [3, 4])",
+            "[1, 2; 3, 4] + 1",
+            "[1, 2; 3, 4] + [1, 2]",
+            "[1, 2, 3][4]",
+            "[1...10^6]",
+            "[[1, 2], 3]",
+        ];
+        for input in invalid {
+            assert!(evaluate_line(Span::new(input), &mut Evaluator::<Value<Exact>>::new()).is_err(), "{input} should be invalid");
+        }
+        for input in ["[1 m...5 s]", "[1 m, 2 s, ..., 5 m]"] {
+            assert!(evaluate_line(Span::new(input), &mut Evaluator::<Value<UnitVal>>::new()).is_err(), "{input} should be invalid");
+        }
+    }
+}
@@ -4,8 +4,10 @@
 use types::BaseField;
 
 use crate::{
-  evaluator::Evaluator,
+  dependencies::{evaluation_order, EvaluationOrder},
+  evaluator::{with_stack, Evaluator, DEFAULT_MAX_DEPTH},
   types::{Span, CResult},
+  error::Error,
   parser::parse,
   fields::*,
   menus::{save_file, get_menus, handle_menu_event},
@@ -16,6 +18,8 @@ use std::fs::File;
 use std::io::Read;
 use std::result::Result;
 
+mod calculus;
+mod dependencies;
 mod evaluator;
 mod parser;
 mod parsing_helpers;
@@ -36,38 +40,112 @@ fn evaluate_line<T>(line: Span, eval: &mut Evaluator<T>) -> EvalResult<T> where
     Ok(eval)
 }
 
-fn evaluate_sequence<T>(inputs: Vec<&str>) -> Vec<EvalResult<T>> where for<'a> T: BaseField<'a> + 'a {
-    let mut eval = Evaluator::new();
+/// Evaluates a sheet on a thread with enough stack for functions to call themselves `max_depth` times
+/// (`DEFAULT_MAX_DEPTH` if not given)
+fn evaluate_sheet<T>(input: &str, max_depth: Option<usize>) -> CResult<Vec<EvalResult<T>>> where for<'a> T: BaseField<'a> + 'a + Send {
+    let max_depth = max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+    with_stack(max_depth, || evaluate_sequence(input.lines().collect(), max_depth))
+}
+
+/// Definitions can be used anywhere in the sheet, so lines are evaluated in the order of their dependencies
+fn evaluate_sequence<T>(inputs: Vec<&str>, max_depth: usize) -> Vec<EvalResult<T>> where for<'a> T: BaseField<'a> + 'a {
+    let mut eval = Evaluator::with_max_depth(max_depth);
     let mut results = vec![];
+    let mut exprs = vec![];
 
     for (i, input) in inputs.into_iter().enumerate() {      
         if input.is_empty() {
             results.push(Ok(None));
+            exprs.push(None);
         } else {
             let line_num: u32 = (i + 1) as u32;
             let line = unsafe { Span::new_from_raw_offset(0, line_num, &input, ()) };
-            results.push(evaluate_line(line, &mut eval));
+            match parse(line) {
+                Ok(expr) => {
+                    results.push(Ok(None));
+                    exprs.push(Some(expr));
+                },
+                Err(err) => {
+                    results.push(Err(err));
+                    exprs.push(None);
+                },
+            }
+        }
+    }
+
+    let EvaluationOrder { order, errors } = evaluation_order(&exprs.iter().map(Option::as_ref).collect::<Vec<_>>());
+    for i in order {
+        if let Some(expr) = &exprs[i] {
+            results[i] = eval.eval_expr_mut_context(expr);
         }
     }
+    for (i, err) in errors {
+        results[i] = Err(err);
+    }
     results
 }
 
+/// Results are displayed in the given system ("SI" by default) or, if given, a custom list of preferred units.
+/// Angles are shown in the `angle` unit (e.g. "deg") if there is one
+fn display_system(system: Option<String>, units: Option<Vec<String>>, angle: Option<String>) -> CResult<UnitSystem> {
+    let system = match units {
+        Some(units) => UnitSystem::custom(&units)?,
+        None => UnitSystem::named(system.as_deref().unwrap_or("SI"))?,
+    };
+    match angle {
+        Some(angle) => system.prefer(&angle),
+        None => Ok(system),
+    }
+}
+
+#[tauri::command]
+async fn evaluate_units(input: &str, max_depth: Option<usize>, system: Option<String>, units: Option<Vec<String>>, angle: Option<String>) -> Result<Vec<EvalResult<Value<String>>>, Error> {
+    let system = display_system(system, units, angle)?;
+    let results = evaluate_sheet::<Value<UnitVal>>(input, max_depth)?;
+    Ok(results.into_iter().map(|result| result.map(|val| val.map(|val| val.map(|val| val.to_string_in(&system))))).collect())
+}
+
 #[tauri::command]
-async fn evaluate_units(input: &str) -> Result<Vec<EvalResult<UnitVal>>, ()> {
-    let inputs = input.lines().collect::<Vec<&str>>();
-    Ok(evaluate_sequence(inputs))
+async fn evaluate_uncertain(input: &str, max_depth: Option<usize>, system: Option<String>, units: Option<Vec<String>>, angle: Option<String>) -> Result<Vec<EvalResult<Value<String>>>, Error> {
+    let system = display_system(system, units, angle)?;
+    let results = evaluate_sheet::<Value<Uncertain>>(input, max_depth)?;
+    Ok(results.into_iter().map(|result| result.map(|val| val.map(|val| val.map(|val| val.to_string_in(&system))))).collect())
 }
 
+/// Complex values are shown in rectangular form unless `form` is "polar" or "exponential"
 #[tauri::command]
-async fn evaluate_complex(input: &str) -> Result<Vec<EvalResult<Complex>>, ()> {
-    let inputs = input.lines().collect::<Vec<&str>>();
-    Ok(evaluate_sequence(inputs))
+async fn evaluate_complex_units(input: &str, max_depth: Option<usize>, system: Option<String>, units: Option<Vec<String>>, angle: Option<String>, form: Option<String>) -> Result<Vec<EvalResult<Value<String>>>, Error> {
+    let system = display_system(system, units, angle)?;
+    let form = form.as_deref().map(ComplexForm::named).transpose()?.unwrap_or_default();
+    let results = evaluate_sheet::<Value<ComplexUnitVal>>(input, max_depth)?;
+    Ok(results.into_iter().map(|result| result.map(|val| val.map(|val| val.map(|val| val.to_string_in(&system, form))))).collect())
 }
 
+/// Fractions are shown exactly, and approximations (e.g. `\\sqrt{2}`) to `precision` significant digits
 #[tauri::command]
-async fn evaluate_float(input: &str) -> Result<Vec<EvalResult<Float>>, ()> {
-    let inputs = input.lines().collect::<Vec<&str>>();
-    Ok(evaluate_sequence(inputs))
+async fn evaluate_exact(input: &str, max_depth: Option<usize>, precision: Option<u64>) -> Result<Vec<EvalResult<Value<String>>>, Error> {
+    let precision = precision.unwrap_or(Exact::DEFAULT_PRECISION);
+    let results = evaluate_sheet::<Value<Exact>>(input, max_depth)?;
+    Ok(results.into_iter().map(|result| result.map(|val| val.map(|val| val.map(|val| val.to_string_with(precision))))).collect())
+}
+
+#[tauri::command]
+async fn evaluate_interval(input: &str, max_depth: Option<usize>) -> Result<Vec<EvalResult<Value<Interval>>>, Error> {
+    evaluate_sheet(input, max_depth)
+}
+
+/// Polar angles are shown in the `angle` unit (radians by default)
+#[tauri::command]
+async fn evaluate_complex(input: &str, max_depth: Option<usize>, angle: Option<String>, form: Option<String>) -> Result<Vec<EvalResult<Value<String>>>, Error> {
+    let system = display_system(None, None, angle)?;
+    let form = form.as_deref().map(ComplexForm::named).transpose()?.unwrap_or_default();
+    let results = evaluate_sheet::<Value<Complex>>(input, max_depth)?;
+    Ok(results.into_iter().map(|result| result.map(|val| val.map(|val| val.map(|val| val.to_string_in(&system, form))))).collect())
+}
+
+#[tauri::command]
+async fn evaluate_float(input: &str, max_depth: Option<usize>) -> Result<Vec<EvalResult<Value<Float>>>, Error> {
+    evaluate_sheet(input, max_depth)
 }
 
 
@@ -80,7 +158,7 @@ fn main() {
     let mut input_file_contents = String::new();
     test_file.read_to_string(&mut input_file_contents).unwrap();
     let inputs = input_file_contents.lines().collect::<Vec<&str>>();
-    for (i, result) in evaluate_sequence::<fields::Complex>(inputs.clone()).iter().enumerate() {
+    for (i, result) in evaluate_sheet::<fields::Complex>(&input_file_contents, None).unwrap().iter().enumerate() {
       if let Ok(Some(val)) = result {
         println!("{} = {}", inputs[i], val);
       } else if let Err(err) = result {
@@ -91,7 +169,7 @@ fn main() {
     tauri::Builder::default()
       .menu(get_menus())
       .on_menu_event(handle_menu_event)
-      .invoke_handler(tauri::generate_handler![evaluate_units, evaluate_complex, evaluate_float, save_file])
+      .invoke_handler(tauri::generate_handler![evaluate_units, evaluate_uncertain, evaluate_exact, evaluate_interval, evaluate_complex, evaluate_complex_units, evaluate_float, save_file])
       .run(tauri::generate_context!())
       .expect("error while running tauri application");
   }
@@ -102,8 +180,9 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
-    use crate::{Evaluator, Span, evaluate_line};
-    use crate::fields::UnitVal;
+    use crate::{Evaluator, Span, evaluate_line, evaluate_sheet};
+    use crate::evaluator::MAX_DEPTH_LIMIT;
+    use crate::fields::{Float, UnitVal};
 
     #[test]
     fn valid_input() {
@@ -130,4 +209,17 @@ mod tests {
             evaluate_line(line, &mut eval).unwrap_err();
         }
     }
+
+    #[test]
+    fn runaway_recursion() {
+        // Stops before overflowing the stack
+        let results = evaluate_sheet::<Float>("g(x) = g(x) + 1\ng(1)", None).unwrap();
+        assert!(results[1].is_err());
+
+        // Sheets can allow deeper recursion, up to a limit
+        let count = "count(n) = \\{n \\le 1: 1, 1 + count(n - 1)\\}\ncount(400)";
+        assert_eq!(evaluate_sheet::<Float>(count, Some(500)).unwrap()[1].as_ref().unwrap(), &Some(400.0.into()));
+        assert!(evaluate_sheet::<Float>(count, Some(300)).unwrap()[1].is_err());
+        assert!(evaluate_sheet::<Float>(count, Some(MAX_DEPTH_LIMIT + 1)).is_err());
+    }
 }
@@ -3,14 +3,16 @@ use crate::error::{Error, ParseError};
 use crate::parsing_helpers::*;
 
 use nom::branch::alt;
-use nom::character::complete::{alpha1, char, digit1, space0};
-use nom::bytes::complete::{take, take_until};
+use nom::character::complete::{alpha1, char, digit1, satisfy, space0, space1};
+use nom::bytes::complete::{take, take_until, take_while1};
 use nom::character::{is_alphabetic, is_digit};
-use nom::combinator::map;
-use nom::multi::{many0, separated_list0};
-use nom::sequence::{delimited, pair, tuple};
+use nom::combinator::{map, not, opt, peek, verify};
+use nom::multi::{many0, many1, separated_list0, separated_list1};
+use nom::sequence::{delimited, pair, preceded, separated_pair, terminated, tuple};
+
+/// Each order of a derivative multiplies the work and loses precision, so only so many are allowed
+const MAX_DERIVATIVE_ORDER: u32 = 3;
 
-use std::str::FromStr;
 
 
 
@@ -31,23 +33,49 @@ pub(crate) fn parse<T>(input: Span) -> CResult<Expr<T>> where for<'a> T: BaseFie
 }
 
 fn parse_math_expr_or_def<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
-    let (input, expr) = alt((parse_def, parse_math_expr))(input)?;
+    let (input, expr) = alt((parse_def, parse_converted_expr))(input)?;
     Ok((input, expr))
 }
 
 fn parse_def<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
     let (rhs, lhs) = take_until("=")(input)?;
-    if lhs.contains('{') {
+    if lhs.replace("^{\\prime}", "").contains('{') {
         return Err(nom::Err::Error(
             ParseError::new("Matched definition, but it's most likely within a latex command. Is this corect?", lhs)
         ));
     }
+    // e.g. the "=" in "[x^2 for x=L]"
+    if lhs.contains('[') {
+        return Err(nom::Err::Error(ParseError::new("Matched definition, but it's within a list", lhs)));
+    }
+    // e.g. the "=" in "solve(x^2 = 2, x)"
+    if lhs.matches('(').count() > lhs.matches(')').count() {
+        return Err(nom::Err::Error(ParseError::new("Matched definition, but it's within brackets", lhs)));
+    }
+    // Unit definitions look like "unit furlong = 201.168 m"
+    let (lhs, unit_def) = opt(terminated(keyword("unit"), peek(start_alpha)))(lhs)?;
     let (lhs, var) = mcut(trim(start_alpha), "Variable name must start with an alphabetic character")(lhs)?;
+    if is_keyword(var.fragment()) {
+        return Err(nom::Err::Failure(ParseError::new("Keywords such as \"to\" and \"if\" can't be defined", var)));
+    }
+    let (lhs, primes) = parse_primes(lhs)?;
     let (rhs, _) = char('=')(rhs)?;
     let (rhs, _) = space0(rhs)?;
-    let (rhs, expr) = prepend_cut(parse_math_expr, "In RHS of definition")(rhs)?;
+    let (rhs, expr) = prepend_cut(parse_converted_expr, "In RHS of definition")(rhs)?;
+    if unit_def.is_some() {
+        if !lhs.trim().is_empty() {
+            return Err(nom::Err::Failure(ParseError::new("Unexpected input after unit name", lhs)));
+        }
+        return Ok((rhs, EDefUnit(var.to_string(), Box::new(expr))));
+    }
     if lhs.contains('(') {
         let (_, params) = mcut(parse_call_params::<T>,"Invalid function parameters")(lhs)?;
+        // A value at a point, e.g. "y(0) = 1" or "y'(0) = 0", is an initial value of a differential equation
+        if let [point] = params.as_slice() {
+            if !matches!(point, EVar(_)) {
+                return Ok((rhs, EDefInitialValue(var.to_string(), primes, Box::new(point.clone()), Box::new(expr))));
+            }
+        }
         // Assert each params is just a Var and get the string that makes it
         let mut param_strs = vec![String::from(""); params.len()];
         for (i, param) in params.iter().enumerate() {
@@ -56,16 +84,77 @@ fn parse_def<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> +
                 _ => return Err(nom::Err::Failure(ParseError::new("Unexpected expression in function definition", lhs))),
             };
         }
-        Ok((rhs, EDefFunc(var.to_string(), param_strs, Box::new(expr))))
+        if primes == 0 {
+            return Ok((rhs, EDefFunc(var.to_string(), param_strs, Box::new(expr))));
+        }
+        // e.g. "y'(x) = x y"
+        match <[String; 1]>::try_from(param_strs) {
+            Ok([param]) => Ok((rhs, EDefODE(var.to_string(), primes, Some(param), Box::new(expr)))),
+            Err(_) => Err(nom::Err::Failure(ParseError::new("Differential equations can only be of one variable", lhs))),
+        }
+    } else if primes > 0 {
+        // e.g. "y'' = -k y"
+        Ok((rhs, EDefODE(var.to_string(), primes, None, Box::new(expr))))
     } else {
         Ok((rhs, EDefVar(var.to_string(), Box::new(expr))))
     }
 }
 
+/// An expression that is optionally converted to a unit, e.g. "1 km to ft" or "1 km in ft"
+fn parse_converted_expr<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let (input, expr) = parse_math_expr(input)?;
+    let (input, unit) = opt(preceded(alt((keyword("to"), keyword("in"))), parse_unit_str))(input)?;
+    match unit {
+        Some(unit) => Ok((input, EConvert(Box::new(expr), unit))),
+        None => Ok((input, expr)),
+    }
+}
+
+/// Converts a (possibly latex) unit expression to the plain form understood by `UnitVal::from_unit_str`
+fn parse_unit_str(input: Span) -> BaseParseResult<String> {
+    let unit_chars = |c: char| c.is_alphanumeric() || "*/^-·°√".contains(c);
+    let (rest, parts) = many1(alt((
+        map(take_while1(unit_chars), |unit: Span| unit.fragment().to_string()),
+        map(pair(tag("\\cdot"), space0), |_| "*".to_string()),
+        map(alt((tag("\\ "), space1)), |_| " ".to_string()),
+        map(
+            delimited(alt((tag("("), tag("\\left("))), parse_unit_str, alt((tag(")"), tag("\\right)")))),
+            |unit| format!("({unit})")
+        ),
+        map(preceded(tag("\\frac"), pair(parse_unit_group, parse_unit_group)), |(num, denom)| format!("{num}/{denom}")),
+        map(preceded(tag("\\sqrt"), parse_unit_group), |unit| format!("√{unit}")),
+        parse_unit_group,
+    )))(input)?;
+    Ok((rest, parts.concat().trim().to_string()))
+}
+
+/// A unit expression in curly braces, which are only kept (as parentheses) when needed
+fn parse_unit_group(input: Span) -> BaseParseResult<String> {
+    map(delimited(tag("{"), parse_unit_str, tag("}")), |unit| {
+        if unit.contains(['*', '/', ' ', '·']) && !is_parenthesized(&unit) { format!("({unit})") } else { unit }
+    })(input)
+}
+
+fn is_parenthesized(unit: &str) -> bool {
+    let mut depth = 0;
+    for (i, c) in unit.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth == 0 => return false,
+            _ => (),
+        }
+        if depth == 0 && i + c.len_utf8() < unit.len() {
+            return false;
+        }
+    }
+    !unit.is_empty()
+}
+
 fn parse_math_expr<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
     // println!("expr -> term: {:?}", input.fragment());
     let (input, num1) = parse_term(input)?;
-    let term_splitters = alt((tag("+"), tag("-"))); 
+    let term_splitters = alt((tag("+"), tag("-"), tag("±"), tag("\\pm"))); 
     // println!("expr -> term2: {:?}", input.fragment());
     let (input, exprs) = many0(tuple((term_splitters, parse_term)))(input)?;
     // println!("expr done");
@@ -74,17 +163,38 @@ fn parse_math_expr<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<
 
 fn parse_term<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
     // println!("term -> factor: {:?}", input.fragment());
+    // A leading minus sign negates the whole term, e.g. "-x^2 + 1" or "5 - -2"
+    let (input, negated) = opt(trim(char('-')))(input)?;
     let (input, num1) = parse_term_no_fractions(input)?;
-    let term_splitters = alt((tag("/"), tag("*"), tag("\\cdot"))); 
+    let term_splitters = alt((tag("/"), tag("*"), tag("·"), tag("\\cdot"), tag("⊙"), tag("\\odot"))); 
     // println!("term -> factor2: {:?}", input.fragment());
     let (input, exprs) = many0(tuple((term_splitters, parse_term_no_fractions)))(input)?;
     // println!("term done");
-    Ok((input, map_ops(num1, exprs)))
+    let term = map_ops(num1, exprs);
+    match negated {
+        Some(_) => Ok((input, EMul(Box::new(ENum((-1.0).into())), Box::new(term)))),
+        None => Ok((input, term)),
+    }
 }
 
 fn parse_term_no_fractions<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
     // println!("factor -> insides: {:?}", input.fragment());
     let (input, base) = parse_component(input)?;
+    let (input, index) = opt(parse_index)(input)?;
+    let base = match index {
+        Some(index) => EIndex(Box::new(base), Box::new(index)),
+        None => base,
+    };
+    let (input, factorial) = opt(terminated(char('!'), not(char('='))))(input)?;
+    let base = match factorial {
+        Some(_) => latex_function("factorial", base),
+        None => base,
+    };
+    let (input, postfix) = opt(parse_matrix_postfix)(input)?;
+    let base = match postfix {
+        Some(name) => latex_function(name, base),
+        None => base,
+    };
     // println!("factor -> factor: {:?}", input.fragment());
     let (input, exprs) = many0(tuple((tag("^"), parse_term_no_fractions)))(input)?;
     // println!("factor done");
@@ -94,21 +204,355 @@ fn parse_term_no_fractions<T>(input: Span) -> ParseResult<T> where for<'a> T: Ba
 fn parse_component<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
     // println!("insides -> alt: {:?}", input.fragment());
     let (input, _) = trim(space0)(input)?;
-    alt((parse_parens, parse_implicit_multiply, parse_func_call, parse_latex, parse_number, parse_var_use))(input)
+    alt((
+        parse_polar, parse_implicit_multiply, parse_imaginary, parse_list, parse_matrix, parse_piecewise, parse_parens, parse_solve, parse_func_call,
+        parse_latex_const, parse_integral, parse_derivative, parse_latex, parse_root_symbol, parse_number, parse_var_use,
+    ))(input)
+}
+
+/// A plain text square root, e.g. "√Hz", which is evaluated like "\sqrt{Hz}"
+fn parse_root_symbol<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let (rest, param) = preceded(tag("√"), parse_term_no_fractions)(input)?;
+    Ok((rest, latex_function("sqrt", param)))
+}
+
+fn latex_function<T>(name: &str, param: Expr<T>) -> Expr<T> where for<'a> T: BaseField<'a> + 'a {
+    ETex(LatexExpr { name: name.to_string(), superscript: None, subscript: None, params: vec![param] })
+}
+
+/// A complex number in polar form, e.g. "5∠30°" or "(2 V) \angle \frac{\pi}{4}". Angles without a degree sign are in radians
+fn parse_polar<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    // Avoids parsing the magnitude of every term twice
+    if !input.contains('∠') && !input.contains("\\angle") {
+        return Err(nom::Err::Error(ParseError::new("Not a polar complex number", input)));
+    }
+    let (rest, (magnitude, _, angle)) = tuple((
+        alt((parse_implicit_multiply, parse_number, parse_parens, parse_latex)),
+        trim(alt((tag("∠"), tag("\\angle")))),
+        alt((parse_degrees, parse_term_no_fractions)),
+    ))(input)?;
+    // r∠θ = r (cos θ + i sin θ)
+    let imaginary = EMul(Box::new(imaginary_unit()), Box::new(latex_function("sin", angle.clone())));
+    let direction = EAdd(Box::new(latex_function("cos", angle)), Box::new(imaginary));
+    Ok((rest, EMul(Box::new(magnitude), Box::new(direction))))
+}
+
+/// An angle in degrees, e.g. "30°" or "30^{\circ}", which is converted to radians
+fn parse_degrees<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let (rest, angle) = terminated(parse_number, trim(alt((tag("°"), tag("^\\circ"), tag("^{\\circ}")))))(input)?;
+    Ok((rest, EMul(Box::new(angle), Box::new(ENum((std::f64::consts::PI / 180.0).into())))))
 }
 
+/// A number, matrix or parenthesized expression followed by a term, e.g. "2 m", "4i Ω", "[1, 2] m" or "(12.3 ± 0.1) mm"
 fn parse_implicit_multiply<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
     let (input, (num, var)) = 
-    pair(parse_number, parse_term_no_fractions)(input)?;
+    alt((
+        pair(parse_imaginary, parse_term_no_fractions),
+        // "2.5j" is an imaginary number rather than 2.5 times a variable j
+        preceded(not(parse_imaginary::<T>), pair(alt((parse_number, parse_parens, parse_list, parse_matrix)), parse_term_no_fractions)),
+    ))(input)?;
     // println!("found implicit multiply");
     Ok((input, EMul(Box::new(num), Box::new(var))))
 }
 
+/// A matrix such as "[1, 2; 3, 4]" or "\begin{bmatrix}1&2\\3&4\end{bmatrix}". Vectors are matrices with one row or column
+fn parse_matrix<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let environment = || alt((tag("bmatrix"), tag("pmatrix"), tag("matrix")));
+    let bracketed = delimited(
+        alt((tag("\\left["), tag("["))),
+        separated_list1(char(';'), separated_list1(char(','), parse_math_expr)),
+        alt((tag("\\right]"), tag("]"))),
+    );
+    let latex = delimited(
+        tuple((tag("\\begin{"), environment(), tag("}"))),
+        separated_list1(trim(tag("\\\\")), separated_list1(char('&'), parse_math_expr)),
+        tuple((tag("\\end{"), environment(), tag("}"))),
+    );
+    map(trim(alt((bracketed, latex))), EMatrix)(input)
+}
+
+/// A list, e.g. "[1, 2, 3]", a range such as "[1...10]" or "[1, 3, ..., 11]", or a comprehension such as "[x^2 for x=L]"
+fn parse_list<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let ellipsis = trim(alt((tag("..."), tag("\\ldots"), tag("\\dots"), tag("…"))));
+    let range = map(
+        tuple((parse_math_expr, opt(preceded(char(','), parse_math_expr)), opt(char(',')), ellipsis, opt(char(',')), parse_math_expr)),
+        |(first, second, _, _, _, last)| ERange(Box::new(first), second.map(Box::new), Box::new(last)),
+    );
+    let comprehension = map(
+        tuple((parse_math_expr, alt((tag("\\operatorname{for}"), keyword("for"))), start_alpha, trim(char('=')), parse_math_expr)),
+        |(expr, _, var, _, list)| EListComprehension(Box::new(expr), var.to_string(), Box::new(list)),
+    );
+    let elements = map(separated_list1(char(','), parse_math_expr), EList);
+    trim(delimited(
+        alt((tag("\\left["), tag("["))),
+        alt((range, comprehension, elements)),
+        alt((tag("\\right]"), tag("]"))),
+    ))(input)
+}
+
+/// An index into a list, counting from 1, e.g. "L[2]"
+fn parse_index<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    trim(delimited(alt((tag("\\left["), tag("["))), parse_math_expr, alt((tag("\\right]"), tag("]")))))(input)
+}
+
+/// A superscript that transposes or inverts a matrix, e.g. "A^T", "A^{\top}" or "A^{-1}"
+fn parse_matrix_postfix(input: Span) -> BaseParseResult<&'static str> {
+    let transpose = || alt((tag("T"), tag("\\top"), tag("\\intercal")));
+    preceded(tag("^"), alt((
+        map(alt((delimited(tag("{"), transpose(), tag("}")), terminated(transpose(), not(satisfy(char::is_alphanumeric))))), |_| "transpose"),
+        map(alt((tag("{-1}"), tag("-1"))), |_| "inv"),
+    )))(input)
+}
+
+/// A Desmos style piecewise expression, e.g. "\{x < 0: -x, x\}", or a latex cases environment such as
+/// "\begin{cases} -x & x < 0 \\ x & \text{otherwise} \end{cases}"
+fn parse_piecewise<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    // A condition on its own is 1 where it holds, like in Desmos
+    let desmos_branch = alt((
+        map(pair(parse_condition, opt(preceded(trim(char(':')), parse_math_expr))), |(condition, value)| {
+            (Some(condition), value.unwrap_or_else(|| ENum(1.0.into())))
+        }),
+        map(parse_math_expr, |value| (None, value)),
+    ));
+    let desmos = delimited(
+        alt((tag("\\left\\{"), tag("\\{"))),
+        separated_list1(char(','), desmos_branch),
+        alt((tag("\\right\\}"), tag("\\}"))),
+    );
+    let otherwise = alt((tag("\\text{otherwise}"), tag("\\text{else}"), tag("otherwise"), tag("else")));
+    let if_keyword = alt((tag("\\text{if}"), tag("\\text{if }"), keyword("if")));
+    let cases_branch = separated_pair(
+        parse_math_expr,
+        char('&'),
+        alt((map(trim(otherwise), |_| None), map(preceded(opt(trim(if_keyword)), parse_condition), Some))),
+    );
+    let cases = delimited(
+        tag("\\begin{cases}"),
+        separated_list1(trim(tag("\\\\")), map(cases_branch, |(value, condition)| (condition, value))),
+        tag("\\end{cases}"),
+    );
+    let (rest, branches) = trim(alt((desmos, cases)))(input)?;
+    let mut conditions = vec![];
+    let mut otherwise = None;
+    for (condition, value) in branches {
+        if otherwise.is_some() {
+            return Err(nom::Err::Failure(ParseError::new("The value without a condition must come last", input)));
+        }
+        match condition {
+            Some(condition) => conditions.push((condition, value)),
+            None => otherwise = Some(Box::new(value)),
+        }
+    }
+    Ok((rest, EPiecewise(conditions, otherwise)))
+}
+
+/// Comparisons joined by "and" and "or", e.g. "0 < x < 1 or x = 2". "and" takes precedence over "or"
+fn parse_condition<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let or = || trim(alt((keyword("or"), tag("\\operatorname{or}"), tag("\\lor"), tag("\\vee"), tag("∨"))));
+    let and = || trim(alt((keyword("and"), tag("\\operatorname{and}"), tag("\\land"), tag("\\wedge"), tag("∧"))));
+    let conjunction = |input| {
+        let (input, first) = parse_comparison(input)?;
+        let (input, rest) = many0(preceded(and(), parse_comparison))(input)?;
+        Ok((input, rest.into_iter().fold(first, |lhs, rhs| EAnd(Box::new(lhs), Box::new(rhs)))))
+    };
+    let (input, first) = conjunction(input)?;
+    let (input, rest) = many0(preceded(or(), conjunction))(input)?;
+    Ok((input, rest.into_iter().fold(first, |lhs, rhs| EOr(Box::new(lhs), Box::new(rhs)))))
+}
+
+/// A comparison such as "x \le 1 km", or a chain of them such as "0 < x < 1"
+fn parse_comparison<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let (input, first) = parse_math_expr(input)?;
+    let (input, rest) = many1(pair(parse_comparison_op, parse_math_expr))(input)?;
+    let mut lhs = first;
+    let mut comparisons = vec![];
+    for (comparison, rhs) in rest {
+        comparisons.push(ECompare(Box::new(lhs), comparison, Box::new(rhs.clone())));
+        lhs = rhs;
+    }
+    let first = comparisons.remove(0);
+    Ok((input, comparisons.into_iter().fold(first, |lhs, rhs| EAnd(Box::new(lhs), Box::new(rhs)))))
+}
+
+fn parse_comparison_op(input: Span) -> BaseParseResult<Comparison> {
+    // e.g. "\le" but not "\left("
+    let command = |name| terminated(tag(name), not(satisfy(char::is_alphabetic)));
+    trim(alt((
+        map(alt((tag("<="), tag("≤"), command("\\leq"), command("\\le"))), |_| Comparison::LessEqual),
+        map(alt((tag(">="), tag("≥"), command("\\geq"), command("\\ge"))), |_| Comparison::GreaterEqual),
+        map(alt((tag("!="), tag("≠"), command("\\neq"), command("\\ne"))), |_| Comparison::NotEqual),
+        map(alt((tag("<"), command("\\lt"))), |_| Comparison::Less),
+        map(alt((tag(">"), command("\\gt"))), |_| Comparison::Greater),
+        map(alt((tag("=="), tag("="))), |_| Comparison::Equal),
+    )))(input)
+}
+
 fn parse_func_call<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
-    let (input, name) = start_alpha(input)?;
+    // Names that aren't latex commands come through as e.g. "\operatorname{mean}"
+    let (input, name) = alt((delimited(tag("\\operatorname{"), alpha1, tag("}")), start_alpha))(input)?;
+    let (input, primes) = parse_primes(input)?;
     let (input, params) = parse_call_params(input)?;
+    let (input, _) = space0(input)?;
     // println!("found func call");
-    Ok((input, EFunc(name.to_string(), params)))
+    if primes == 0 {
+        return Ok((input, EFunc(name.to_string(), params)));
+    }
+    // f'(a) is the derivative of f(x) at x = a. The variable can't clash with any others, as names can't contain "'"
+    let argument = match <[Expr<T>; 1]>::try_from(params) {
+        Ok([argument]) => argument,
+        Err(_) => return Err(nom::Err::Failure(ParseError::new("Only functions of one variable can be differentiated with '", name))),
+    };
+    let var = format!("{name}'");
+    let mut derivative = EFunc(name.to_string(), vec![EVar(var.clone())]);
+    for _ in 1..primes {
+        derivative = EDerivative(Box::new(derivative), var.clone(), Box::new(EVar(var.clone())));
+    }
+    Ok((input, EDerivative(Box::new(derivative), var, Box::new(argument))))
+}
+
+/// An equation to solve for a variable, e.g. "solve(x^2 = 2, x)", optionally near a guess, e.g.
+/// "\operatorname{solve}(\cos(x) = x, x, 1)", or between two limits, e.g. "solve(\sin(x) = 0.5, x, 0, 10)"
+fn parse_solve<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let (rest, _) = tuple((
+        alt((tag("\\operatorname{solve}"), terminated(tag("solve"), not(satisfy(char::is_alphanumeric))))),
+        alt((tag("("), tag("\\left("))),
+    ))(input)?;
+    let (rest, (lhs, _, rhs, _, var, limits, _)) = mcut(tuple((
+        parse_math_expr, trim(char('=')), parse_math_expr, char(','), trim(start_alpha),
+        many0(preceded(char(','), parse_math_expr)), alt((tag(")"), tag("\\right)"))),
+    )), "Expected an equation and the variable to solve for, e.g. solve(x^2 = 2, x)")(rest)?;
+    if limits.len() > 2 {
+        return Err(nom::Err::Failure(ParseError::new("solve can only be given a guess, or two limits to search between", input)));
+    }
+    let (rest, _) = space0(rest)?;
+    Ok((rest, ESolve(Box::new(lhs), Box::new(rhs), var.to_string(), limits)))
+}
+
+/// A derivative of the term after it, e.g. "\frac{d}{dx} x^2" or "\frac{d^2}{dt^2} \sin(t)", at the current value
+/// of the variable
+fn parse_derivative<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let order = || opt(preceded(char('^'), alt((delimited(char('{'), digit1, char('}')), digit1))));
+    let (rest, (_, numerator_order, _, var, denominator_order, _)) = tuple((
+        tag("\\frac{d"), order(), tag("}{d"), start_alpha, order(), char('}'),
+    ))(input)?;
+    let order = match (numerator_order, denominator_order) {
+        (None, None) => 1,
+        (Some(numerator), Some(denominator)) if numerator.fragment() == denominator.fragment() => {
+            numerator.fragment().parse().unwrap_or(0)
+        },
+        _ => return Err(nom::Err::Failure(ParseError::new("The orders of the derivative don't match", input))),
+    };
+    if !(1..=MAX_DERIVATIVE_ORDER).contains(&order) {
+        return Err(nom::Err::Failure(ParseError::new(&format!("Derivatives can only be taken up to {MAX_DERIVATIVE_ORDER} times"), input)));
+    }
+    let (rest, mut expr) = parse_term(rest)?;
+    for _ in 0..order {
+        expr = EDerivative(Box::new(expr), var.to_string(), Box::new(EVar(var.to_string())));
+    }
+    Ok((rest, expr))
+}
+
+/// A definite integral, e.g. "\int_0^1 x^2 dx" or "\int_{0}^{\infty} e^{-t} \mathrm{d}t". The integrand is everything up
+/// to the differential
+fn parse_integral<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let (rest, _) = terminated(tag("\\int"), not(alpha1))(input)?;
+    let lower = || preceded(char('_'), parse_limit::<T>);
+    let upper = || preceded(char('^'), parse_limit::<T>);
+    let (rest, (lower, upper)) = mcut(
+        alt((pair(lower(), upper()), map(pair(upper(), lower()), |(upper, lower)| (lower, upper)))),
+        "Integrals need a lower and an upper limit, e.g. \\int_0^1",
+    )(rest)?;
+    let spacing = || many0(alt((tag("\\,"), tag("\\;"), tag("\\:"), tag("\\!"), tag("\\ "), space1)));
+    let mut integrand_error = None;
+    for position in differential_positions(rest.fragment()) {
+        let (differential, integrand) = take(rest.fragment()[..position].chars().count())(rest)?;
+        let (after, var) = preceded(alt((tag("\\mathrm{d}"), tag("\\operatorname{d}"), tag("\\text{d}"), tag("d"))), terminated(start_alpha, space0))(differential)?;
+        // e.g. "\int_0^1 dx" integrates one
+        if integrand.fragment().trim().is_empty() {
+            return Ok((after, EIntegral(Box::new(ENum(1.0.into())), var.to_string(), lower, upper)));
+        }
+        match terminated(parse_math_expr, spacing())(integrand) {
+            Ok((left, integrand)) if left.is_empty() => {
+                return Ok((after, EIntegral(Box::new(integrand), var.to_string(), lower, upper)));
+            },
+            Ok((left, _)) => integrand_error = Some(ParseError::new("Failed to parse the integrand", left)),
+            Err(nom::Err::Error(e) | nom::Err::Failure(e)) => integrand_error = Some(e),
+            Err(nom::Err::Incomplete(_)) => (),
+        }
+    }
+    match integrand_error {
+        Some(e) => Err(nom::Err::Failure(e)),
+        None => Err(nom::Err::Failure(ParseError::new("Integrals need a differential after the integrand, e.g. dx", input))),
+    }
+}
+
+/// A limit of an integral, which can also be infinite, e.g. "0", "{2 m}", "\infty" or "{-∞}"
+fn parse_limit<T>(input: Span) -> BaseParseResult<Limit<Box<Expr<T>>>> where for<'a> T: BaseField<'a> + 'a {
+    alt((
+        delimited(char('{'), parse_infinity, char('}')),
+        parse_infinity,
+        map(parse_latex_param(parse_math_expr), |limit| Limit::Finite(Box::new(limit))),
+    ))(input)
+}
+
+fn parse_infinity<V>(input: Span) -> BaseParseResult<Limit<V>> {
+    let sign = opt(terminated(alt((char('+'), char('-'))), space0));
+    let infinity = alt((terminated(tag("\\infty"), not(alpha1)), tag("∞")));
+    let (rest, (sign, _)) = delimited(space0, pair(sign, infinity), space0)(input)?;
+    match sign {
+        Some('-') => Ok((rest, Limit::NegativeInfinity)),
+        _ => Ok((rest, Limit::Infinity)),
+    }
+}
+
+/// Byte offsets in the rest of an integral where its differential could start: a "d" or "\mathrm{d}" followed by a
+/// variable, outside of any brackets. The differentials of integrals nested inside it are skipped
+fn differential_positions(input: &str) -> Vec<usize> {
+    let mut positions = vec![];
+    let (mut depth, mut nested_integrals) = (0usize, 0usize);
+    let mut previous = ' ';
+    let mut i = 0;
+    while let Some(c) = input[i..].chars().next() {
+        let rest = &input[i..];
+        let mut length = c.len_utf8();
+        let mut is_differential = false;
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' if depth == 0 => break,
+            ')' | ']' | '}' => depth -= 1,
+            '\\' => match ["\\mathrm{d}", "\\operatorname{d}", "\\text{d}"].iter().find(|d| rest.starts_with(**d)) {
+                Some(d) => {
+                    is_differential = true;
+                    length = d.len();
+                },
+                None => {
+                    // Skips over the name of the command, or a single symbol such as "\,"
+                    let name = &rest[1..rest[1..].find(|c: char| !c.is_ascii_alphabetic()).map_or(rest.len(), |end| end + 1)];
+                    if name == "int" && depth == 0 {
+                        nested_integrals += 1;
+                    }
+                    length = 1 + if name.is_empty() { rest[1..].chars().next().map_or(0, char::len_utf8) } else { name.len() };
+                },
+            },
+            'd' => is_differential = !previous.is_alphabetic() && rest[1..].starts_with(char::is_alphabetic),
+            _ => (),
+        }
+        if is_differential && depth == 0 {
+            if nested_integrals > 0 {
+                nested_integrals -= 1;
+            } else {
+                positions.push(i);
+            }
+        }
+        previous = rest[..length].chars().last().unwrap_or(c);
+        i += length;
+    }
+    positions
+}
+
+/// The number of primes after a name, e.g. 2 for "y''" or "y^{\\prime}^{\\prime}"
+fn parse_primes(input: Span) -> BaseParseResult<usize> {
+    map(many0(alt((tag("'"), tag("^{\\prime}"), tag("^\\prime")))), |primes| primes.len())(input)
 }
 
 fn parse_call_params<T>(input: Span) -> ParseResultVec<T> where for<'a> T: BaseField<'a> + 'a {
@@ -121,8 +565,10 @@ fn parse_call_params<T>(input: Span) -> ParseResultVec<T> where for<'a> T: BaseF
 
 fn parse_latex<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
     // println!("testing for latex: {:?}", input.fragment());
+    // Environments such as matrices are parsed separately
+    let not_environment = |name: &Span| !matches!(*name.fragment(), "begin" | "end");
     let (rest, (_, func_name, script_params)) = tuple((
-        char('\\'), alpha1, 
+        char('\\'), verify(alpha1, not_environment), 
         many0(
             alt((
                 pair(char('^'), parse_latex_param(parse_math_expr)),
@@ -184,7 +630,7 @@ fn parse_latex_param<F, T>(f: F) -> impl FnMut(Span) -> ParseResult<T>
 
 fn parse_number<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
     alt((
-        parse_decimal,
+        trim(parse_decimal),
         map(trim(digit1), parse_enum),
     ))(input)
 }
@@ -200,29 +646,61 @@ fn parse_enum<T>(parsed_num: Span) -> Expr<T> where for<'a> T: BaseField<'a> + '
 }
 
 fn into_enum<T>(parsed_num: &str) -> Expr<T> where for<'a> T: BaseField<'a> + 'a {
-    let num = f64::from_str(parsed_num).unwrap();
-    ENum(num.into())
+    ENum(T::from_literal(parsed_num))
 }
 
-fn parse_var_use<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
-    map(trim(start_alpha), parse_evar)(input)
+/// An imaginary number such as "3i", "2.5j" or a bare "i", but not the start of a longer name such as "in".
+/// A bare "i" is looked up like a variable so a sum's index can shadow it, and "j" is only imaginary after a number
+fn parse_imaginary<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let end_of_name = || not(satisfy(char::is_alphanumeric));
+    let number = alt((parse_decimal, map(digit1, parse_enum)));
+    // Powers only apply to the unit, e.g. "3i^2" is -3
+    let powers = many0(tuple((tag("^"), parse_term_no_fractions)));
+    trim(alt((
+        map(
+            pair(terminated(number, terminated(alt((tag("i"), tag("j"))), end_of_name())), powers),
+            |(num, powers)| EMul(Box::new(num), Box::new(map_ops(imaginary_unit(), powers))),
+        ),
+        map(terminated(tag("i"), end_of_name()), parse_evar),
+    )))(input)
 }
 
-fn parse_evar<T>(input: Span) -> Expr<T> where for<'a> T: BaseField<'a> + 'a {
-    match_const(input)
-        .or_else(|_| match_unit(input))
-        .or_else(|_| Ok::<Expr<T>, Box<dyn std::error::Error>>(EVar(input.fragment().to_string()))).unwrap()
+/// The imaginary unit in fields that have one, so that a variable named i doesn't change "3i" or "5∠30°"
+fn imaginary_unit<T>() -> Expr<T> where for<'a> T: BaseField<'a> + 'a {
+    T::try_from("i").map(ENum).unwrap_or_else(|_| EVar("i".to_string()))
+}
+
+/// Constants written as latex commands or unicode symbols, e.g. "\pi" or "π"
+fn parse_latex_const<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let (rest, name) = trim(terminated(alt((tag("\\pi"), tag("π"))), not(alpha1)))(input)?;
+    Ok((rest, parse_evar(name)))
 }
 
-fn match_unit<T>(input: Span) -> Result<Expr<T>, Box<dyn std::error::Error>> where for<'a> T: BaseField<'a> + 'a {
-    let unit_val = (*input.fragment()).try_into()?;
-    Ok(ENum(unit_val))
+/// "to" is reserved for conversions, "for" for list comprehensions and "and", "or" and "if" for conditions.
+/// "in" can't be since it's also inches, so "5 in mm" is 5 inches followed by "mm" rather than a conversion
+fn is_keyword(name: &str) -> bool {
+    matches!(name, "to" | "for" | "and" | "or" | "if" | "otherwise" | "else")
+}
+
+fn parse_var_use<T>(input: Span) -> ParseResult<T> where for<'a> T: BaseField<'a> + 'a {
+    let (rest, (var, primes)) = trim(pair(verify(start_alpha, |var: &Span| !is_keyword(var.fragment())), parse_primes))(input)?;
+    // Derivatives in differential equations, e.g. the y' in "y'' = -y' - y"
+    match primes {
+        0 => Ok((rest, parse_evar(var))),
+        _ => Ok((rest, EVar(primed_name(var.fragment(), primes)))),
+    }
+}
+
+/// Units are left as variables, which the evaluator resolves after any variables of the same name, so that e.g. "t"
+/// can be a variable rather than tonnes
+fn parse_evar<T>(input: Span) -> Expr<T> where for<'a> T: BaseField<'a> + 'a {
+    match_const(input).unwrap_or_else(|_| EVar(input.fragment().to_string()))
 }
 
 fn match_const<T>(input: Span) -> Result<Expr<T>, Box<dyn std::error::Error>> where for<'a> T: BaseField<'a> + 'a {
     match *input.fragment() {
         "e" => Ok(ENum(std::f64::consts::E.into())),
-        "pi" => Ok(ENum(std::f64::consts::PI.into())),
+        "pi" | "\\pi" | "π" => Ok(ENum(std::f64::consts::PI.into())),
         _ => Err(Box::new(ParseError::new("Unknown constant", input))),
     }
 }
@@ -244,7 +722,9 @@ fn parse_op<T>(tup: (Span, Expr<T>), expr1: Expr<T>) -> Expr<T> where for<'a> T:
     match *op.fragment() {
         "+" => EAdd(Box::new(expr1), Box::new(expr2)),
         "-" => ESub(Box::new(expr1), Box::new(expr2)),
-        "*" | "\\cdot" => EMul(Box::new(expr1), Box::new(expr2)),
+        "±" | "\\pm" => EPlusMinus(Box::new(expr1), Box::new(expr2)),
+        "*" | "·" | "\\cdot" => EMul(Box::new(expr1), Box::new(expr2)),
+        "⊙" | "\\odot" => EHadamard(Box::new(expr1), Box::new(expr2)),
         "/" => EDiv(Box::new(expr1), Box::new(expr2)),
         "^" => EExp(Box::new(expr1), Box::new(expr2)),
         _ => panic!("Unknown Operation, {:?}", op),
@@ -343,6 +823,42 @@ mod tests {
         assert_eq!(parsed, expected);
     }
 
+    #[test]
+    fn test_differential_equation() {
+        let parsed = parse::<UnitVal>("y'' = -y'".into()).unwrap();
+        let expected = EDefODE(
+            "y".to_string(),
+            2,
+            None,
+            Box::new(EMul(boxed_num(-1.0), Box::new(EVar("y'".to_string())))),
+        );
+        assert_eq!(parsed, expected);
+        let parsed = parse::<UnitVal>("y^{\\prime}(x) = x".into()).unwrap();
+        assert_eq!(parsed, EDefODE("y".to_string(), 1, Some("x".to_string()), Box::new(EVar("x".to_string()))));
+        let parsed = parse::<UnitVal>("y'(0) = 1".into()).unwrap();
+        assert_eq!(parsed, EDefInitialValue("y".to_string(), 1, boxed_num(0.0), boxed_num(1.0)));
+        assert!(parse::<UnitVal>("y'(x, z) = x".into()).is_err());
+    }
+
+    #[test]
+    fn test_unit_definition() {
+        let parsed = parse::<UnitVal>("unit furlong = 201.168 m".into()).unwrap();
+        let expected = EDefUnit(
+            "furlong".to_string(),
+            Box::new(EMul(boxed_num(201.168), Box::new(EVar("m".to_string())))),
+        );
+        assert_eq!(parsed, expected);
+        let parsed = parse::<UnitVal>("unit\\ rpm=1/min".into()).unwrap();
+        let expected = EDefUnit(
+            "rpm".to_string(),
+            Box::new(EDiv(boxed_num(1.0), Box::new(EVar("min".to_string())))),
+        );
+        assert_eq!(parsed, expected);
+        // "unit" is still a valid variable name
+        let parsed = parse::<UnitVal>("unit = 2".into()).unwrap();
+        assert_eq!(parsed, EDefVar("unit".to_string(), boxed_num(2.0)));
+    }
+
     #[test]
     fn test_function_call() {
         let parsed = parse::<UnitVal>("f(1,a)".into()).unwrap();
@@ -353,6 +869,32 @@ mod tests {
         assert_eq!(parsed, expected);
     }
 
+    #[test]
+    fn test_piecewise() {
+        let var = |name: &str| Box::new(EVar(name.to_string()));
+        let negative = || ECompare(var("x"), Comparison::Less, Box::new(num(0.0)));
+        let negated = || EMul(Box::new(num(-1.0)), var("x"));
+        let expected = EPiecewise(vec![(negative(), negated())], Some(var("x")));
+        assert_eq!(parse::<UnitVal>("\\left\\{x<0:-x,x\\right\\}".into()).unwrap(), expected);
+        assert_eq!(parse::<UnitVal>("\\begin{cases} -x & \\text{if } x < 0 \\\\ x & \\text{otherwise} \\end{cases}".into()).unwrap(), expected);
+
+        // "and" takes precedence, and chained comparisons are joined with it
+        let parsed = parse::<UnitVal>("\\{0 \\le x < 1 or x = 2\\}".into()).unwrap();
+        let expected = EPiecewise(vec![(
+            EOr(
+                Box::new(EAnd(
+                    Box::new(ECompare(Box::new(num(0.0)), Comparison::LessEqual, var("x"))),
+                    Box::new(ECompare(var("x"), Comparison::Less, Box::new(num(1.0)))),
+                )),
+                Box::new(ECompare(var("x"), Comparison::Equal, Box::new(num(2.0)))),
+            ),
+            num(1.0),
+        )], None);
+        assert_eq!(parsed, expected);
+
+        parse::<UnitVal>("\\{1, x > 0: 2\\}".into()).unwrap_err();
+    }
+
     #[test]
     fn test_latex() {
         let parsed = parse::<UnitVal>("\\frac{1}{2}".into()).unwrap();
@@ -384,14 +926,30 @@ mod tests {
     fn test_units() {
         let parsed = parse::<UnitVal>("1 km + 1 m".into()).unwrap();
         let expected = EAdd(
-            Box::new(EMul(boxed_num(1.0), Box::new(ENum(UnitVal::new_identity("km"))))),
-            Box::new(EMul(boxed_num(1.0), Box::new(ENum(UnitVal::new_identity("m"))))),
+            Box::new(EMul(boxed_num(1.0), Box::new(EVar("km".to_string())))),
+            Box::new(EMul(boxed_num(1.0), Box::new(EVar("m".to_string())))),
         );
         assert_eq!(parsed, expected);
         let parsed = parse::<UnitVal>("100 m^2".into()).unwrap();
         let expected = EMul(
             boxed_num(100.0),
-            Box::new(EExp(Box::new(ENum(UnitVal::new_identity("m"))), boxed_num(2.0)))
+            Box::new(EExp(Box::new(EVar("m".to_string())), boxed_num(2.0)))
+        );
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn test_conversion() {
+        let parsed = parse::<UnitVal>("1 km to ft".into()).unwrap();
+        let expected = EConvert(
+            Box::new(EMul(boxed_num(1.0), Box::new(EVar("km".to_string())))),
+            "ft".to_string(),
+        );
+        assert_eq!(parsed, expected);
+        let parsed = parse::<UnitVal>("1\\ in\\ in\\ m^{2}\\cdot s".into()).unwrap();
+        let expected = EConvert(
+            Box::new(EMul(boxed_num(1.0), Box::new(EVar("in".to_string())))),
+            "m^2*s".to_string(),
         );
         assert_eq!(parsed, expected);
     }
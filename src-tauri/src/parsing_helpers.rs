@@ -2,7 +2,8 @@ use crate::types::{Span, BaseParseResult, ParseResultStr};
 use crate::error::ParseError;
 
 
-use nom::character::complete::{alphanumeric1, space0};
+use nom::bytes::complete::take_while1;
+use nom::character::complete::{space0, space1};
 use nom::branch::alt;
 use nom::sequence::delimited;
 
@@ -10,8 +11,9 @@ use nom::sequence::delimited;
 
 pub fn start_alpha(input: Span) -> ParseResultStr {
     let (input, _) = space0(input)?;
-    let (input, first) = alphanumeric1(input)?;
-    if first.fragment().starts_with(|c: char| c.is_alphabetic()) {
+    // Allows units like °C and μm
+    let (input, first) = take_while1(|c: char| c.is_alphanumeric() || c == '°')(input)?;
+    if first.fragment().starts_with(|c: char| c.is_alphabetic() || c == '°') {
         Ok((input, first))
     } else {
         Err(nom::Err::Error(ParseError::new("Expected alphabetic character", first)))
@@ -53,6 +55,11 @@ pub fn trim<'a, O, F>(f: F) -> impl FnMut(Span<'a>) -> BaseParseResult<'a, O>
     delimited(alt((tag("\\ "), space0)), f, alt((tag("\\ "), space0)))
 }
 
+/// Matches a word that is followed by whitespace (e.g. " to " but not "tons")
+pub fn keyword<'a>(word: &str) -> impl FnMut(Span<'a>) -> ParseResultStr<'a> {
+    delimited(alt((tag("\\ "), space0)), tag(word), alt((tag("\\ "), space1)))
+}
+
 pub fn tag(s: &str) -> impl Fn(Span) -> ParseResultStr
 {
     let s = s.bytes().collect::<Vec<u8>>();
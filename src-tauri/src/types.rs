@@ -1,12 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use nom;
 use nom_locate::LocatedSpan;
 
+use crate::calculus;
 use crate::error::{Error, ParseError};
+use crate::fields::{Unit, UnitRegistry};
 
 
 pub type CResult<T> = Result<T, Error>;
 
+/// Largest n for which n! is calculated, so that a typo doesn't hang the app
+const MAX_FACTORIAL: i64 = 1000;
+
 
 pub trait BaseField<'a>: 
     std::fmt::Debug + Clone +
@@ -16,16 +21,248 @@ pub trait BaseField<'a>:
     std::convert::From<f64> +
     std::ops::Add<Output = CResult<Self>> +
     std::ops::Sub<Output = CResult<Self>> +
-    std::ops::Mul<Output = Self> +
-    std::ops::Div<Output = Self>
+    std::ops::Mul<Output = CResult<Self>> +
+    std::ops::Div<Output = CResult<Self>>
 {
     fn as_scalar(&self) -> CResult<f64>;
     fn powf(&self, exp: Self) -> CResult<Self>;
     fn root(&self, n: Self) -> CResult<Self>;
     fn fract(&self) -> CResult<f64>;
+    /// The value as a number that is known exactly, for functions of whole numbers such as `n!`
+    fn as_exact_scalar(&self) -> CResult<f64> {
+        self.as_scalar()
+    }
     fn sin(&self) -> CResult<Self>;
     fn cos(&self) -> CResult<Self>;
     fn tan(&self) -> CResult<Self>;
+    fn asin(&self) -> CResult<Self>;
+    fn acos(&self) -> CResult<Self>;
+    fn atan(&self) -> CResult<Self>;
+    /// The absolute value, or the magnitude of a complex number
+    fn abs(&self) -> CResult<Self>;
+    /// The real part of a complex number. Real values are their own real part
+    fn re(&self) -> CResult<Self> {
+        Ok(self.clone())
+    }
+    /// The imaginary part of a complex number, which is zero (in the same units) for real values
+    fn im(&self) -> CResult<Self> {
+        self.clone() * Self::from(0.0)
+    }
+    /// The complex conjugate
+    fn conj(&self) -> CResult<Self> {
+        Ok(self.clone())
+    }
+    /// The argument (phase angle) of a complex number, between -π and π
+    fn arg(&self) -> CResult<Self> {
+        Ok(Self::from(if self.as_scalar()? < 0.0 { std::f64::consts::PI } else { 0.0 }))
+    }
+    /// Applies a function of real numbers to a value without units. The functions below default to this, and are
+    /// overridden by fields that can do better, e.g. for complex numbers or to propagate uncertainties
+    fn map_real(&self, _name: &str, f: impl Fn(f64) -> f64) -> CResult<Self> {
+        Ok(Self::from(f(self.as_scalar()?)))
+    }
+    fn sinh(&self) -> CResult<Self> {
+        self.map_real("sinh", f64::sinh)
+    }
+    fn cosh(&self) -> CResult<Self> {
+        self.map_real("cosh", f64::cosh)
+    }
+    fn tanh(&self) -> CResult<Self> {
+        self.map_real("tanh", f64::tanh)
+    }
+    fn asinh(&self) -> CResult<Self> {
+        self.map_real("asinh", f64::asinh)
+    }
+    fn acosh(&self) -> CResult<Self> {
+        self.map_real("acosh", f64::acosh)
+    }
+    fn atanh(&self) -> CResult<Self> {
+        self.map_real("atanh", f64::atanh)
+    }
+    /// The natural logarithm
+    fn ln(&self) -> CResult<Self> {
+        self.map_real("ln", f64::ln)
+    }
+    fn exp(&self) -> CResult<Self> {
+        self.map_real("exp", f64::exp)
+    }
+    /// The logarithm to the given base, e.g. `\log_2 8 = 3`
+    fn log(&self, base: &Self) -> CResult<Self> {
+        let base = base.as_scalar()?;
+        self.map_real("log", |x| log(x, base))
+    }
+    fn floor(&self) -> CResult<Self> {
+        self.map_real("floor", f64::floor)
+    }
+    fn ceil(&self) -> CResult<Self> {
+        self.map_real("ceil", f64::ceil)
+    }
+    /// Rounds to the nearest whole number, and halves away from zero
+    fn round(&self) -> CResult<Self> {
+        self.map_real("round", f64::round)
+    }
+    /// -1, 0 or 1 depending on the sign of the value
+    fn sign(&self) -> CResult<Self> {
+        self.map_real("sign", |x| if x == 0.0 { 0.0 } else { x.signum() })
+    }
+    /// The remainder of floored division, which has the same sign as `rhs` (e.g. `mod(-1, 3) = 2`)
+    fn modulo(&self, rhs: &Self) -> CResult<Self> {
+        let quotient = (self.clone() / rhs.clone())?.floor()?;
+        self.clone() - (rhs.clone() * quotient)?
+    }
+    /// The greatest common divisor of two whole numbers
+    fn gcd(&self, rhs: &Self) -> CResult<Self> {
+        let (a, b) = (whole_number(self, "gcd")?, whole_number(rhs, "gcd")?);
+        Ok(Self::from(gcd(a, b) as f64))
+    }
+    /// The least common multiple of two whole numbers
+    fn lcm(&self, rhs: &Self) -> CResult<Self> {
+        let (a, b) = (whole_number(self, "lcm")?, whole_number(rhs, "lcm")?);
+        if a == 0 || b == 0 {
+            return Ok(Self::from(0.0));
+        }
+        Ok(Self::from((a / gcd(a, b) * b).abs() as f64))
+    }
+    /// `n!` for a whole number `n`. The product is taken in the field, so e.g. exact values stay exact
+    fn factorial(&self) -> CResult<Self> {
+        let n = whole_number(self, "factorial")?;
+        if n < 0 {
+            return Err(Error::EvalError(format!("Factorials are only defined from 0 upwards, but got {n}")));
+        }
+        if n > MAX_FACTORIAL {
+            return Err(Error::EvalError(format!("{n}! is too large, factorials are only calculated up to {MAX_FACTORIAL}!")));
+        }
+        let product = (1..=n).try_fold(Self::from(1.0), |product, k| product * Self::from(k as f64))?;
+        if product.overflowed() {
+            return Err(Error::EvalError(format!("{n}! is too large to calculate in this mode")));
+        }
+        Ok(product)
+    }
+    /// The number of ways to pick `r` of `self` items in order (`nPr`)
+    fn permutations(&self, r: &Self) -> CResult<Self> {
+        let (n, r) = (whole_number(self, "nPr")?, whole_number(r, "nPr")?);
+        if r < 0 || r > n {
+            return Err(Error::EvalError(format!("nPr({n}, {r}) needs 0 ≤ r ≤ n")));
+        }
+        if r > MAX_FACTORIAL {
+            return Err(Error::EvalError(format!("nPr({n}, {r}) is too large to calculate")));
+        }
+        let product = (n - r + 1..=n).try_fold(Self::from(1.0), |product, k| product * Self::from(k as f64))?;
+        if product.overflowed() {
+            return Err(Error::EvalError(format!("nPr({n}, {r}) is too large to calculate in this mode")));
+        }
+        Ok(product)
+    }
+    /// The number of ways to pick `r` of `self` items in any order (`nCr`)
+    fn combinations(&self, r: &Self) -> CResult<Self> {
+        let (n, k) = (whole_number(self, "nCr")?, whole_number(r, "nCr")?);
+        // nCr = nC(n-r), which needs fewer terms
+        let r = if k > n / 2 && k <= n { Self::from((n - k) as f64) } else { r.clone() };
+        self.permutations(&r)? / r.factorial()?
+    }
+    fn min(&self, other: &Self) -> CResult<Self> {
+        match self.compare(other)? {
+            Some(std::cmp::Ordering::Greater) => Ok(other.clone()),
+            _ => Ok(self.clone()),
+        }
+    }
+    fn max(&self, other: &Self) -> CResult<Self> {
+        match self.compare(other)? {
+            Some(std::cmp::Ordering::Less) => Ok(other.clone()),
+            _ => Ok(self.clone()),
+        }
+    }
+    /// The derivative of `f` at this value, e.g. for `\frac{d}{dx}`
+    fn derivative(&self, f: impl Fn(Self) -> CResult<Self>) -> CResult<Self> {
+        calculus::derivative(self, f)
+    }
+    /// The definite integral of `f`, e.g. for `\int_0^1 x^2 dx`. Fields that can hold the estimated error include it,
+    /// and the others are rounded to the digits it leaves
+    fn integral(lower: &Limit<Self>, upper: &Limit<Self>, f: impl Fn(Self) -> CResult<Self>) -> CResult<Self> {
+        let (value, error) = calculus::integral(lower, upper, f)?;
+        value.plus_minus(error.clone()).or_else(|_| calculus::round_to_error(&value, &error))
+    }
+    /// One SI unit of the same quantity, e.g. 1 m for 5 km
+    fn unit(&self) -> CResult<Self> {
+        Ok(Self::from(1.0))
+    }
+    /// The size of the value in SI units, which numerical methods use to control their errors
+    fn magnitude(&self) -> CResult<f64> {
+        self.abs()?.as_scalar()
+    }
+    /// Whether the value became too large for the field to hold, e.g. `171!` with `f64`
+    fn overflowed(&self) -> bool {
+        self.magnitude().is_ok_and(f64::is_infinite)
+    }
+    /// Orders two values for conditions such as `x < 0`. Values with units can only be compared to the same quantity
+    fn compare(&self, other: &Self) -> CResult<Option<std::cmp::Ordering>> {
+        Ok(self.as_scalar()?.partial_cmp(&other.as_scalar()?))
+    }
+    fn convert(&self, unit: &str, units: &UnitRegistry) -> CResult<Self>;
+    /// Creates a unit named `name` that is the size of this value (e.g. `unit furlong = 201.168 m`)
+    fn define_unit(&self, name: &str) -> CResult<Unit>;
+    /// A value of one `unit`, which can be a user defined unit
+    fn unit_identity(unit: &str, units: &UnitRegistry) -> CResult<Self>;
+    /// Adds a standard uncertainty to the value, e.g. `9.81 ± 0.02`
+    fn plus_minus(&self, uncertainty: Self) -> CResult<Self>;
+    /// Builds a matrix from its rows, for fields that can hold matrices
+    fn matrix(_rows: Vec<Vec<Self>>) -> CResult<Self> {
+        Err(Error::EvalError("Matrices can't be used in this mode".to_string()))
+    }
+    /// Builds a list from its elements, for fields that can hold lists
+    fn list(_elements: Vec<Self>) -> CResult<Self> {
+        Err(Error::EvalError("Lists can't be used in this mode".to_string()))
+    }
+    /// The elements of a list, or `None` if this isn't a list
+    fn as_list(&self) -> Option<Vec<Self>> {
+        None
+    }
+    /// Element-wise (Hadamard) product, which is ordinary multiplication for numbers
+    fn hadamard(self, rhs: Self) -> CResult<Self> {
+        self * rhs
+    }
+    fn transpose(&self) -> CResult<Self> {
+        Ok(self.clone())
+    }
+    /// The determinant of a square matrix. Numbers are their own determinant
+    fn det(&self) -> CResult<Self> {
+        Ok(self.clone())
+    }
+    /// The inverse of a square matrix, or the reciprocal of a number
+    fn inv(&self) -> CResult<Self> {
+        Self::from(1.0) / self.clone()
+    }
+    fn dot(&self, rhs: &Self) -> CResult<Self> {
+        self.clone() * rhs.clone()
+    }
+    fn cross(&self, _rhs: &Self) -> CResult<Self> {
+        Err(Error::EvalError("The cross product is only defined for 3D vectors".to_string()))
+    }
+    /// Parses a number literal such as "0.1", for fields that can represent it more precisely than an f64
+    fn from_literal(literal: &str) -> Self {
+        Self::from(literal.parse::<f64>().unwrap_or(f64::NAN))
+    }
+}
+
+/// The value as an integer, for functions that are only defined for whole numbers
+fn whole_number<'a, T: BaseField<'a>>(value: &T, operation: &str) -> CResult<i64> {
+    if value.fract()? != 0.0 {
+        return Err(Error::EvalError(format!("{operation} is only defined for whole numbers, but got {value}")));
+    }
+    Ok(value.as_exact_scalar()? as i64)
+}
+
+/// The logarithm of a real number. Common bases have their own functions, so e.g. log(1000) is exactly 3
+pub fn log(x: f64, base: f64) -> f64 {
+    match base {
+        10.0 => x.log10(),
+        2.0 => x.log2(),
+        base => x.log(base),
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 { a.abs() } else { gcd(b, a % b) }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -35,12 +272,223 @@ pub enum Expr<T> where for<'a> T: BaseField<'a> {
     EFunc(String, Vec<Expr<T>>),
     EAdd(Box<Expr<T>>, Box<Expr<T>>),
     ESub(Box<Expr<T>>, Box<Expr<T>>),
+    EPlusMinus(Box<Expr<T>>, Box<Expr<T>>),
     EMul(Box<Expr<T>>, Box<Expr<T>>),
     EDiv(Box<Expr<T>>, Box<Expr<T>>),
     EExp(Box<Expr<T>>, Box<Expr<T>>),
+    /// Element-wise product, e.g. `A \odot B`
+    EHadamard(Box<Expr<T>>, Box<Expr<T>>),
+    /// Rows of a matrix or vector, e.g. `[1, 2; 3, 4]`
+    EMatrix(Vec<Vec<Expr<T>>>),
+    /// A list, e.g. `[1, 2, 3]`
+    EList(Vec<Expr<T>>),
+    /// Evenly spaced list from the first to the last value, e.g. `[1...10]` or `[1, 3, ..., 11]`
+    ERange(Box<Expr<T>>, Option<Box<Expr<T>>>, Box<Expr<T>>),
+    /// Element of a list, counting from 1, e.g. `L[2]`
+    EIndex(Box<Expr<T>>, Box<Expr<T>>),
+    /// A list built by evaluating an expression for each element of another, e.g. `[x^2 for x=L]`
+    EListComprehension(Box<Expr<T>>, String, Box<Expr<T>>),
+    /// A comparison, which can only be used as a condition, e.g. `x \le 1`
+    ECompare(Box<Expr<T>>, Comparison, Box<Expr<T>>),
+    EAnd(Box<Expr<T>>, Box<Expr<T>>),
+    EOr(Box<Expr<T>>, Box<Expr<T>>),
+    /// Conditions and their values, e.g. `\{x < 0: -x, x\}`, and the value to use if none of the conditions hold
+    EPiecewise(Vec<(Expr<T>, Expr<T>)>, Option<Box<Expr<T>>>),
+    /// The derivative of an expression with respect to a variable, at the given value of the variable,
+    /// e.g. `\frac{d}{dx} x^2` or `f'(2)`
+    EDerivative(Box<Expr<T>>, String, Box<Expr<T>>),
+    /// The integral of an expression over a variable between two limits, e.g. `\int_0^1 x^2 dx`
+    EIntegral(Box<Expr<T>>, String, Limit<Box<Expr<T>>>, Limit<Box<Expr<T>>>),
+    /// The values of a variable that make both sides of an equation equal, searching near a guess or between two
+    /// limits if given, e.g. `solve(x^2 = 2, x)` or `solve(\sin(x) = 0.5, x, 0, 10)`
+    ESolve(Box<Expr<T>>, Box<Expr<T>>, String, Vec<Expr<T>>),
     ETex(LatexExpr<T>),
+    EConvert(Box<Expr<T>>, String),
     EDefVar(String, Box<Expr<T>>),
     EDefFunc(String, Vec<String>, Box<Expr<T>>),
+    EDefUnit(String, Box<Expr<T>>),
+    /// A differential equation for the highest derivative of a function, e.g. `y'' = -k y`, which can name the
+    /// variable the function is of, e.g. `y'(x) = x y`. The function and its lower derivatives are written `y`, `y'`
+    EDefODE(String, usize, Option<String>, Box<Expr<T>>),
+    /// The value of a function or one of its derivatives at a point, which starts a differential equation,
+    /// e.g. `y'(0) = 0`
+    EDefInitialValue(String, usize, Box<Expr<T>>, Box<Expr<T>>),
+}
+
+impl<T> Expr<T> where for<'a> T: BaseField<'a> {
+    /// The variable, function or unit that this line defines, if any
+    pub fn defined_name(&self) -> Option<&str> {
+        use Expr::*;
+        match self {
+            EDefVar(name, _) | EDefFunc(name, _, _) | EDefUnit(name, _) | EDefODE(name, ..) | EDefInitialValue(name, ..) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Initial values are defined alongside the differential equation of the same name, rather than replacing it
+    pub fn is_initial_value(&self) -> bool {
+        matches!(self, Expr::EDefInitialValue(..))
+    }
+
+    /// Names of the variables, functions and user defined units that the expression refers to.
+    /// Function parameters, summation indices and the like aren't included
+    pub fn dependencies(&self) -> HashSet<String> {
+        let mut names = HashSet::new();
+        self.collect_dependencies(&mut vec![], &mut names);
+        names
+    }
+
+    fn collect_dependencies(&self, bound: &mut Vec<String>, names: &mut HashSet<String>) {
+        use Expr::*;
+        match self {
+            ENum(_) => (),
+            EVar(name) => {
+                if !bound.contains(name) {
+                    names.insert(name.clone());
+                }
+            },
+            EFunc(name, inputs) => {
+                names.insert(name.clone());
+                inputs.iter().for_each(|input| input.collect_dependencies(bound, names));
+            },
+            EAdd(lhs, rhs) | ESub(lhs, rhs) | EPlusMinus(lhs, rhs) | EMul(lhs, rhs) | EDiv(lhs, rhs) | EExp(lhs, rhs)
+            | EHadamard(lhs, rhs) | EIndex(lhs, rhs) | ECompare(lhs, _, rhs) | EAnd(lhs, rhs) | EOr(lhs, rhs) => {
+                lhs.collect_dependencies(bound, names);
+                rhs.collect_dependencies(bound, names);
+            },
+            EMatrix(rows) => rows.iter().flatten().for_each(|expr| expr.collect_dependencies(bound, names)),
+            EList(elements) => elements.iter().for_each(|expr| expr.collect_dependencies(bound, names)),
+            ERange(first, second, last) => {
+                first.collect_dependencies(bound, names);
+                if let Some(second) = second {
+                    second.collect_dependencies(bound, names);
+                }
+                last.collect_dependencies(bound, names);
+            },
+            EListComprehension(expr, var, list) => {
+                list.collect_dependencies(bound, names);
+                bound.push(var.clone());
+                expr.collect_dependencies(bound, names);
+                bound.pop();
+            },
+            EDerivative(expr, var, point) => {
+                point.collect_dependencies(bound, names);
+                bound.push(var.clone());
+                expr.collect_dependencies(bound, names);
+                bound.pop();
+            },
+            EIntegral(integrand, var, lower, upper) => {
+                for limit in [lower, upper] {
+                    if let Limit::Finite(limit) = limit {
+                        limit.collect_dependencies(bound, names);
+                    }
+                }
+                bound.push(var.clone());
+                integrand.collect_dependencies(bound, names);
+                bound.pop();
+            },
+            ESolve(lhs, rhs, var, limits) => {
+                limits.iter().for_each(|limit| limit.collect_dependencies(bound, names));
+                bound.push(var.clone());
+                lhs.collect_dependencies(bound, names);
+                rhs.collect_dependencies(bound, names);
+                bound.pop();
+            },
+            EPiecewise(conditions, otherwise) => {
+                for (condition, value) in conditions {
+                    condition.collect_dependencies(bound, names);
+                    value.collect_dependencies(bound, names);
+                }
+                if let Some(otherwise) = otherwise {
+                    otherwise.collect_dependencies(bound, names);
+                }
+            },
+            ETex(latex) => {
+                // The index of a sum, e.g. the i in "\sum_{i=1}^{3} i^2", is only defined within it
+                let index = match latex.subscript.as_deref() {
+                    Some(EDefVar(index, start)) => {
+                        start.collect_dependencies(bound, names);
+                        Some(index.clone())
+                    },
+                    Some(subscript) => {
+                        subscript.collect_dependencies(bound, names);
+                        None
+                    },
+                    None => None,
+                };
+                let indexed = index.is_some();
+                bound.extend(index);
+                latex.superscript.iter().for_each(|superscript| superscript.collect_dependencies(bound, names));
+                latex.params.iter().for_each(|param| param.collect_dependencies(bound, names));
+                if indexed {
+                    bound.pop();
+                }
+            },
+            EConvert(expr, unit) => {
+                expr.collect_dependencies(bound, names);
+                // User defined units can appear in the target unit, e.g. "to furlong/s"
+                names.extend(unit.split(|c: char| !c.is_alphanumeric()).filter(|name| !name.is_empty()).map(str::to_string));
+            },
+            EDefVar(_, expr) | EDefUnit(_, expr) => expr.collect_dependencies(bound, names),
+            EDefFunc(_, params, expr) => {
+                bound.extend(params.iter().cloned());
+                expr.collect_dependencies(bound, names);
+                bound.truncate(bound.len() - params.len());
+            },
+            EDefODE(name, order, var, expr) => {
+                let state = (0..*order).map(|order| primed_name(name, order)).chain(var.clone()).collect::<Vec<_>>();
+                bound.extend(state.iter().cloned());
+                expr.collect_dependencies(bound, names);
+                bound.truncate(bound.len() - state.len());
+            },
+            EDefInitialValue(_, _, point, value) => {
+                point.collect_dependencies(bound, names);
+                value.collect_dependencies(bound, names);
+            },
+        }
+    }
+}
+
+/// A limit of an integral, which can be infinite
+#[derive(Debug, PartialEq, Clone)]
+pub enum Limit<V> {
+    Finite(V),
+    Infinity,
+    NegativeInfinity,
+}
+
+impl<V> Limit<V> {
+    pub fn try_map<W>(&self, f: impl FnOnce(&V) -> CResult<W>) -> CResult<Limit<W>> {
+        Ok(match self {
+            Limit::Finite(value) => Limit::Finite(f(value)?),
+            Limit::Infinity => Limit::Infinity,
+            Limit::NegativeInfinity => Limit::NegativeInfinity,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    /// Whether the comparison holds for values with the given ordering
+    pub fn holds(self, ordering: std::cmp::Ordering) -> bool {
+        match self {
+            Comparison::Less => ordering.is_lt(),
+            Comparison::LessEqual => ordering.is_le(),
+            Comparison::Greater => ordering.is_gt(),
+            Comparison::GreaterEqual => ordering.is_ge(),
+            Comparison::Equal => ordering.is_eq(),
+            Comparison::NotEqual => ordering.is_ne(),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -72,10 +520,34 @@ impl<T> LatexExpr<T> where for<'a> T: BaseField<'a> {
     }
 }
 
+/// e.g. "y''" for the second derivative of "y"
+pub fn primed_name(name: &str, order: usize) -> String {
+    format!("{name}{}", "'".repeat(order))
+}
+
+/// A differential equation `y^(n) = f(x, y, y', ..., y^(n-1))` of order n, as defined by `Expr::EDefODE`
+#[derive(Debug, Clone)]
+pub struct DifferentialEquation<T> where for<'a> T: BaseField<'a> {
+    pub order: usize,
+    pub var: Option<String>,
+    pub expr: Expr<T>,
+}
+
+/// The value of the derivative of the given order (zero for the function itself) at a point
+#[derive(Debug, Clone)]
+pub struct InitialValue<T> {
+    pub order: usize,
+    pub point: T,
+    pub value: T,
+}
+
 #[derive(Debug, Clone)]
 pub struct Context<T> where for<'a> T: BaseField<'a> {
     pub vars: HashMap<String, T>,
     pub funcs: HashMap<String, (Vec<String>, Expr<T>)>,
+    pub units: UnitRegistry,
+    pub equations: HashMap<String, DifferentialEquation<T>>,
+    pub initial_values: HashMap<String, Vec<InitialValue<T>>>,
 }
 
 impl<T> Context<T> where for<'a> T: BaseField<'a> {
@@ -83,6 +555,9 @@ impl<T> Context<T> where for<'a> T: BaseField<'a> {
         Context {
             vars: HashMap::new(),
             funcs: HashMap::new(),
+            units: UnitRegistry::new(),
+            equations: HashMap::new(),
+            initial_values: HashMap::new(),
         }
     }
 }